@@ -0,0 +1,361 @@
+// generates a synthetic library of tagged audio files, for benchmarking reloads and checking the tag parsers
+// cargo run --release --example gen_fixture -- <directory> [track count] [mp3|ogg|opus|wav|aiff]
+// tracks are spread over albums of 10 tracks and artists of 5 albums, and every album has its own embedded cover
+// every 4th track also features the next artist, as a second artist value
+// albums cycle through a few genres, written as id3v1 codes in id3 tags and as names elsewhere
+// and come out a year apart from 1970 on
+// every artist's tracks are credited to a composer of their own
+// ogg and opus tracks don't hold real audio, only pages with the right granule positions, and last 60 to 69 seconds
+// wav and aiff tracks are that long too, in 8kHz 8 bit mono, and tagged with an id3 chunk
+use std::{
+    fs::{create_dir_all, write},
+    path::{Path, PathBuf},
+};
+use id3::{
+    Tag, TagLike, Version,
+    frame::{Picture, PictureType},
+};
+
+const TRACKS_PER_ALBUM: usize = 10;
+const ALBUMS_PER_ARTIST: usize = 5;
+const FEATURE_EVERY: usize = 4;
+// id3v1 codes and names
+const GENRES: &[(u32, &str)] = &[(17, "Rock"), (13, "Pop"), (8, "Jazz"), (52, "Electronic")];
+const FIRST_YEAR: usize = 1970;
+const YEARS: usize = 50;
+// a few silent mpeg1 layer 3 frames, enough for a duration to be read
+const FRAME_COUNT: usize = 4;
+const FRAME_SIZE: usize = 417;
+// ogg streams
+const SAMPLE_RATE: u32 = 44100;
+const OPUS_PRE_SKIP: u16 = 312;
+const OGG_AUDIO_PAGES: u64 = 8;
+// pcm streams
+const PCM_SAMPLE_RATE: u32 = 8000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Mp3,
+    Vorbis,
+    Opus,
+    Wav,
+    Aiff,
+}
+
+// tags shared by every format
+struct Track {
+    index: usize,
+    title: String,
+    // the first one is also the album artist
+    artists: Vec<String>,
+    composer: String,
+    album: String,
+    genre: (u32, &'static str),
+    year: usize,
+    track_number: u32,
+    cover: Vec<u8>,
+    length_seconds: u64,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let root = PathBuf::from(args.next().ok_or("usage: gen_fixture <directory> [track count] [mp3|ogg|opus|wav|aiff]")?);
+    let track_count: usize = match args.next() {
+        Some(count) => count.parse()?,
+        None => 50_000,
+    };
+    let format = match args.next().as_deref() {
+        None | Some("mp3") => Format::Mp3,
+        Some("ogg") => Format::Vorbis,
+        Some("opus") => Format::Opus,
+        Some("wav") => Format::Wav,
+        Some("aiff") => Format::Aiff,
+        Some(other) => Err(format!("Unknown format {}", other))?,
+    };
+
+    for index in 0..track_count {
+        let album = index / TRACKS_PER_ALBUM;
+        let artist = album / ALBUMS_PER_ARTIST;
+        let dir = root.join(format!("Artist{:05}", artist)).join(format!("Album{:06}", album));
+        if index % TRACKS_PER_ALBUM == 0 {
+            create_dir_all(&dir)?;
+        }
+
+        let track = Track {
+            index,
+            title: format!("Track {}", index),
+            artists: match index % FEATURE_EVERY {
+                0 => vec![format!("Artist {}", artist), format!("Artist {}", artist + 1)],
+                _ => vec![format!("Artist {}", artist)],
+            },
+            composer: format!("Composer {}", artist),
+            album: format!("Album {}", album),
+            genre: GENRES[album % GENRES.len()],
+            year: FIRST_YEAR + album % YEARS,
+            track_number: (index % TRACKS_PER_ALBUM + 1) as u32,
+            cover: format!("cover of album {}", album).into_bytes(),
+            length_seconds: 60 + (index % 10) as u64,
+        };
+        let name = format!("{:02}", track.track_number);
+        match format {
+            Format::Mp3 => write_mp3(&dir.join(name + ".mp3"), &track)?,
+            Format::Vorbis => write_ogg(&dir.join(name + ".ogg"), &track, format)?,
+            Format::Opus => write_ogg(&dir.join(name + ".opus"), &track, format)?,
+            Format::Wav => write_pcm(&dir.join(name + ".wav"), &track, format)?,
+            Format::Aiff => write_pcm(&dir.join(name + ".aiff"), &track, format)?,
+        }
+    }
+
+    println!("Generated {} tracks in {}", track_count, root.to_string_lossy());
+    Ok(())
+}
+
+fn write_mp3(path: &Path, track: &Track) -> Result<(), Box<dyn std::error::Error>> {
+    // audio differs between tracks so that they don't all share a fingerprint
+    let mut audio = Vec::with_capacity(FRAME_COUNT * FRAME_SIZE);
+    for _ in 0..FRAME_COUNT {
+        let mut frame = vec![0; FRAME_SIZE];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        frame[4..12].copy_from_slice(&(track.index as u64).to_be_bytes());
+        audio.extend(frame);
+    }
+    write(path, audio)?;
+
+    id3_tag(track).write_to_path(path, Version::Id3v24)?;
+    Ok(())
+}
+
+fn write_pcm(path: &Path, track: &Track, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    // audio differs between tracks so that they don't all share a fingerprint
+    let mut samples = vec![0x80u8; (PCM_SAMPLE_RATE as u64 * track.length_seconds) as usize];
+    samples[..8].copy_from_slice(&(track.index as u64).to_be_bytes());
+
+    let mut data = Vec::new();
+    if format == Format::Wav {
+        let mut fmt = Vec::new();
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(PCM_SAMPLE_RATE.to_le_bytes());
+        fmt.extend(PCM_SAMPLE_RATE.to_le_bytes());
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(8u16.to_le_bytes());
+        let chunks = [(b"fmt ", fmt), (b"data", samples)];
+        let body_len: usize = chunks.iter().map(|(_, chunk)| 8 + chunk.len() + chunk.len() % 2).sum();
+        data.extend(b"RIFF");
+        data.extend((4 + body_len as u32).to_le_bytes());
+        data.extend(b"WAVE");
+        for (id, chunk) in chunks.iter() {
+            data.extend(*id);
+            data.extend((chunk.len() as u32).to_le_bytes());
+            data.extend(chunk);
+            if chunk.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+    } else {
+        // aiff samples are signed
+        let samples: Vec<u8> = samples.iter().map(|s| s.wrapping_sub(0x80)).collect();
+        let mut comm = Vec::new();
+        comm.extend(1u16.to_be_bytes());
+        comm.extend((samples.len() as u32).to_be_bytes());
+        comm.extend(8u16.to_be_bytes());
+        comm.extend(extended(PCM_SAMPLE_RATE));
+        let mut ssnd = vec![0u8; 8];
+        ssnd.extend(samples);
+        let chunks = [(b"COMM", comm), (b"SSND", ssnd)];
+        let body_len: usize = chunks.iter().map(|(_, chunk)| 8 + chunk.len() + chunk.len() % 2).sum();
+        data.extend(b"FORM");
+        data.extend((4 + body_len as u32).to_be_bytes());
+        data.extend(b"AIFF");
+        for (id, chunk) in chunks.iter() {
+            data.extend(*id);
+            data.extend((chunk.len() as u32).to_be_bytes());
+            data.extend(chunk);
+            if chunk.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+    }
+    write(path, data)?;
+
+    let tag = id3_tag(track);
+    match format {
+        Format::Wav => tag.write_to_wav_path(path, Version::Id3v24)?,
+        _ => tag.write_to_aiff_path(path, Version::Id3v24)?,
+    }
+    Ok(())
+}
+
+// a positive integer as an 80 bit extended float, as aiff stores its sample rate
+fn extended(value: u32) -> [u8; 10] {
+    let shift = value.leading_zeros();
+    let mut bytes = [0u8; 10];
+    bytes[0..2].copy_from_slice(&(16383 + 31 - shift as u16).to_be_bytes());
+    bytes[2..10].copy_from_slice(&(((value as u64) << shift) << 32).to_be_bytes());
+    bytes
+}
+
+fn id3_tag(track: &Track) -> Tag {
+    let mut tag = Tag::new();
+    tag.set_title(&track.title);
+    // id3v2.4 separates multiple values with nulls
+    tag.set_artist(track.artists.join("\0"));
+    tag.set_album_artist(&track.artists[0]);
+    tag.set_album(&track.album);
+    tag.set_genre(format!("({})", track.genre.0));
+    tag.set_text("TDRC", track.year.to_string());
+    tag.set_text("TCOM", &track.composer);
+    tag.set_track(track.track_number);
+    tag.set_disc(1);
+    tag.add_frame(Picture {
+        mime_type: "image/png".to_string(),
+        picture_type: PictureType::CoverFront,
+        description: String::new(),
+        data: track.cover.clone(),
+    });
+    tag
+}
+
+fn write_ogg(path: &Path, track: &Track, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    // the cover goes in as a base64 flac picture block
+    let mut picture = Vec::new();
+    picture.extend(3u32.to_be_bytes());
+    picture.extend((b"image/png".len() as u32).to_be_bytes());
+    picture.extend(b"image/png");
+    picture.extend(0u32.to_be_bytes());
+    picture.extend([0u8; 16]);
+    picture.extend((track.cover.len() as u32).to_be_bytes());
+    picture.extend(&track.cover);
+
+    // vorbis comments repeat a field for each of its values
+    let mut comments = vec![format!("TITLE={}", track.title)];
+    comments.extend(track.artists.iter().map(|artist| format!("ARTIST={}", artist)));
+    comments.extend([
+        format!("ALBUMARTIST={}", track.artists[0]),
+        format!("ALBUM={}", track.album),
+        format!("GENRE={}", track.genre.1),
+        format!("DATE={}", track.year),
+        format!("COMPOSER={}", track.composer),
+        format!("TRACKNUMBER={}/{}", track.track_number, TRACKS_PER_ALBUM),
+        "DISCNUMBER=1".to_string(),
+        format!("METADATA_BLOCK_PICTURE={}", base64::encode(&picture)),
+    ]);
+    let mut comment_block = Vec::new();
+    let vendor = b"gen_fixture";
+    comment_block.extend((vendor.len() as u32).to_le_bytes());
+    comment_block.extend(vendor);
+    comment_block.extend((comments.len() as u32).to_le_bytes());
+    for comment in comments.iter() {
+        comment_block.extend((comment.len() as u32).to_le_bytes());
+        comment_block.extend(comment.as_bytes());
+    }
+
+    // header packets, then the rate granule positions count samples at
+    let (headers, rate, pre_skip) = match format {
+        Format::Opus => {
+            let mut head = b"OpusHead".to_vec();
+            head.extend([1, 2]);
+            head.extend(OPUS_PRE_SKIP.to_le_bytes());
+            head.extend(SAMPLE_RATE.to_le_bytes());
+            head.extend([0, 0, 0]);
+            let mut tags = b"OpusTags".to_vec();
+            tags.extend(comment_block);
+            (vec![head, tags], 48000, OPUS_PRE_SKIP as u64)
+        },
+        _ => {
+            let mut id = b"\x01vorbis".to_vec();
+            id.extend(0u32.to_le_bytes());
+            id.push(2);
+            id.extend(SAMPLE_RATE.to_le_bytes());
+            id.extend([0u8; 12]);
+            id.extend([0xb8, 1]);
+            let mut comment = b"\x03vorbis".to_vec();
+            comment.extend(comment_block);
+            comment.push(1);
+            let setup = b"\x05vorbis".to_vec();
+            (vec![id, comment, setup], SAMPLE_RATE as u64, 0)
+        },
+    };
+
+    let mut writer = OggWriter { data: Vec::new(), serial: track.index as u32, sequence: 0 };
+    for (i, header) in headers.iter().enumerate() {
+        writer.write_packet(header, 0, i == 0, false);
+    }
+    // audio differs between tracks so that they don't all share a fingerprint
+    let total = pre_skip + track.length_seconds * rate;
+    for page in 1..=OGG_AUDIO_PAGES {
+        let mut audio = vec![0u8; 200];
+        audio[..8].copy_from_slice(&(track.index as u64).to_be_bytes());
+        audio[8] = format as u8;
+        writer.write_packet(&audio, total * page / OGG_AUDIO_PAGES, false, page == OGG_AUDIO_PAGES);
+    }
+
+    write(path, writer.data)?;
+    Ok(())
+}
+
+// writes every packet on pages of its own
+struct OggWriter {
+    data: Vec<u8>,
+    serial: u32,
+    sequence: u32,
+}
+
+impl OggWriter {
+    fn write_packet(&mut self, packet: &[u8], granule: u64, first: bool, last: bool) {
+        // a page holds at most 255 segments of 255 bytes, longer packets continue on the next page
+        let mut chunks: Vec<&[u8]> = packet.chunks(255 * 255).collect();
+        if packet.len() % (255 * 255) == 0 {
+            chunks.push(&[]);
+        }
+        let chunk_count = chunks.len();
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let last_chunk = i + 1 == chunk_count;
+            let mut lacing = vec![255u8; chunk.len() / 255];
+            if last_chunk {
+                lacing.push((chunk.len() % 255) as u8);
+            }
+
+            let mut flags = 0;
+            if i > 0 {
+                flags |= 0x01;
+            }
+            if first && i == 0 {
+                flags |= 0x02;
+            }
+            if last && last_chunk {
+                flags |= 0x04;
+            }
+            // pages where the packet doesn't end have no granule position
+            let page_granule = if last_chunk { granule } else { u64::MAX };
+
+            let start = self.data.len();
+            self.data.extend(b"OggS");
+            self.data.extend([0, flags]);
+            self.data.extend(page_granule.to_le_bytes());
+            self.data.extend(self.serial.to_le_bytes());
+            self.data.extend(self.sequence.to_le_bytes());
+            self.data.extend([0u8; 4]);
+            self.data.push(lacing.len() as u8);
+            self.data.extend(lacing);
+            self.data.extend(chunk);
+
+            let crc = ogg_crc(&self.data[start..]);
+            self.data[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+            self.sequence += 1;
+        }
+    }
+}
+
+// crc32 with polynomial 0x04c11db7, no reflection and no final xor, computed with the crc field zeroed
+fn ogg_crc(page: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in page {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+        }
+    }
+    crc
+}
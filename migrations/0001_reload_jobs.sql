@@ -0,0 +1,23 @@
+--
+-- Reload job ids and errors collected during reloads
+-- Databases created before reloads collected their errors need it before any of the migrations after it
+--
+
+CREATE SEQUENCE IF NOT EXISTS public.reload_job_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+CREATE TABLE IF NOT EXISTS public.scan_error (
+    scan_error_id serial PRIMARY KEY,
+    job_id integer NOT NULL,
+    path text,
+    phase text NOT NULL,
+    error text NOT NULL,
+    occurred_at timestamp without time zone DEFAULT now() NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS scan_error_job_id_index ON public.scan_error USING btree (job_id);
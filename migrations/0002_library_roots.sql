@@ -0,0 +1,15 @@
+--
+-- Library each track belongs to, now that paths are relative to one of several roots
+-- Existing tracks go to the library named default, which is the name music_directory gets in config
+--
+
+BEGIN;
+
+ALTER TABLE public.track ADD COLUMN library text;
+UPDATE public.track SET library = 'default';
+ALTER TABLE public.track ALTER COLUMN library SET NOT NULL;
+
+ALTER TABLE public.track DROP CONSTRAINT track_path_key;
+ALTER TABLE public.track ADD CONSTRAINT track_library_path_key UNIQUE (library, path);
+
+COMMIT;
@@ -0,0 +1,21 @@
+--
+-- History of reloads and watcher updates, kept after they end
+--
+
+CREATE TABLE IF NOT EXISTS public.scan_run (
+    scan_run_id serial PRIMARY KEY,
+    job_id integer,
+    trigger text NOT NULL,
+    status text NOT NULL,
+    library text,
+    scope text,
+    started_at timestamp without time zone DEFAULT now() NOT NULL,
+    finished_at timestamp without time zone,
+    files_added integer DEFAULT 0 NOT NULL,
+    files_updated integer DEFAULT 0 NOT NULL,
+    files_moved integer DEFAULT 0 NOT NULL,
+    files_deleted integer DEFAULT 0 NOT NULL,
+    errors integer DEFAULT 0 NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS scan_run_job_id_index ON public.scan_run USING btree (job_id);
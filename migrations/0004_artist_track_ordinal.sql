@@ -0,0 +1,15 @@
+--
+-- Several artists per track, in the order their tags list them
+-- role tells a track's performing artists apart from other credits
+--
+
+BEGIN;
+
+ALTER TABLE public.artist_track ADD COLUMN ordinal integer DEFAULT 0 NOT NULL;
+ALTER TABLE public.artist_track ADD COLUMN role text DEFAULT 'artist' NOT NULL;
+
+ALTER TABLE public.artist_track DROP CONSTRAINT unique_track_id;
+ALTER TABLE public.artist_track ADD CONSTRAINT artist_track_pkey PRIMARY KEY (track_id, role, ordinal);
+CREATE INDEX artist_track_artist_id_index ON public.artist_track USING btree (artist_id);
+
+COMMIT;
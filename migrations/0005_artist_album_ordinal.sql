@@ -0,0 +1,15 @@
+--
+-- Several album artists per album, in the order their tags list them
+-- an album is still found by its name along with all of its album artists
+--
+
+BEGIN;
+
+ALTER TABLE public.artist_album ADD COLUMN ordinal integer DEFAULT 0 NOT NULL;
+ALTER TABLE public.artist_album ALTER COLUMN artist_id SET NOT NULL;
+
+ALTER TABLE public.artist_album DROP CONSTRAINT unique_album_id_artist;
+ALTER TABLE public.artist_album ADD CONSTRAINT artist_album_pkey PRIMARY KEY (album_id, ordinal);
+DROP INDEX public.artist_album_album_id_index;
+
+COMMIT;
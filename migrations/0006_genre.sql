@@ -0,0 +1,21 @@
+--
+-- Genres of each track, in the order their tags list them
+--
+
+BEGIN;
+
+CREATE TABLE public.genre (
+    genre_id serial PRIMARY KEY,
+    genre_name text NOT NULL CONSTRAINT unique_genre_name UNIQUE
+);
+
+CREATE TABLE public.track_genre (
+    genre_id integer NOT NULL REFERENCES public.genre(genre_id) ON DELETE CASCADE,
+    track_id integer NOT NULL REFERENCES public.track(track_id) ON DELETE CASCADE,
+    ordinal integer DEFAULT 0 NOT NULL,
+    CONSTRAINT track_genre_pkey PRIMARY KEY (track_id, ordinal)
+);
+
+CREATE INDEX track_genre_genre_id_index ON public.track_genre USING btree (genre_id);
+
+COMMIT;
@@ -0,0 +1,14 @@
+--
+-- Recording and release dates of tracks and albums, as partial dates like 2001, 2001-05 or 2001-05-17
+-- An album's dates are the earliest of its tracks'
+--
+
+BEGIN;
+
+ALTER TABLE public.track ADD COLUMN recording_date text;
+ALTER TABLE public.track ADD COLUMN release_date text;
+
+ALTER TABLE public.album ADD COLUMN recording_date text;
+ALTER TABLE public.album ADD COLUMN release_date text;
+
+COMMIT;
@@ -0,0 +1,16 @@
+--
+-- An album's dates are worked out from its tracks' whenever it's listed, rather than stored with it
+-- so that they follow its tracks as they are reparsed, moved or deleted
+--
+
+BEGIN;
+
+ALTER TABLE public.album DROP COLUMN recording_date;
+ALTER TABLE public.album DROP COLUMN release_date;
+
+CREATE VIEW public.album_date AS
+    SELECT album_track.album_id, MIN(track.recording_date) AS recording_date, MIN(track.release_date) AS release_date
+    FROM public.album_track JOIN public.track ON (track.track_id = album_track.track_id)
+    GROUP BY album_track.album_id;
+
+COMMIT;
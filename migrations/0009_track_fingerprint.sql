@@ -0,0 +1,6 @@
+--
+-- Hash of each track's audio, by which a moved or renamed file keeps its track id
+-- Existing tracks get theirs on the next reload
+--
+
+ALTER TABLE public.track ADD COLUMN IF NOT EXISTS fingerprint bytea;
@@ -72,6 +72,36 @@ CREATE TABLE public.album_track (
 );
 
 
+--
+-- Name: track; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.track (
+    track_id integer NOT NULL,
+    track_name text NOT NULL,
+    library text NOT NULL,
+    path text NOT NULL,
+    last_modified timestamp without time zone NOT NULL,
+    length_seconds integer NOT NULL,
+    fingerprint bytea,
+    recording_date text,
+    release_date text
+);
+
+
+--
+-- Name: album_date; Type: VIEW; Schema: public; Owner: -
+--
+
+CREATE VIEW public.album_date AS
+ SELECT album_track.album_id,
+    min(track.recording_date) AS recording_date,
+    min(track.release_date) AS release_date
+   FROM (public.album_track
+     JOIN public.track ON ((track.track_id = album_track.track_id)))
+  GROUP BY album_track.album_id;
+
+
 --
 -- Name: art; Type: TABLE; Schema: public; Owner: -
 --
@@ -118,8 +148,9 @@ CREATE TABLE public.artist (
 --
 
 CREATE TABLE public.artist_album (
-    artist_id integer,
-    album_id integer
+    artist_id integer NOT NULL,
+    album_id integer NOT NULL,
+    ordinal integer DEFAULT 0 NOT NULL
 );
 
 
@@ -159,23 +190,130 @@ ALTER SEQUENCE public.artist_artist_id_seq OWNED BY public.artist.artist_id;
 
 CREATE TABLE public.artist_track (
     artist_id integer NOT NULL,
-    track_id integer NOT NULL
+    track_id integer NOT NULL,
+    ordinal integer DEFAULT 0 NOT NULL,
+    role text DEFAULT 'artist' NOT NULL
 );
 
 
 --
--- Name: track; Type: TABLE; Schema: public; Owner: -
+-- Name: genre; Type: TABLE; Schema: public; Owner: -
 --
 
-CREATE TABLE public.track (
-    track_id integer NOT NULL,
-    track_name text NOT NULL,
-    path text NOT NULL,
-    last_modified timestamp without time zone NOT NULL,
-    length_seconds integer NOT NULL
+CREATE TABLE public.genre (
+    genre_id integer NOT NULL,
+    genre_name text NOT NULL
+);
+
+
+--
+-- Name: genre_genre_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.genre_genre_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: genre_genre_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.genre_genre_id_seq OWNED BY public.genre.genre_id;
+
+
+--
+-- Name: reload_job_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.reload_job_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: scan_error; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.scan_error (
+    scan_error_id integer NOT NULL,
+    job_id integer NOT NULL,
+    path text,
+    phase text NOT NULL,
+    error text NOT NULL,
+    occurred_at timestamp without time zone DEFAULT now() NOT NULL
 );
 
 
+--
+-- Name: scan_error_scan_error_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.scan_error_scan_error_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: scan_error_scan_error_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.scan_error_scan_error_id_seq OWNED BY public.scan_error.scan_error_id;
+
+
+--
+-- Name: scan_run; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.scan_run (
+    scan_run_id integer NOT NULL,
+    job_id integer,
+    trigger text NOT NULL,
+    status text NOT NULL,
+    library text,
+    scope text,
+    started_at timestamp without time zone DEFAULT now() NOT NULL,
+    finished_at timestamp without time zone,
+    files_added integer DEFAULT 0 NOT NULL,
+    files_updated integer DEFAULT 0 NOT NULL,
+    files_moved integer DEFAULT 0 NOT NULL,
+    files_deleted integer DEFAULT 0 NOT NULL,
+    errors integer DEFAULT 0 NOT NULL
+);
+
+
+--
+-- Name: scan_run_scan_run_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.scan_run_scan_run_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: scan_run_scan_run_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.scan_run_scan_run_id_seq OWNED BY public.scan_run.scan_run_id;
+
+
 --
 -- Name: track_art; Type: TABLE; Schema: public; Owner: -
 --
@@ -186,6 +324,17 @@ CREATE TABLE public.track_art (
 );
 
 
+--
+-- Name: track_genre; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.track_genre (
+    genre_id integer NOT NULL,
+    track_id integer NOT NULL,
+    ordinal integer DEFAULT 0 NOT NULL
+);
+
+
 --
 -- Name: track_track_id_seq; Type: SEQUENCE; Schema: public; Owner: -
 --
@@ -227,6 +376,27 @@ ALTER TABLE ONLY public.art ALTER COLUMN art_id SET DEFAULT nextval('public.art_
 ALTER TABLE ONLY public.artist ALTER COLUMN artist_id SET DEFAULT nextval('public.artist_artist_id_seq'::regclass);
 
 
+--
+-- Name: genre genre_id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.genre ALTER COLUMN genre_id SET DEFAULT nextval('public.genre_genre_id_seq'::regclass);
+
+
+--
+-- Name: scan_error scan_error_id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.scan_error ALTER COLUMN scan_error_id SET DEFAULT nextval('public.scan_error_scan_error_id_seq'::regclass);
+
+
+--
+-- Name: scan_run scan_run_id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.scan_run ALTER COLUMN scan_run_id SET DEFAULT nextval('public.scan_run_scan_run_id_seq'::regclass);
+
+
 --
 -- Name: track track_id; Type: DEFAULT; Schema: public; Owner: -
 --
@@ -275,11 +445,59 @@ ALTER TABLE ONLY public.artist
 
 
 --
--- Name: track track_path_key; Type: CONSTRAINT; Schema: public; Owner: -
+-- Name: artist_album artist_album_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.artist_album
+    ADD CONSTRAINT artist_album_pkey PRIMARY KEY (album_id, ordinal);
+
+
+--
+-- Name: artist_track artist_track_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.artist_track
+    ADD CONSTRAINT artist_track_pkey PRIMARY KEY (track_id, role, ordinal);
+
+
+--
+-- Name: genre genre_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.genre
+    ADD CONSTRAINT genre_pkey PRIMARY KEY (genre_id);
+
+
+--
+-- Name: scan_error scan_error_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.scan_error
+    ADD CONSTRAINT scan_error_pkey PRIMARY KEY (scan_error_id);
+
+
+--
+-- Name: scan_run scan_run_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.scan_run
+    ADD CONSTRAINT scan_run_pkey PRIMARY KEY (scan_run_id);
+
+
+--
+-- Name: track track_library_path_key; Type: CONSTRAINT; Schema: public; Owner: -
 --
 
 ALTER TABLE ONLY public.track
-    ADD CONSTRAINT track_path_key UNIQUE (path);
+    ADD CONSTRAINT track_library_path_key UNIQUE (library, path);
+
+
+--
+-- Name: track_genre track_genre_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.track_genre
+    ADD CONSTRAINT track_genre_pkey PRIMARY KEY (track_id, ordinal);
 
 
 --
@@ -298,14 +516,6 @@ ALTER TABLE ONLY public.album_art
     ADD CONSTRAINT unique_album_id_art UNIQUE (album_id);
 
 
---
--- Name: artist_album unique_album_id_artist; Type: CONSTRAINT; Schema: public; Owner: -
---
-
-ALTER TABLE ONLY public.artist_album
-    ADD CONSTRAINT unique_album_id_artist UNIQUE (album_id);
-
-
 --
 -- Name: artist_art unique_artist_id_art; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -323,11 +533,11 @@ ALTER TABLE ONLY public.artist
 
 
 --
--- Name: artist_track unique_track_id; Type: CONSTRAINT; Schema: public; Owner: -
+-- Name: genre unique_genre_name; Type: CONSTRAINT; Schema: public; Owner: -
 --
 
-ALTER TABLE ONLY public.artist_track
-    ADD CONSTRAINT unique_track_id UNIQUE (track_id);
+ALTER TABLE ONLY public.genre
+    ADD CONSTRAINT unique_genre_name UNIQUE (genre_name);
 
 
 --
@@ -354,17 +564,38 @@ CREATE INDEX album_album_name_index ON public.album USING btree (album_name);
 
 
 --
--- Name: artist_album_album_id_index; Type: INDEX; Schema: public; Owner: -
+-- Name: artist_album_artist_id_index; Type: INDEX; Schema: public; Owner: -
 --
 
-CREATE INDEX artist_album_album_id_index ON public.artist_album USING btree (album_id);
+CREATE INDEX artist_album_artist_id_index ON public.artist_album USING btree (artist_id);
 
 
 --
--- Name: artist_album_artist_id_index; Type: INDEX; Schema: public; Owner: -
+-- Name: artist_track_artist_id_index; Type: INDEX; Schema: public; Owner: -
 --
 
-CREATE INDEX artist_album_artist_id_index ON public.artist_album USING btree (artist_id);
+CREATE INDEX artist_track_artist_id_index ON public.artist_track USING btree (artist_id);
+
+
+--
+-- Name: scan_error_job_id_index; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX scan_error_job_id_index ON public.scan_error USING btree (job_id);
+
+
+--
+-- Name: scan_run_job_id_index; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX scan_run_job_id_index ON public.scan_run USING btree (job_id);
+
+
+--
+-- Name: track_genre_genre_id_index; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX track_genre_genre_id_index ON public.track_genre USING btree (genre_id);
 
 
 --
@@ -463,6 +694,22 @@ ALTER TABLE ONLY public.track_art
     ADD CONSTRAINT track_art_track_id_fkey FOREIGN KEY (track_id) REFERENCES public.track(track_id) ON DELETE CASCADE;
 
 
+--
+-- Name: track_genre track_genre_genre_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.track_genre
+    ADD CONSTRAINT track_genre_genre_id_fkey FOREIGN KEY (genre_id) REFERENCES public.genre(genre_id) ON DELETE CASCADE;
+
+
+--
+-- Name: track_genre track_genre_track_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.track_genre
+    ADD CONSTRAINT track_genre_track_id_fkey FOREIGN KEY (track_id) REFERENCES public.track(track_id) ON DELETE CASCADE;
+
+
 --
 -- PostgreSQL database dump complete
 --
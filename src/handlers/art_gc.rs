@@ -0,0 +1,84 @@
+use std::{
+    fs::{read_dir, remove_file},
+    path::Path,
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+};
+use axum::{
+    http::StatusCode,
+    response::Json,
+    extract::{Extension, Path as UrlPath},
+};
+use tower::BoxError;
+use sqlx::postgres::PgPool;
+
+use crate::{
+    utils::{SharedState, Config, ArtGcReport},
+    handlers::reload::parse_job_id,
+};
+
+// report of the art garbage collection of a finished reload
+pub async fn art_gc_report_handler(
+    Extension(state): Extension<SharedState>,
+    UrlPath(params): UrlPath<HashMap<String, String>>,
+) -> Result<Json<ArtGcReport>, (StatusCode, String)> {
+    let job_id = parse_job_id(&params)?;
+
+    match state.read().await.art_gc_reports.get(&job_id) {
+        Some(report) => Ok(Json(report.clone())),
+        None => Err((StatusCode::NOT_FOUND, format!("No art report for job {}", job_id))),
+    }
+}
+
+// delete art that nothing links to anymore, along with its file
+// art rows aren't deleted when the tracks, albums and artists using them are, so this runs after every reload
+// has to run while no reload is going, since a track may be linked to its art after the art is inserted
+pub async fn collect_art_garbage(pool: &PgPool, config: &Config) -> Result<ArtGcReport, BoxError> {
+    let mut report = ArtGcReport::default();
+
+    let deleted = sqlx::query_scalar!("DELETE FROM art WHERE \
+        NOT EXISTS (SELECT 1 FROM track_art WHERE track_art.art_id = art.art_id) AND \
+        NOT EXISTS (SELECT 1 FROM album_art WHERE album_art.art_id = art.art_id) AND \
+        NOT EXISTS (SELECT 1 FROM artist_art WHERE artist_art.art_id = art.art_id) \
+        RETURNING path")
+        .fetch_all(pool)
+        .await?;
+    for path in deleted.into_iter() {
+        match remove_file(Path::new(&config.art_directory).join(&path)) {
+            // already gone is as good as deleted
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => tracing::warn!("Failed to delete art file {}: {}", path, e),
+        }
+        report.deleted.push(path);
+    }
+
+    // compare what is left with the art directory
+    let paths: HashSet<String> = sqlx::query_scalar!("SELECT path FROM art")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    let mut files = HashSet::new();
+    for entry in read_dir(&config.art_directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.insert(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    report.orphan_files = files.difference(&paths).cloned().collect();
+    report.missing_files = paths.difference(&files).cloned().collect();
+    report.deleted.sort();
+    report.orphan_files.sort();
+    report.missing_files.sort();
+
+    if !report.orphan_files.is_empty() {
+        tracing::warn!("{} files in {} aren't used as art", report.orphan_files.len(), config.art_directory);
+    }
+    if !report.missing_files.is_empty() {
+        tracing::warn!("{} art files are missing from {}", report.missing_files.len(), config.art_directory);
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,344 @@
+use std::{
+    path::Path,
+    collections::{HashMap, HashSet},
+};
+use tower::BoxError;
+use sqlx::{
+    postgres::{PgPool, PgConnection},
+    types::time::PrimitiveDateTime
+};
+
+use crate::{
+    utils::Config,
+    handlers::{
+        reload::{clean_track_names, clean_genres, CleanNames, ARTIST_ROLE},
+        tag_parser::{ParsedTrack, NewArt},
+    },
+};
+
+// number of parsed tracks written at once
+pub const BULK_BATCH_SIZE: usize = 1000;
+
+// writes parsed tracks in batches, with a few multi-row inserts per table instead of a few queries per track
+// artist, album and art ids are kept in memory rather than looked up, so it's only used when the db starts out empty
+// tracks are expected to be new, moved tracks can't be recognized
+#[derive(Default)]
+pub struct BulkLoader {
+    artists: HashMap<String, i32>,
+    // by album name and sorted album artist ids, same as when tracks are added one at a time
+    albums: HashMap<(String, Vec<i32>), i32>,
+    genres: HashMap<String, i32>,
+    art: HashMap<Vec<u8>, i32>,
+    // only the first art found for an artist or album is kept
+    artists_with_art: HashSet<i32>,
+    albums_with_art: HashSet<i32>,
+}
+
+impl BulkLoader {
+    // whether there is nothing in the db a bulk load could collide with
+    pub async fn db_is_empty(pool: &PgPool) -> Result<bool, BoxError> {
+        let empty = sqlx::query_scalar!(r#"SELECT NOT EXISTS (SELECT 1 FROM track)
+            AND NOT EXISTS (SELECT 1 FROM album)
+            AND NOT EXISTS (SELECT 1 FROM artist)
+            AND NOT EXISTS (SELECT 1 FROM genre)
+            AND NOT EXISTS (SELECT 1 FROM art) as "empty!""#)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(empty)
+    }
+
+    // forget the ids of rows that may have been rolled back, then pick up the ones that were actually stored
+    pub async fn reload_ids(&mut self, pool: &PgPool) -> Result<(), BoxError> {
+        *self = BulkLoader::default();
+
+        for row in sqlx::query!("SELECT artist_id, artist_name FROM artist").fetch_all(pool).await? {
+            self.artists.insert(row.artist_name, row.artist_id);
+        }
+        for row in sqlx::query!(r#"SELECT album_id, album_name,
+            ARRAY(SELECT artist_id FROM artist_album WHERE artist_album.album_id = album.album_id ORDER BY artist_id) as "artist_ids!"
+            FROM album"#).fetch_all(pool).await? {
+            self.albums.insert((row.album_name, row.artist_ids), row.album_id);
+        }
+        for row in sqlx::query!("SELECT genre_id, genre_name FROM genre").fetch_all(pool).await? {
+            self.genres.insert(row.genre_name, row.genre_id);
+        }
+        for row in sqlx::query!("SELECT art_id, hash FROM art").fetch_all(pool).await? {
+            self.art.insert(row.hash, row.art_id);
+        }
+        self.artists_with_art = sqlx::query_scalar!("SELECT artist_id FROM artist_art").fetch_all(pool).await?.into_iter().collect();
+        self.albums_with_art = sqlx::query_scalar!("SELECT album_id FROM album_art").fetch_all(pool).await?.into_iter().collect();
+
+        Ok(())
+    }
+
+    // write a batch of tracks in a single transaction
+    // on error nothing of the batch is stored, but reload_ids has to be called before the loader is used again
+    pub async fn insert_batch(&mut self, pool: &PgPool, config: &Config, batch: &[ParsedTrack]) -> Result<(), BoxError> {
+        let mut tx = pool.begin().await?;
+
+        // art, stored once per distinct picture
+        let mut track_art_ids = Vec::with_capacity(batch.len());
+        let mut new_art: Vec<(Vec<u8>, String)> = Vec::new();
+        // written once the batch is committed
+        let mut new_art_files: Vec<NewArt> = Vec::new();
+        let mut new_art_seen = HashSet::new();
+        for parsed in batch.iter() {
+            let hash = match &parsed.picture {
+                Some(picture) => blake3::hash(picture),
+                None => {
+                    track_art_ids.push(None);
+                    continue;
+                },
+            };
+            let hash_bytes = hash.as_bytes().to_vec();
+            if !self.art.contains_key(&hash_bytes) && new_art_seen.insert(hash_bytes.clone()) {
+                let name = hash.to_hex().to_string();
+                new_art_files.push(NewArt {
+                    path: Path::new(&config.art_directory).join(&name),
+                    data: parsed.picture.clone().unwrap_or_default(),
+                });
+                new_art.push((hash_bytes.clone(), name));
+            }
+            track_art_ids.push(Some(hash_bytes));
+        }
+        let art_ids = next_ids(&mut tx, "art_art_id_seq", new_art.len()).await?;
+        let (art_hashes, art_paths): (Vec<Vec<u8>>, Vec<String>) = new_art.into_iter().unzip();
+        sqlx::query!("INSERT INTO art (art_id, hash, path) SELECT * FROM UNNEST($1::integer[], $2::bytea[], $3::text[])",
+            &art_ids, &art_hashes, &art_paths)
+            .execute(&mut *tx)
+            .await?;
+        self.art.extend(art_hashes.into_iter().zip(art_ids));
+        let track_art_ids: Vec<Option<i32>> = track_art_ids.into_iter()
+            .map(|hash| hash.and_then(|h| self.art.get(&h).copied()))
+            .collect();
+
+        // artists, both of tracks and of albums
+        let names: Vec<_> = batch.iter().map(|parsed| clean_track_names(&parsed.info)).collect();
+        let mut new_artists: Vec<String> = Vec::new();
+        let mut new_artists_seen = HashSet::new();
+        for clean in names.iter() {
+            let credited = clean.credits.iter().flat_map(|(_, names)| names.iter());
+            for name in clean.artist_names.iter().chain(clean.album_artist_names.iter()).chain(credited) {
+                if !self.artists.contains_key(name) && new_artists_seen.insert(name) {
+                    new_artists.push(name.clone());
+                }
+            }
+        }
+        let artist_ids = next_ids(&mut tx, "artist_artist_id_seq", new_artists.len()).await?;
+        sqlx::query!("INSERT INTO artist (artist_id, artist_name) SELECT * FROM UNNEST($1::integer[], $2::text[])",
+            &artist_ids, &new_artists)
+            .execute(&mut *tx)
+            .await?;
+        self.artists.extend(new_artists.into_iter().zip(artist_ids));
+
+        // albums, along with their album artists in the order the first of their tracks lists them
+        let mut new_albums: Vec<(String, Vec<i32>)> = Vec::new();
+        let mut new_album_artist_ids: Vec<Vec<i32>> = Vec::new();
+        let mut new_albums_seen = HashSet::new();
+        for clean in names.iter() {
+            let key = self.album_key(clean);
+            if !self.albums.contains_key(&key) && new_albums_seen.insert(key.clone()) {
+                new_albums.push(key);
+                new_album_artist_ids.push(clean.album_artist_names.iter().map(|name| self.artists[name]).collect());
+            }
+        }
+        let album_ids = next_ids(&mut tx, "album_album_id_seq", new_albums.len()).await?;
+        let album_names: Vec<String> = new_albums.iter().map(|(name, _)| name.clone()).collect();
+        sqlx::query!("INSERT INTO album (album_id, album_name) SELECT * FROM UNNEST($1::integer[], $2::text[])",
+            &album_ids, &album_names)
+            .execute(&mut *tx)
+            .await?;
+        let mut album_artist_ids = Vec::new();
+        let mut album_artist_album_ids = Vec::new();
+        let mut album_artist_ordinals = Vec::new();
+        for (artist_ids, album_id) in new_album_artist_ids.iter().zip(album_ids.iter()) {
+            for (ordinal, artist_id) in artist_ids.iter().enumerate() {
+                album_artist_ids.push(*artist_id);
+                album_artist_album_ids.push(*album_id);
+                album_artist_ordinals.push(ordinal as i32);
+            }
+        }
+        sqlx::query!("INSERT INTO artist_album (artist_id, album_id, ordinal) SELECT * FROM UNNEST($1::integer[], $2::integer[], $3::integer[])",
+            &album_artist_ids, &album_artist_album_ids, &album_artist_ordinals)
+            .execute(&mut *tx)
+            .await?;
+        self.albums.extend(new_albums.into_iter().zip(album_ids));
+
+        // genres
+        let genres: Vec<Vec<String>> = batch.iter().map(|parsed| clean_genres(&parsed.info, &config.genre_separators)).collect();
+        let mut new_genres: Vec<String> = Vec::new();
+        for genre in genres.iter().flatten() {
+            if !self.genres.contains_key(genre) && !new_genres.contains(genre) {
+                new_genres.push(genre.clone());
+            }
+        }
+        let genre_ids = next_ids(&mut tx, "genre_genre_id_seq", new_genres.len()).await?;
+        sqlx::query!("INSERT INTO genre (genre_id, genre_name) SELECT * FROM UNNEST($1::integer[], $2::text[])",
+            &genre_ids, &new_genres)
+            .execute(&mut *tx)
+            .await?;
+        self.genres.extend(new_genres.into_iter().zip(genre_ids));
+
+        // tracks and everything linking to them
+        let track_ids = next_ids(&mut tx, "track_track_id_seq", batch.len()).await?;
+        let mut tracks = TrackColumns::default();
+        let mut links = LinkColumns::default();
+        for ((((parsed, clean), track_genres), track_id), art_id) in batch.iter().zip(names.iter()).zip(genres.iter()).zip(track_ids).zip(track_art_ids) {
+            let info = &parsed.info;
+            let artist_ids: Vec<i32> = clean.artist_names.iter().map(|name| self.artists[name]).collect();
+            let album_id = self.albums[&self.album_key(clean)];
+
+            tracks.track_ids.push(track_id);
+            tracks.names.push(clean.track_name.clone());
+            tracks.libraries.push(info.library.clone());
+            tracks.paths.push(info.path_str.clone());
+            tracks.last_modified.push(info.last_modified);
+            tracks.lengths.push(info.length_seconds as i32);
+            tracks.fingerprints.push(info.fingerprint.clone());
+            tracks.recording_dates.push(info.recording_date.clone());
+            tracks.release_dates.push(info.release_date.clone());
+
+            for (ordinal, artist_id) in artist_ids.iter().enumerate() {
+                links.artist_track.push((*artist_id, track_id, ordinal as i32, ARTIST_ROLE));
+            }
+            for (role, names) in clean.credits.iter() {
+                for (ordinal, name) in names.iter().enumerate() {
+                    links.artist_track.push((self.artists[name], track_id, ordinal as i32, role));
+                }
+            }
+            links.album_track.push((album_id, track_id, info.track_number as i32, info.disc_number as i32));
+            for (ordinal, genre) in track_genres.iter().enumerate() {
+                links.track_genre.push((self.genres[genre], track_id, ordinal as i32));
+            }
+            if let Some(art_id) = art_id {
+                links.track_art.push((track_id, art_id));
+                for artist_id in artist_ids.iter() {
+                    if self.artists_with_art.insert(*artist_id) {
+                        links.artist_art.push((*artist_id, art_id));
+                    }
+                }
+                if self.albums_with_art.insert(album_id) {
+                    links.album_art.push((album_id, art_id));
+                }
+            }
+        }
+        tracks.insert(&mut tx).await?;
+        links.insert(&mut tx).await?;
+
+        tx.commit().await?;
+        for art in new_art_files.iter() {
+            art.write()?;
+        }
+        Ok(())
+    }
+
+    // album name and sorted album artist ids, once the album artists are stored
+    fn album_key(&self, clean: &CleanNames) -> (String, Vec<i32>) {
+        let mut artist_ids: Vec<i32> = clean.album_artist_names.iter().map(|name| self.artists[name]).collect();
+        artist_ids.sort_unstable();
+        (clean.album_name.clone(), artist_ids)
+    }
+}
+
+// take count ids from a sequence, so that rows can be linked before they are inserted
+async fn next_ids(conn: &mut PgConnection, sequence: &str, count: usize) -> Result<Vec<i32>, BoxError> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let ids = sqlx::query_scalar!(r#"SELECT nextval($1::text::regclass)::integer as "id!" FROM generate_series(1, $2)"#,
+        sequence, count as i32)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    Ok(ids)
+}
+
+// columns of the tracks of a batch, as arrays for UNNEST
+#[derive(Default)]
+struct TrackColumns {
+    track_ids: Vec<i32>,
+    names: Vec<String>,
+    libraries: Vec<String>,
+    paths: Vec<String>,
+    last_modified: Vec<PrimitiveDateTime>,
+    lengths: Vec<i32>,
+    fingerprints: Vec<Option<Vec<u8>>>,
+    recording_dates: Vec<Option<String>>,
+    release_dates: Vec<Option<String>>,
+}
+impl TrackColumns {
+    async fn insert(&self, conn: &mut PgConnection) -> Result<(), BoxError> {
+        sqlx::query!("INSERT INTO track (track_id, track_name, library, path, last_modified, length_seconds, fingerprint, recording_date, release_date) \
+            SELECT * FROM UNNEST($1::integer[], $2::text[], $3::text[], $4::text[], $5::timestamp[], $6::integer[], $7::bytea[], $8::text[], $9::text[])",
+            &self.track_ids, &self.names, &self.libraries, &self.paths, &self.last_modified, &self.lengths, &self.fingerprints as &[Option<Vec<u8>>],
+            &self.recording_dates as &[Option<String>], &self.release_dates as &[Option<String>])
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// rows linking the tracks of a batch to their artists, album, genres and art
+#[derive(Default)]
+struct LinkColumns<'a> {
+    // artist, track, ordinal and role
+    artist_track: Vec<(i32, i32, i32, &'a str)>,
+    album_track: Vec<(i32, i32, i32, i32)>,
+    track_genre: Vec<(i32, i32, i32)>,
+    track_art: Vec<(i32, i32)>,
+    artist_art: Vec<(i32, i32)>,
+    album_art: Vec<(i32, i32)>,
+}
+impl LinkColumns<'_> {
+    async fn insert(&self, conn: &mut PgConnection) -> Result<(), BoxError> {
+        let artist_ids: Vec<i32> = self.artist_track.iter().map(|t| t.0).collect();
+        let track_ids: Vec<i32> = self.artist_track.iter().map(|t| t.1).collect();
+        let ordinals: Vec<i32> = self.artist_track.iter().map(|t| t.2).collect();
+        let roles: Vec<&str> = self.artist_track.iter().map(|t| t.3).collect();
+        sqlx::query!("INSERT INTO artist_track (artist_id, track_id, ordinal, role) \
+            SELECT * FROM UNNEST($1::integer[], $2::integer[], $3::integer[], $4::text[])",
+            &artist_ids, &track_ids, &ordinals, &roles as &[&str])
+            .execute(&mut *conn)
+            .await?;
+
+        let album_ids: Vec<i32> = self.album_track.iter().map(|t| t.0).collect();
+        let track_ids: Vec<i32> = self.album_track.iter().map(|t| t.1).collect();
+        let track_nos: Vec<i32> = self.album_track.iter().map(|t| t.2).collect();
+        let disc_nos: Vec<i32> = self.album_track.iter().map(|t| t.3).collect();
+        sqlx::query!("INSERT INTO album_track (album_id, track_id, track_no, disc_no) \
+            SELECT * FROM UNNEST($1::integer[], $2::integer[], $3::integer[], $4::integer[])",
+            &album_ids, &track_ids, &track_nos, &disc_nos)
+            .execute(&mut *conn)
+            .await?;
+
+        let genre_ids: Vec<i32> = self.track_genre.iter().map(|t| t.0).collect();
+        let track_ids: Vec<i32> = self.track_genre.iter().map(|t| t.1).collect();
+        let ordinals: Vec<i32> = self.track_genre.iter().map(|t| t.2).collect();
+        sqlx::query!("INSERT INTO track_genre (genre_id, track_id, ordinal) SELECT * FROM UNNEST($1::integer[], $2::integer[], $3::integer[])",
+            &genre_ids, &track_ids, &ordinals)
+            .execute(&mut *conn)
+            .await?;
+
+        let (track_ids, art_ids): (Vec<i32>, Vec<i32>) = self.track_art.iter().copied().unzip();
+        sqlx::query!("INSERT INTO track_art (track_id, art_id) SELECT * FROM UNNEST($1::integer[], $2::integer[])",
+            &track_ids, &art_ids)
+            .execute(&mut *conn)
+            .await?;
+
+        let (artist_ids, art_ids): (Vec<i32>, Vec<i32>) = self.artist_art.iter().copied().unzip();
+        sqlx::query!("INSERT INTO artist_art (artist_id, art_id) SELECT * FROM UNNEST($1::integer[], $2::integer[])",
+            &artist_ids, &art_ids)
+            .execute(&mut *conn)
+            .await?;
+
+        let (album_ids, art_ids): (Vec<i32>, Vec<i32>) = self.album_art.iter().copied().unzip();
+        sqlx::query!("INSERT INTO album_art (album_id, art_id) SELECT * FROM UNNEST($1::integer[], $2::integer[])",
+            &album_ids, &art_ids)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use std::path::Path;
+use tower::BoxError;
+use sqlx::{
+    postgres::PgPool,
+    types::time::PrimitiveDateTime
+};
+
+use crate::{
+    utils::{SharedState, Config, CheckReport, CheckTrack, CheckModifiedTrack, CheckItem, CheckRepair, CheckError},
+    handlers::{
+        reload::{delete_track, reparse_track, delete_empty_albums_and_artists, ARTIST_ROLE},
+        art_gc::collect_art_garbage,
+        tag_parser::modified_time,
+    },
+};
+
+// run by `musicthing check [--repair]`
+// prints the report as json, then exits with 1 if anything was found, even if it was repaired
+// repairing writes to the db, so it's refused while the server is reloading or applying watcher changes
+pub async fn run_check(pool: PgPool, config: Config, repair: bool) -> Result<(), BoxError> {
+    if repair && scan_running(&pool).await? {
+        Err("A reload or watcher update is running, repair once it has finished")?;
+    }
+    let mut report = check(&pool, &config).await?;
+    if repair {
+        report.repair = Some(repair_report(&pool, &config, &report).await?);
+    }
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.is_clean() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// whether a reload or a batch of watcher changes is running, as the scan history has it
+// runs left running by a server that stopped are only marked as interrupted once it starts again
+async fn scan_running(pool: &PgPool) -> Result<bool, BoxError> {
+    Ok(sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM scan_run WHERE status = 'running' AND finished_at IS NULL) as "running!""#)
+        .fetch_one(pool)
+        .await?)
+}
+
+// cross check the db with the library roots and the art directory, without changing anything
+pub async fn check(pool: &PgPool, config: &Config) -> Result<CheckReport, BoxError> {
+    // struct for interfacing with the database
+    struct DBTrack {
+        track_id: i32,
+        library: String,
+        path: String,
+        last_modified: PrimitiveDateTime,
+        has_album: bool,
+        has_artist: bool,
+    }
+
+    let mut report = CheckReport::default();
+
+    let tracks = sqlx::query_as!(DBTrack, r#"SELECT track_id, library, path, last_modified,
+        EXISTS (SELECT 1 FROM album_track WHERE album_track.track_id = track.track_id) as "has_album!",
+        EXISTS (SELECT 1 FROM artist_track WHERE artist_track.track_id = track.track_id AND role = ($1)) as "has_artist!"
+        FROM track ORDER BY library, path"#, ARTIST_ROLE)
+        .fetch_all(pool)
+        .await?;
+    for track in tracks.into_iter() {
+        let check_track = CheckTrack {
+            id: track.track_id,
+            library: track.library.clone(),
+            path: track.path.clone(),
+        };
+        if !track.has_album {
+            report.tracks_without_album.push(check_track.clone());
+        }
+        if !track.has_artist {
+            report.tracks_without_artist.push(check_track.clone());
+        }
+
+        let library = match config.library(&track.library) {
+            Some(library) => library,
+            None => {
+                report.tracks_in_unknown_library.push(check_track);
+                continue;
+            },
+        };
+        let path_full = Path::new(&library.path).join(&track.path);
+        if !path_full.exists() {
+            report.tracks_missing.push(check_track);
+            continue;
+        }
+
+        // unreadable times are left for a reload to report
+        if let Ok(file_modified) = modified_time(&path_full) {
+            if file_modified != track.last_modified {
+                report.tracks_modified.push(CheckModifiedTrack {
+                    id: track.track_id,
+                    library: track.library,
+                    path: track.path,
+                    db_modified: track.last_modified.assume_utc().unix_timestamp(),
+                    file_modified: file_modified.assume_utc().unix_timestamp(),
+                });
+            }
+        }
+    }
+
+    report.empty_albums = sqlx::query_as!(CheckItem, "SELECT album.album_id as id, album_name as name FROM album \
+        WHERE NOT EXISTS (SELECT 1 FROM album_track WHERE album_track.album_id = album.album_id) \
+        ORDER BY album.album_id")
+        .fetch_all(pool)
+        .await?;
+    report.empty_artists = sqlx::query_as!(CheckItem, "SELECT artist.artist_id as id, artist_name as name FROM artist \
+        WHERE NOT EXISTS (SELECT 1 FROM artist_track WHERE artist_track.artist_id = artist.artist_id) \
+        AND NOT EXISTS (SELECT 1 FROM artist_album WHERE artist_album.artist_id = artist.artist_id) \
+        ORDER BY artist.artist_id")
+        .fetch_all(pool)
+        .await?;
+
+    let art = sqlx::query_as!(CheckItem, "SELECT art_id as id, path as name FROM art ORDER BY art_id")
+        .fetch_all(pool)
+        .await?;
+    report.art_missing_files = art.into_iter()
+        .filter(|art| !Path::new(&config.art_directory).join(&art.name).exists())
+        .collect();
+
+    Ok(report)
+}
+
+// fix what can be fixed without losing anything a reload wouldn't lose either
+// missing tracks are deleted, tracks that are modified or missing their album or artist are parsed again,
+// and art rows whose file is gone are deleted then recreated by parsing the tracks using them again
+async fn repair_report(pool: &PgPool, config: &Config, report: &CheckReport) -> Result<CheckRepair, BoxError> {
+    let mut repair = CheckRepair::default();
+    // nothing is served, so the cache only has to exist
+    let state = SharedState::default();
+
+    for track in report.tracks_missing.iter() {
+        delete_track(pool, track.id).await?;
+        repair.tracks_deleted += 1;
+    }
+
+    let mut to_reparse: Vec<CheckTrack> = report.tracks_modified.iter()
+        .map(|t| CheckTrack { id: t.id, library: t.library.clone(), path: t.path.clone() })
+        .chain(report.tracks_without_album.iter().cloned())
+        .chain(report.tracks_without_artist.iter().cloned())
+        .collect();
+    for art in report.art_missing_files.iter() {
+        let tracks = sqlx::query_as!(CheckTrack, "SELECT track.track_id as id, library, path FROM track \
+            JOIN track_art ON (track_art.track_id = track.track_id) \
+            WHERE art_id = ($1)", art.id)
+            .fetch_all(pool)
+            .await?;
+        to_reparse.extend(tracks);
+
+        // links to art don't cascade
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM track_art WHERE art_id = ($1)", art.id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM album_art WHERE art_id = ($1)", art.id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM artist_art WHERE art_id = ($1)", art.id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM art WHERE art_id = ($1)", art.id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        repair.art_deleted += 1;
+    }
+
+    // a track may be in more than one list, and tracks that were just deleted can't be parsed
+    to_reparse.sort_by_key(|t| t.id);
+    to_reparse.dedup_by_key(|t| t.id);
+    to_reparse.retain(|t| !report.tracks_missing.iter().any(|m| m.id == t.id));
+    for track in to_reparse.iter() {
+        let library = match config.library(&track.library) {
+            Some(library) => library,
+            None => continue,
+        };
+        match reparse_track(pool, config, &state, library, track.id, Path::new(&track.path)).await {
+            Ok(()) => repair.tracks_reparsed += 1,
+            Err(e) => repair.errors.push(CheckError { path: track.path.clone(), error: e.to_string() }),
+        }
+    }
+
+    let (albums_deleted, artists_deleted) = delete_empty_albums_and_artists(pool).await?;
+    repair.albums_deleted = albums_deleted as u64;
+    repair.artists_deleted = artists_deleted as u64;
+    repair.art_deleted += collect_art_garbage(pool, config).await?.deleted.len() as u64;
+
+    Ok(repair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handlers::{
+            reload::{start_job, run_job, spawn_job, ReloadTarget},
+            scan_history::start_scan_run,
+        },
+        utils::{ScanTrigger, testing::{TestDb, test_config, test_state, write_test_mp3}},
+    };
+
+    #[tokio::test]
+    async fn repair_fixes_what_check_reports() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let music = dir.path().join("music");
+        let art_dir = dir.path().join("art");
+        std::fs::create_dir_all(music.join("album")).unwrap();
+        std::fs::create_dir(&art_dir).unwrap();
+        let config = test_config(&music, &art_dir);
+        let state = test_state();
+
+        write_test_mp3(&music.join("album/a.mp3"), "A", 1);
+        write_test_mp3(&music.join("album/b.mp3"), "B", 2);
+        std::fs::write(music.join("album/cover.jpg"), b"cover").unwrap();
+        let job_id = start_job(&db.pool, &state, ScanTrigger::Manual, false, false).await.unwrap();
+        let job = run_job(db.pool.clone(), config.clone(), state.clone(), job_id, false, ReloadTarget::default());
+        spawn_job(db.pool.clone(), state.clone(), job_id, job).await.unwrap();
+        assert!(check(&db.pool, &config).await.unwrap().is_clean());
+
+        // a missing track, an empty album and an art row whose file is gone
+        std::fs::remove_file(music.join("album/a.mp3")).unwrap();
+        sqlx::query!("INSERT INTO album (album_name) VALUES ('Empty')").execute(&db.pool).await.unwrap();
+        let art_path = sqlx::query_scalar!("SELECT path FROM art").fetch_one(&db.pool).await.unwrap();
+        std::fs::remove_file(art_dir.join(&art_path)).unwrap();
+
+        let report = check(&db.pool, &config).await.unwrap();
+        let missing: Vec<&str> = report.tracks_missing.iter().map(|track| track.path.as_str()).collect();
+        assert_eq!(missing, vec!["album/a.mp3"]);
+        let empty_albums: Vec<&str> = report.empty_albums.iter().map(|album| album.name.as_str()).collect();
+        assert_eq!(empty_albums, vec!["Empty"]);
+        let art_missing: Vec<&str> = report.art_missing_files.iter().map(|art| art.name.as_str()).collect();
+        assert_eq!(art_missing, vec![art_path.as_str()]);
+        assert!(report.tracks_modified.is_empty() && report.empty_artists.is_empty());
+
+        let repair = repair_report(&db.pool, &config, &report).await.unwrap();
+        assert_eq!(repair.tracks_deleted, 1);
+        // b used the missing art, so it's parsed again to recreate it
+        assert_eq!(repair.tracks_reparsed, 1);
+        assert_eq!(repair.albums_deleted, 1);
+        assert_eq!(repair.art_deleted, 1);
+        assert!(repair.errors.is_empty());
+
+        assert!(check(&db.pool, &config).await.unwrap().is_clean());
+        assert_eq!(db.count("track").await, 1);
+        let art_path = sqlx::query_scalar!("SELECT path FROM art").fetch_one(&db.pool).await.unwrap();
+        assert!(art_dir.join(art_path).exists());
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn repair_is_refused_while_a_scan_runs() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), dir.path());
+
+        assert!(!scan_running(&db.pool).await.unwrap());
+        start_scan_run(&db.pool, ScanTrigger::Watcher, None, None, None).await.unwrap();
+        assert!(scan_running(&db.pool).await.unwrap());
+        assert!(run_check(db.pool.clone(), config, true).await.is_err());
+        db.drop_db().await;
+    }
+}
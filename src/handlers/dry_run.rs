@@ -0,0 +1,300 @@
+use std::{
+    path::{Path, PathBuf},
+    collections::{HashMap, HashSet},
+};
+use axum::{
+    http::StatusCode,
+    response::Json,
+    extract::{Extension, Query, Path as UrlPath},
+};
+use tower::BoxError;
+use sqlx::{
+    postgres::PgPool,
+    types::time::PrimitiveDateTime
+};
+
+use crate::{
+    utils::{SharedState, Config, Library, ReloadStatus, ReloadPhase, ScanTrigger, DryRunReport, DryRunMove, DryRunAlbum, DryRunError},
+    handlers::{
+        exclude::{ExcludeFilter, walk_audio_files},
+        reload::{
+            MissingTracks, ReloadCancelled, ReloadTarget, start_job, spawn_job, update_job, job_status, job_cancelled, parse_job_id,
+            parse_target, set_job_target, scan_worker_count, take_missing, clean_track_names,
+        },
+        tag_parser::{ParsedTrack, parse_tag, modified_time},
+    },
+};
+
+// start a dry run of a reload, or of a hard reload with ?hard=true
+// takes the same ?library= and ?path= as a reload
+// the dry run reads the library roots and the db but never writes to either, nor to the art directory
+pub async fn dry_run_handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(config): Extension<Config>,
+    Extension(state): Extension<SharedState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ReloadStatus>, (StatusCode, String)> {
+    let hard = params.get("hard").map(String::as_str) == Some("true");
+    let target = parse_target(&config, params.get("library").map(String::as_str), params.get("path").map(String::as_str))?;
+    if hard && target.is_partial(&config) {
+        return Err((StatusCode::BAD_REQUEST, "A hard reload can't be limited to a library or path".to_string()));
+    }
+    let job_id = start_job(&pool, &state, if hard { ScanTrigger::Hard } else { ScanTrigger::Manual }, hard, true).await?;
+    set_job_target(&state, job_id, &target).await;
+
+    spawn_job(pool.clone(), state.clone(), job_id, run_dry_run(pool, config, state.clone(), job_id, hard, target));
+
+    job_status(&state, job_id).await
+}
+
+// report of a finished dry run
+pub async fn dry_run_report_handler(
+    Extension(state): Extension<SharedState>,
+    UrlPath(params): UrlPath<HashMap<String, String>>,
+) -> Result<Json<DryRunReport>, (StatusCode, String)> {
+    let job_id = parse_job_id(&params)?;
+
+    match state.read().await.dry_run_reports.get(&job_id) {
+        Some(report) => Ok(Json(report.clone())),
+        None => Err((StatusCode::NOT_FOUND, format!("No dry run report for job {}", job_id))),
+    }
+}
+
+// track as currently stored, along with the names the album and artist diff is based on
+struct DBTrack {
+    track_id: i32,
+    library: String,
+    path: String,
+    last_modified: PrimitiveDateTime,
+    fingerprint: Option<Vec<u8>>,
+    // in every role, so that credited artists are kept too
+    artist_names: Vec<String>,
+    album_name: Option<String>,
+    album_artist_names: Vec<String>,
+}
+
+// albums and artists that will be left once the reload is done
+#[derive(Default)]
+struct Outcome {
+    albums: HashSet<DryRunAlbum>,
+    artists: HashSet<String>,
+}
+impl Outcome {
+    fn keep(&mut self, artist_names: &[String], album_name: &str, album_artist_names: &[String]) {
+        self.artists.extend(artist_names.iter().cloned());
+        self.artists.extend(album_artist_names.iter().cloned());
+        // album artists match in any order
+        let mut album_artist_names = album_artist_names.to_vec();
+        album_artist_names.sort();
+        self.albums.insert(DryRunAlbum {
+            name: album_name.to_string(),
+            album_artist_names,
+        });
+    }
+
+    fn keep_db_track(&mut self, track: &DBTrack) {
+        if let Some(album_name) = &track.album_name {
+            self.keep(&track.artist_names, album_name, &track.album_artist_names);
+        }
+    }
+
+    fn keep_parsed_track(&mut self, parsed: &ParsedTrack) {
+        let clean = clean_track_names(&parsed.info);
+        self.keep(&clean.artist_names, &clean.album_name, &clean.album_artist_names);
+        self.artists.extend(clean.credits.into_iter().flat_map(|(_, names)| names));
+    }
+}
+
+// path of a track as it appears in the report, prefixed with its library when there are several
+fn report_path(config: &Config, library: &str, path: &str) -> String {
+    if config.libraries.len() > 1 {
+        format!("{}/{}", library, path)
+    } else {
+        path.to_string()
+    }
+}
+
+// go through the same steps as load_db, only recording what would change
+async fn run_dry_run(pool: PgPool, config: Config, state: SharedState, job_id: i32, hard: bool, target: ReloadTarget) -> Result<(), BoxError> {
+    let libraries = target.libraries(&config);
+    let library_names: Vec<String> = libraries.iter().map(|l| l.name.clone()).collect();
+    let scope_str = target.scope.as_ref().map(|s| s.to_string_lossy().to_string());
+    let dir_prefix = scope_str.as_ref().map(|s| format!("{}/", s));
+
+    // only the target's tracks are looked at, but the album and artist diff needs every track
+    let all_tracks = sqlx::query_as!(DBTrack, r#"SELECT track.track_id as "track_id!",
+        track.library as "library!",
+        track.path as "path!",
+        last_modified as "last_modified!",
+        fingerprint,
+        ARRAY(SELECT artist_name FROM artist_track
+            JOIN artist ON (artist.artist_id = artist_track.artist_id)
+            WHERE artist_track.track_id = track.track_id) as "artist_names!",
+        album_name as "album_name?",
+        ARRAY(SELECT artist_name FROM artist_album
+            JOIN artist ON (artist.artist_id = artist_album.artist_id)
+            WHERE artist_album.album_id = album.album_id ORDER BY ordinal) as "album_artist_names!" FROM track
+        LEFT OUTER JOIN album_track ON (album_track.track_id = track.track_id)
+        LEFT OUTER JOIN album ON (album.album_id = album_track.album_id)"#)
+        .fetch_all(&pool)
+        .await?;
+    let in_target = |track: &DBTrack| library_names.contains(&track.library)
+        && (scope_str.is_none() || Some(&track.path) == scope_str.as_ref()
+            || matches!(&dir_prefix, Some(prefix) if track.path.starts_with(prefix.as_str())));
+    let (db_tracks, other_tracks): (Vec<DBTrack>, Vec<DBTrack>) = all_tracks.into_iter().partition(in_target);
+    let albums_before = sqlx::query_as!(DryRunAlbum, r#"SELECT album_name as "name!",
+        ARRAY(SELECT artist_name FROM artist_album
+            JOIN artist ON (artist.artist_id = artist_album.artist_id)
+            WHERE artist_album.album_id = album.album_id) as "album_artist_names!" FROM album"#)
+        .fetch_all(&pool)
+        .await?;
+    let artists_before = sqlx::query_scalar!("SELECT artist_name FROM artist")
+        .fetch_all(&pool)
+        .await?;
+
+    let mut report = DryRunReport::default();
+    let mut outcome = Outcome::default();
+    let mut missing = MissingTracks::new();
+    let mut to_parse: Vec<(&Library, PathBuf, Option<usize>)> = Vec::new();
+
+    // tracks outside of the target stay as they are
+    for track in other_tracks.iter() {
+        outcome.keep_db_track(track);
+    }
+
+    // old metadata. a hard reload wipes everything first
+    update_job(&state, job_id, |s| s.start_phase(ReloadPhase::UpdateOldMetadata, Some(db_tracks.len() as u64))).await;
+    let mut excludes = HashMap::new();
+    for library in libraries.iter() {
+        excludes.insert(library.name.as_str(), (*library, ExcludeFilter::new(&config, library)?));
+    }
+    let db_paths: HashMap<(&str, &str), usize> = db_tracks.iter().enumerate()
+        .map(|(i, t)| ((t.library.as_str(), t.path.as_str()), i))
+        .collect();
+    for (index, track) in db_tracks.iter().enumerate() {
+        // nothing was written, so a cancelled dry run can simply stop
+        if job_cancelled(&state, job_id).await {
+            Err(ReloadCancelled)?;
+        }
+        // db_tracks only holds tracks of these libraries
+        let (library, exclude) = match excludes.get_mut(track.library.as_str()) {
+            Some(e) => e,
+            None => continue,
+        };
+        let path_full = Path::new(&library.path).join(&track.path);
+        let path_str = report_path(&config, &track.library, &track.path);
+
+        if hard || exclude.is_excluded(Path::new(&track.path), false) {
+            report.tracks_to_delete.push(path_str);
+        } else if !path_full.exists() {
+            match &track.fingerprint {
+                Some(fingerprint) => missing.entry(fingerprint.clone()).or_default().push((track.track_id, path_str)),
+                None => report.tracks_to_delete.push(path_str),
+            }
+        } else {
+            match modified_time(&path_full) {
+                Ok(new_modified) if track.last_modified < new_modified => to_parse.push((*library, PathBuf::from(&track.path), Some(index))),
+                Ok(_) => outcome.keep_db_track(track),
+                Err(e) => {
+                    // a reload would record the error and leave the track as is
+                    report.errors.push(DryRunError { path: path_str, error: e.to_string() });
+                    outcome.keep_db_track(track);
+                },
+            }
+        }
+        update_job(&state, job_id, |s| s.tick()).await;
+    }
+
+    // new metadata
+    for library in libraries.iter() {
+        for path in walk_audio_files(&config, library, target.scope.as_deref())? {
+            if hard || !db_paths.contains_key(&(library.name.as_str(), path.to_string_lossy().as_ref())) {
+                to_parse.push((library, path, None));
+            }
+        }
+    }
+    update_job(&state, job_id, |s| s.start_phase(ReloadPhase::LoadNewMetadata, Some(to_parse.len() as u64))).await;
+
+    // parse in batches, using as many blocking threads as a reload would
+    for batch in to_parse.chunks(scan_worker_count(&config)) {
+        if job_cancelled(&state, job_id).await {
+            Err(ReloadCancelled)?;
+        }
+        let handles: Vec<_> = batch.iter().map(|(library, path, _)| {
+            let library = (*library).clone();
+            let path = path.clone();
+            tokio::spawn(async move { parse_tag(&library, &path).await })
+        }).collect();
+
+        for ((library, path, db_index), handle) in batch.iter().zip(handles) {
+            let path_str = report_path(&config, &library.name, &path.to_string_lossy());
+            match (handle.await?, db_index) {
+                // modified track
+                (Ok(parsed), Some(_)) => {
+                    outcome.keep_parsed_track(&parsed);
+                    report.tracks_to_update.push(path_str);
+                    update_job(&state, job_id, |s| s.files_updated += 1).await;
+                },
+                // new file, which may be a missing track that moved
+                // moved tracks keep their id but take the metadata of their new file, as it may have been retagged too
+                (Ok(parsed), None) => {
+                    outcome.keep_parsed_track(&parsed);
+                    let moved = parsed.info.fingerprint.as_ref()
+                        .and_then(|fingerprint| take_missing(&mut missing, fingerprint, &path_str))
+                        .and_then(|track_id| db_tracks.iter().find(|t| t.track_id == track_id));
+                    match moved {
+                        Some(track) => {
+                            report.tracks_to_move.push(DryRunMove { from: report_path(&config, &track.library, &track.path), to: path_str });
+                            update_job(&state, job_id, |s| s.files_moved += 1).await;
+                        },
+                        None => {
+                            report.tracks_to_add.push(path_str);
+                            update_job(&state, job_id, |s| s.files_added += 1).await;
+                        },
+                    }
+                },
+                (Err(e), db_index) => {
+                    if let Some(index) = db_index {
+                        outcome.keep_db_track(&db_tracks[*index]);
+                    }
+                    report.errors.push(DryRunError { path: path_str, error: e.to_string() });
+                    update_job(&state, job_id, |s| s.errors += 1).await;
+                },
+            }
+            update_job(&state, job_id, |s| {
+                s.files_seen += 1;
+                s.tick();
+            }).await;
+        }
+    }
+
+    // tracks that weren't found anywhere else would be deleted
+    report.tracks_to_delete.extend(missing.into_values().flatten().map(|(_, path)| path));
+    update_job(&state, job_id, |s| s.files_deleted = report.tracks_to_delete.len() as u64).await;
+
+    // compare albums and artists before and after
+    let albums_before: HashSet<DryRunAlbum> = albums_before.into_iter()
+        .map(|mut album| {
+            album.album_artist_names.sort();
+            album
+        })
+        .collect();
+    let artists_before: HashSet<String> = artists_before.into_iter().collect();
+    report.albums_to_create = outcome.albums.difference(&albums_before).cloned().collect();
+    report.albums_to_empty = albums_before.difference(&outcome.albums).cloned().collect();
+    report.artists_to_create = outcome.artists.difference(&artists_before).cloned().collect();
+    report.artists_to_empty = artists_before.difference(&outcome.artists).cloned().collect();
+
+    report.tracks_to_add.sort();
+    report.tracks_to_update.sort();
+    report.tracks_to_move.sort_by(|a, b| a.from.cmp(&b.from));
+    report.tracks_to_delete.sort();
+    report.albums_to_create.sort();
+    report.albums_to_empty.sort();
+    report.artists_to_create.sort();
+    report.artists_to_empty.sort();
+
+    state.write().await.dry_run_reports.insert(job_id, report);
+
+    Ok(())
+}
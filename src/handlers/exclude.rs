@@ -0,0 +1,127 @@
+use std::{
+    path::{Path, PathBuf},
+    collections::HashMap,
+};
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+use tower::BoxError;
+use walkdir::WalkDir;
+
+use crate::{
+    utils::{Config, Library},
+    handlers::RECOGNIZED_EXTENSIONS,
+};
+
+// gitignore-style files leaving paths out of the library, honored in any directory of a library
+pub const IGNORE_FILE_NAME: &str = ".musicthingignore";
+
+// decides which paths under a library's root are left out of the library
+// ignore files are only read once, so a filter should only be kept for the length of a scan
+pub struct ExcludeFilter {
+    root: PathBuf,
+    // exclude_globs from config
+    global: Gitignore,
+    // ignore file of every directory looked at so far, by path relative to the root
+    dirs: HashMap<PathBuf, Option<Gitignore>>,
+}
+impl ExcludeFilter {
+    pub fn new(config: &Config, library: &Library) -> Result<ExcludeFilter, BoxError> {
+        let root = PathBuf::from(&library.path);
+        let mut builder = GitignoreBuilder::new(&root);
+        for glob in config.exclude_globs.iter() {
+            builder.add_line(None, glob)?;
+        }
+
+        Ok(ExcludeFilter {
+            global: builder.build()?,
+            root,
+            dirs: HashMap::new(),
+        })
+    }
+
+    // whether a path relative to the root is excluded, either itself or through one of its parent directories
+    pub fn is_excluded(&mut self, path: &Path, is_dir: bool) -> bool {
+        let mut parents: Vec<PathBuf> = path.ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect();
+        parents.reverse();
+
+        parents.iter().any(|parent| self.matches(parent, true)) || self.matches(path, is_dir)
+    }
+
+    // whether a path relative to the root is excluded, without looking at its parent directories
+    // enough when walking down from the root, since excluded directories are never entered
+    pub fn matches(&mut self, path: &Path, is_dir: bool) -> bool {
+        let path_full = self.root.join(path);
+
+        // the ignore file closest to the path wins, same as git. exclude_globs come last
+        let parents: Vec<PathBuf> = path.ancestors().skip(1).map(Path::to_path_buf).collect();
+        for parent in parents.iter() {
+            if let Some(gitignore) = self.dir_ignore(parent) {
+                match gitignore.matched(&path_full, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => (),
+                }
+            }
+        }
+
+        self.global.matched(&path_full, is_dir).is_ignore()
+    }
+
+    // ignore file of a directory, read the first time it is needed
+    fn dir_ignore(&mut self, dir: &Path) -> Option<&Gitignore> {
+        let root = &self.root;
+        self.dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+            let ignore_file = root.join(dir).join(IGNORE_FILE_NAME);
+            if !ignore_file.is_file() {
+                return None;
+            }
+
+            // invalid lines are skipped rather than failing the whole scan
+            let (gitignore, err) = Gitignore::new(&ignore_file);
+            if let Some(e) = err {
+                tracing::warn!("Error in {}: {}", ignore_file.to_string_lossy(), e);
+            }
+            Some(gitignore)
+        }).as_ref()
+    }
+}
+
+// paths of the audio files of a library at or below start that aren't excluded, relative to the library's root
+// start is relative to the root too, the whole library is walked if it is none
+pub fn walk_audio_files(config: &Config, library: &Library, start: Option<&Path>) -> Result<impl Iterator<Item = PathBuf>, BoxError> {
+    let mut filter = ExcludeFilter::new(config, library)?;
+    let root = PathBuf::from(&library.path);
+    let start = match start {
+        Some(start) => root.join(start),
+        None => root.clone(),
+    };
+
+    let filter_root = root.clone();
+    let files = WalkDir::new(start).follow_links(true).into_iter()
+        // excluded directories are skipped along with everything in them
+        .filter_entry(move |entry| {
+            let path = entry.path().strip_prefix(&filter_root).unwrap_or(entry.path());
+            let is_dir = entry.file_type().is_dir();
+            if path.as_os_str().is_empty() {
+                true
+            } else if entry.depth() == 0 {
+                // start may be inside an excluded directory
+                !filter.is_excluded(path, is_dir)
+            } else {
+                !filter.matches(path, is_dir)
+            }
+        })
+        // silently discards of errors
+        .filter_map(|e| e.ok())
+        .filter(|entry| matches!(entry.path().extension(), Some(ext) if RECOGNIZED_EXTENSIONS.iter().any(|i| i == &ext)))
+        // walkdir only yields paths under the root
+        .map(move |entry| entry.path().strip_prefix(&root).unwrap_or(entry.path()).to_path_buf());
+
+    Ok(files)
+}
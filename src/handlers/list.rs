@@ -1,7 +1,7 @@
 use axum::{
     http::StatusCode,
     response::{Json},
-    extract::{Extension, Path},
+    extract::{Extension, Path, Query},
 };
 use tower::BoxError;
 use sqlx::postgres::PgPool;
@@ -9,15 +9,84 @@ use std::collections::HashMap;
 use crate::{
     utils::{
         internal_error,
-        SharedState, AlbumCache,
-        ListAlbum, ListAlbumID, ListDisc, ListTrack,
+        SharedState, AlbumCache, Config,
+        ListAlbum, ListAlbumID, ListDisc, ListTrack, ListArtist, ListGenre, ListGenreID,
+        ListArtistID, ListCredit,
     },
+    handlers::reload::ARTIST_ROLE,
 };
 
+// what part of the albums to list and in which order
+#[derive(Default)]
+struct AlbumFilter {
+    library: Option<String>,
+    // inclusive
+    years: Option<(i32, i32)>,
+    sort_by_year: bool,
+}
+
+impl AlbumFilter {
+    fn from_params(params: &HashMap<String, String>) -> Result<AlbumFilter, String> {
+        let year = |value: &str| value.trim().parse::<i32>().map_err(|_| format!("Invalid year {}", value));
+
+        let years = match (params.get("year"), params.get("decade")) {
+            (Some(_), Some(_)) => return Err("Only one of year and decade can be given".to_string()),
+            // a single year or a range, 1990-1999
+            (Some(value), None) => match value.split_once('-') {
+                Some((from, to)) => Some((year(from)?, year(to)?)),
+                None => Some((year(value)?, year(value)?)),
+            },
+            // 1990 or 1990s
+            (None, Some(value)) => {
+                let decade = year(value.strip_suffix('s').unwrap_or(value))?;
+                if decade % 10 != 0 {
+                    return Err(format!("Invalid decade {}", value));
+                }
+                Some((decade, decade + 9))
+            },
+            (None, None) => None,
+        };
+
+        let sort_by_year = match params.get("sort").map(String::as_str) {
+            None | Some("name") => false,
+            Some("year") => true,
+            Some(other) => return Err(format!("Can't sort albums by {}", other)),
+        };
+
+        Ok(AlbumFilter {
+            library: params.get("library").cloned(),
+            years,
+            sort_by_year,
+        })
+    }
+
+    fn is_everything(&self) -> bool {
+        self.library.is_none() && self.years.is_none() && !self.sort_by_year
+    }
+}
+
+// ?library=name only lists the albums with tracks in that library
+// ?year=1999, ?year=1990-1999 or ?decade=1990s only list the albums of those years
+// ?sort=year sorts by year instead of name, albums without a year last
 pub async fn list_albums_handler(
     Extension(pool): Extension<PgPool>,
+    Extension(config): Extension<Config>,
     Extension(state): Extension<SharedState>,
+    Query(params): Query<HashMap<String, String>>,
 ) -> Result<Json<Option<Vec<ListAlbum>>>, (StatusCode, String)> {
+    let filter = AlbumFilter::from_params(&params).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    if let Some(library) = &filter.library {
+        if config.library(library).is_none() {
+            return Err((StatusCode::NOT_FOUND, format!("No library named {}", library)));
+        }
+    }
+
+    // only the full listing is cached
+    if !filter.is_everything() {
+        let albums = list_albums(&pool, &filter).await.map_err(internal_error)?;
+        return Ok(Json(albums));
+    }
+
     {
         let cache_read = state.read().await;
 
@@ -28,7 +97,7 @@ pub async fn list_albums_handler(
 
     // if function did not early return in previous step this means list cache is outdated
     // update state with new list cache
-    let new_list_album_cache = list_albums(&pool).await.map_err(internal_error)?;
+    let new_list_album_cache = list_albums(&pool, &filter).await.map_err(internal_error)?;
     state.write().await.album_cache = AlbumCache {
         list_album_cache_outdated: false,
         list_album_cache: new_list_album_cache.clone(),
@@ -38,25 +107,118 @@ pub async fn list_albums_handler(
     Ok(Json(new_list_album_cache))
 }
 
-async fn list_albums(pool: &PgPool) -> Result<Option<Vec<ListAlbum>>, BoxError> {
+async fn list_albums(pool: &PgPool, filter: &AlbumFilter) -> Result<Option<Vec<ListAlbum>>, BoxError> {
+    let (from_year, to_year) = match filter.years {
+        Some((from_year, to_year)) => (Some(from_year), Some(to_year)),
+        None => (None, None),
+    };
+
     // query all relevant information
-    let albums = sqlx::query_as!(ListAlbum, r#"SELECT DISTINCT
+    let albums = sqlx::query!(r#"SELECT
         album.album_id as id, 
         album_name as name, 
-        artist_name, 
-        path as "art_path?" FROM album
-        JOIN artist_album ON (album.album_id = artist_album.album_id)
-        JOIN artist ON (artist.artist_id = artist_album.artist_id)
+        path as "art_path?",
+        recording_date as "recording_date?",
+        release_date as "release_date?",
+        CAST(LEFT(COALESCE(release_date, recording_date), 4) AS integer) as "year?" FROM album
+        LEFT OUTER JOIN album_date ON (album_date.album_id = album.album_id)
         LEFT OUTER JOIN album_art ON (album_art.album_id = album.album_id)
         LEFT OUTER JOIN art ON (album_art.art_id = art.art_id)
-        ORDER BY (album_name)"#)
+        WHERE ($1::text IS NULL OR EXISTS (SELECT 1 FROM album_track
+            JOIN track ON (track.track_id = album_track.track_id)
+            WHERE album_track.album_id = album.album_id AND track.library = ($1)))
+        AND ($2::integer IS NULL OR CAST(LEFT(COALESCE(release_date, recording_date), 4) AS integer) BETWEEN ($2) AND ($3))
+        ORDER BY (album_name)"#, filter.library, from_year, to_year)
         .fetch_all(pool)
         .await?;
+    let album_ids: Vec<i32> = albums.iter().map(|album| album.id).collect();
+    let mut artists = album_artists(pool, &album_ids).await?;
+
+    let mut albums: Vec<ListAlbum> = albums.into_iter().map(|album| ListAlbum {
+        id: album.id,
+        name: album.name,
+        album_artists: artists.remove(&album.id).unwrap_or_default(),
+        art_path: album.art_path,
+        recording_date: album.recording_date,
+        release_date: album.release_date,
+        year: album.year,
+    }).collect();
+    // stable, so albums of the same year stay sorted by name
+    if filter.sort_by_year {
+        albums.sort_by_key(|album| (album.year.is_none(), album.year));
+    }
 
-    // just return
     Ok(Some(albums))
 }
 
+// album artists in order, by album
+async fn album_artists(pool: &PgPool, album_ids: &[i32]) -> Result<HashMap<i32, Vec<ListArtist>>, BoxError> {
+    let rows = sqlx::query!(r#"SELECT album_id as "album_id!", artist.artist_id as "artist_id!", artist_name as "artist_name!" FROM artist_album
+        JOIN artist ON (artist.artist_id = artist_album.artist_id)
+        WHERE album_id = ANY($1)
+        ORDER BY album_id, ordinal"#, album_ids)
+        .fetch_all(pool)
+        .await?;
+
+    let mut artists: HashMap<i32, Vec<ListArtist>> = HashMap::new();
+    for row in rows {
+        artists.entry(row.album_id).or_default().push(ListArtist {
+            id: row.artist_id,
+            name: row.artist_name,
+        });
+    }
+    Ok(artists)
+}
+
+// albums sorted by name
+async fn albums_by_id(pool: &PgPool, album_ids: &[i32]) -> Result<Vec<ListAlbum>, BoxError> {
+    let albums = sqlx::query!(r#"SELECT
+        album.album_id as id,
+        album_name as name,
+        path as "art_path?",
+        recording_date as "recording_date?",
+        release_date as "release_date?",
+        CAST(LEFT(COALESCE(release_date, recording_date), 4) AS integer) as "year?" FROM album
+        LEFT OUTER JOIN album_date ON (album_date.album_id = album.album_id)
+        LEFT OUTER JOIN album_art ON (album_art.album_id = album.album_id)
+        LEFT OUTER JOIN art ON (album_art.art_id = art.art_id)
+        WHERE album.album_id = ANY($1)
+        ORDER BY (album_name)"#, album_ids)
+        .fetch_all(pool)
+        .await?;
+    let mut artists = album_artists(pool, album_ids).await?;
+
+    Ok(albums.into_iter().map(|album| ListAlbum {
+        id: album.id,
+        name: album.name,
+        album_artists: artists.remove(&album.id).unwrap_or_default(),
+        art_path: album.art_path,
+        recording_date: album.recording_date,
+        release_date: album.release_date,
+        year: album.year,
+    }).collect())
+}
+
+// track artists in order, by track
+async fn track_artists(pool: &PgPool, track_ids: &[i32]) -> Result<HashMap<i32, Vec<ListArtist>>, BoxError> {
+    let rows = sqlx::query!(r#"SELECT track_id as "track_id!", artist.artist_id as "artist_id!", artist_name as "artist_name!" FROM artist_track
+        JOIN artist ON (artist.artist_id = artist_track.artist_id)
+        WHERE track_id = ANY($1) AND role = ($2)
+        ORDER BY track_id, ordinal"#,
+        track_ids, ARTIST_ROLE)
+        .fetch_all(pool)
+        .await?;
+
+    let mut artists: HashMap<i32, Vec<ListArtist>> = HashMap::new();
+    for row in rows {
+        artists.entry(row.track_id).or_default().push(ListArtist {
+            id: row.artist_id,
+            name: row.artist_name,
+        });
+    }
+    Ok(artists)
+}
+
 pub async fn list_album_id_handler(
     Extension(pool): Extension<PgPool>,
     Extension(state): Extension<SharedState>,
@@ -91,8 +253,10 @@ async fn list_album_id(pool: &PgPool, id: &str) -> Result<Option<ListAlbumID>, B
     struct DBAlbum {
         id: i32,
         name: String,
-        album_artist_name: String,
         art_path: Option<String>,
+        recording_date: Option<String>,
+        release_date: Option<String>,
+        year: Option<i32>,
     }
 
     // return early if parsing fails
@@ -103,13 +267,14 @@ async fn list_album_id(pool: &PgPool, id: &str) -> Result<Option<ListAlbumID>, B
     let id_int = id_parse.unwrap();
 
     // get our album
-    let album = sqlx::query_as!(DBAlbum, r#"SELECT DISTINCT 
+    let album = sqlx::query_as!(DBAlbum, r#"SELECT
         album.album_id as id, 
         album_name as name, 
-        artist_name as album_artist_name, 
-        path as "art_path?" FROM album
-        JOIN artist_album ON (album.album_id = artist_album.album_id)
-        JOIN artist ON (artist.artist_id = artist_album.artist_id)
+        path as "art_path?",
+        recording_date as "recording_date?",
+        release_date as "release_date?",
+        CAST(LEFT(COALESCE(release_date, recording_date), 4) AS integer) as "year?" FROM album
+        LEFT OUTER JOIN album_date ON (album_date.album_id = album.album_id)
         LEFT OUTER JOIN album_art ON (album_art.album_id = album.album_id)
         LEFT OUTER JOIN art ON (album_art.art_id = art.art_id)
         WHERE album.album_id = ($1)"#, id_int)
@@ -124,13 +289,17 @@ async fn list_album_id(pool: &PgPool, id: &str) -> Result<Option<ListAlbumID>, B
         .fetch_all(pool)
         .await?;
 
+        // gather the artists of every track on the album
+        let track_ids = sqlx::query_scalar!("SELECT track_id FROM album_track WHERE album_id = ($1)", id_int)
+            .fetch_all(pool)
+            .await?;
+        let mut track_artists = track_artists(pool, &track_ids).await?;
+
         // construct disc_struct
         let mut disc_structs: Vec<ListDisc> = Vec::new();
         for disc in discs {
             // gather all tracks on disc
-            let tracks = sqlx::query!(r#"SELECT track.track_id as track_id, track_no, artist_name, track_name, track.path as path, art.path as "art_path?", length_seconds FROM track
-                JOIN artist_track ON (track.track_id = artist_track.track_id)
-                JOIN artist ON (artist_track.artist_id = artist.artist_id)
+            let tracks = sqlx::query!(r#"SELECT track.track_id as track_id, track_no, track_name, track.library as library, track.path as path, art.path as "art_path?", length_seconds FROM track
                 JOIN album_track ON (track.track_id = album_track.track_id)
                 LEFT OUTER JOIN track_art ON (track_art.track_id = track.track_id)
                 LEFT OUTER JOIN art ON (track_art.art_id = art.art_id)
@@ -143,8 +312,9 @@ async fn list_album_id(pool: &PgPool, id: &str) -> Result<Option<ListAlbumID>, B
             let track_structs = tracks.iter().map(|track| ListTrack {
                     id: track.track_id,
                     number: track.track_no.unwrap_or(0),
-                    artist: track.artist_name.clone(),
+                    artists: track_artists.remove(&track.track_id).unwrap_or_default(),
                     name: track.track_name.clone(),
+                    library: track.library.clone(),
                     path: track.path.clone(),
                     art_path: track.art_path.clone(),
                     length_seconds: track.length_seconds,
@@ -160,11 +330,215 @@ async fn list_album_id(pool: &PgPool, id: &str) -> Result<Option<ListAlbumID>, B
         Ok(Some(ListAlbumID {
             id: alb.id,
             name: alb.name,
-            album_artist_name: alb.album_artist_name,
+            album_artists: album_artists(pool, &[id_int]).await?.remove(&id_int).unwrap_or_default(),
             art_path: alb.art_path,
+            recording_date: alb.recording_date,
+            release_date: alb.release_date,
+            year: alb.year,
             discs: disc_structs,
         }))
     } else {
         Ok(None)
     }
-}
\ No newline at end of file
+}
+
+pub async fn list_genres_handler(
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<ListGenre>>, (StatusCode, String)> {
+    let genres = sqlx::query_as!(ListGenre, r#"SELECT
+        genre.genre_id as id,
+        genre_name as name,
+        COUNT(track_genre.track_id) as "track_count!" FROM genre
+        LEFT OUTER JOIN track_genre ON (track_genre.genre_id = genre.genre_id)
+        GROUP BY genre.genre_id
+        ORDER BY (genre_name)"#)
+        .fetch_all(&pool)
+        .await
+        .map_err(|err| internal_error(err.into()))?;
+
+    Ok(Json(genres))
+}
+
+pub async fn list_genre_id_handler(
+    Extension(pool): Extension<PgPool>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<Option<ListGenreID>>, (StatusCode, String)> {
+    let id = params.get("id").expect("key id not found in parameter");
+    let genre = list_genre_id(&pool, id).await.map_err(internal_error)?;
+    Ok(Json(genre))
+}
+
+// albums and tracks of a genre, tracks ordered as they are on their albums
+async fn list_genre_id(pool: &PgPool, id: &str) -> Result<Option<ListGenreID>, BoxError> {
+    let id_int = match id.parse::<i32>() {
+        Ok(id_int) => id_int,
+        Err(_) => return Ok(None),
+    };
+
+    let genre = sqlx::query!("SELECT genre_id, genre_name FROM genre WHERE genre_id = ($1)", id_int)
+        .fetch_optional(pool)
+        .await?;
+    let genre = match genre {
+        Some(genre) => genre,
+        None => return Ok(None),
+    };
+
+    let album_ids = sqlx::query_scalar!("SELECT DISTINCT album_id FROM album_track
+        JOIN track_genre ON (track_genre.track_id = album_track.track_id)
+        WHERE genre_id = ($1)", id_int)
+        .fetch_all(pool)
+        .await?;
+
+    let tracks = sqlx::query!(r#"SELECT track.track_id as track_id, track_no, track_name, track.library as library, track.path as path, art.path as "art_path?", length_seconds FROM track
+        JOIN track_genre ON (track_genre.track_id = track.track_id)
+        JOIN album_track ON (album_track.track_id = track.track_id)
+        JOIN album ON (album.album_id = album_track.album_id)
+        LEFT OUTER JOIN track_art ON (track_art.track_id = track.track_id)
+        LEFT OUTER JOIN art ON (track_art.art_id = art.art_id)
+        WHERE track_genre.genre_id = ($1)
+        ORDER BY album_name, album.album_id, disc_no, track_no"#, id_int)
+        .fetch_all(pool)
+        .await?;
+    let track_ids: Vec<i32> = tracks.iter().map(|track| track.track_id).collect();
+    let mut track_artists = track_artists(pool, &track_ids).await?;
+
+    Ok(Some(ListGenreID {
+        id: genre.genre_id,
+        name: genre.genre_name,
+        albums: albums_by_id(pool, &album_ids).await?,
+        tracks: tracks.into_iter().map(|track| ListTrack {
+            id: track.track_id,
+            number: track.track_no.unwrap_or(0),
+            artists: track_artists.remove(&track.track_id).unwrap_or_default(),
+            name: track.track_name,
+            library: track.library,
+            path: track.path,
+            art_path: track.art_path,
+            length_seconds: track.length_seconds,
+        }).collect(),
+    }))
+}
+
+// ?role=composer only lists the tracks the artist is credited on as composer
+pub async fn list_artist_id_handler(
+    Extension(pool): Extension<PgPool>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Option<ListArtistID>>, (StatusCode, String)> {
+    let id = params.get("id").expect("key id not found in parameter");
+    let artist = list_artist_id(&pool, id, query.get("role").map(String::as_str)).await.map_err(internal_error)?;
+    Ok(Json(artist))
+}
+
+// albums of an artist and their tracks by role, the artist role first
+async fn list_artist_id(pool: &PgPool, id: &str, role: Option<&str>) -> Result<Option<ListArtistID>, BoxError> {
+    let id_int = match id.parse::<i32>() {
+        Ok(id_int) => id_int,
+        Err(_) => return Ok(None),
+    };
+
+    let artist = sqlx::query!("SELECT artist_id, artist_name FROM artist WHERE artist_id = ($1)", id_int)
+        .fetch_optional(pool)
+        .await?;
+    let artist = match artist {
+        Some(artist) => artist,
+        None => return Ok(None),
+    };
+
+    let album_ids = sqlx::query_scalar!("SELECT album_id FROM artist_album WHERE artist_id = ($1)", id_int)
+        .fetch_all(pool)
+        .await?;
+
+    let tracks = sqlx::query!(r#"SELECT role, album.album_id as album_id, track.track_id as track_id, track_no, track_name, track.library as library, track.path as path, art.path as "art_path?", length_seconds FROM artist_track
+        JOIN track ON (track.track_id = artist_track.track_id)
+        JOIN album_track ON (album_track.track_id = track.track_id)
+        JOIN album ON (album.album_id = album_track.album_id)
+        LEFT OUTER JOIN track_art ON (track_art.track_id = track.track_id)
+        LEFT OUTER JOIN art ON (track_art.art_id = art.art_id)
+        WHERE artist_track.artist_id = ($1) AND ($2::text IS NULL OR role = ($2))
+        ORDER BY role, album_name, album.album_id, disc_no, track_no"#, id_int, role)
+        .fetch_all(pool)
+        .await?;
+    let track_ids: Vec<i32> = tracks.iter().map(|track| track.track_id).collect();
+    let track_artists = track_artists(pool, &track_ids).await?;
+
+    // tracks come sorted by role, so each role's are together
+    // a track shows up once for every role the artist has on it
+    let mut credits: Vec<(String, Vec<i32>, Vec<ListTrack>)> = Vec::new();
+    for track in tracks {
+        if credits.last().map(|(role, _, _)| role != &track.role).unwrap_or(true) {
+            credits.push((track.role.clone(), Vec::new(), Vec::new()));
+        }
+        let (_, album_ids, tracks) = credits.last_mut().expect("role was just pushed");
+        if !album_ids.contains(&track.album_id) {
+            album_ids.push(track.album_id);
+        }
+        tracks.push(ListTrack {
+            id: track.track_id,
+            number: track.track_no.unwrap_or(0),
+            artists: track_artists.get(&track.track_id).cloned().unwrap_or_default(),
+            name: track.track_name,
+            library: track.library,
+            path: track.path,
+            art_path: track.art_path,
+            length_seconds: track.length_seconds,
+        });
+    }
+
+    let mut list_credits = Vec::with_capacity(credits.len());
+    for (role, album_ids, tracks) in credits {
+        list_credits.push(ListCredit {
+            role,
+            albums: albums_by_id(pool, &album_ids).await?,
+            tracks,
+        });
+    }
+    list_credits.sort_by_key(|credit| credit.role != ARTIST_ROLE);
+
+    Ok(Some(ListArtistID {
+        id: artist.artist_id,
+        name: artist.artist_name,
+        albums: albums_by_id(pool, &album_ids).await?,
+        credits: list_credits,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::TestDb;
+
+    #[tokio::test]
+    async fn artist_credited_twice_on_a_track() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let artist_id = sqlx::query_scalar!("INSERT INTO artist (artist_name) VALUES ('Singer') RETURNING artist_id")
+            .fetch_one(&db.pool).await.unwrap();
+        let album_id = sqlx::query_scalar!("INSERT INTO album (album_name) VALUES ('Album') RETURNING album_id")
+            .fetch_one(&db.pool).await.unwrap();
+        sqlx::query!("INSERT INTO artist_album (artist_id, album_id, ordinal) VALUES ($1, $2, 0)", artist_id, album_id)
+            .execute(&db.pool).await.unwrap();
+        let track_id = sqlx::query_scalar!("INSERT INTO track (track_name, library, path, last_modified, length_seconds) \
+            VALUES ('Song', 'default', 'song.mp3', now(), 60) RETURNING track_id")
+            .fetch_one(&db.pool).await.unwrap();
+        sqlx::query!("INSERT INTO album_track (album_id, track_id, track_no, disc_no) VALUES ($1, $2, 1, 1)", album_id, track_id)
+            .execute(&db.pool).await.unwrap();
+        // sings and wrote the song
+        for role in [ARTIST_ROLE, "composer"] {
+            sqlx::query!("INSERT INTO artist_track (artist_id, track_id, ordinal, role) VALUES ($1, $2, 0, $3)", artist_id, track_id, role)
+                .execute(&db.pool).await.unwrap();
+        }
+
+        let artist = list_artist_id(&db.pool, &artist_id.to_string(), None).await.unwrap().unwrap();
+        let roles: Vec<&str> = artist.credits.iter().map(|credit| credit.role.as_str()).collect();
+        assert_eq!(roles, vec![ARTIST_ROLE, "composer"]);
+        for credit in artist.credits.iter() {
+            assert_eq!(credit.tracks.len(), 1);
+            let artists: Vec<&str> = credit.tracks[0].artists.iter().map(|artist| artist.name.as_str()).collect();
+            assert_eq!(artists, vec!["Singer"], "{} credit", credit.role);
+        }
+        db.drop_db().await;
+    }
+}
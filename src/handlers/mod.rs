@@ -1,9 +1,23 @@
+use lazy_static::lazy_static;
+
 pub mod reload;
 pub mod list;
 pub mod tag_parser;
+pub mod watcher;
+pub mod dry_run;
+pub mod scheduler;
+pub mod exclude;
+pub mod art_gc;
+pub mod check;
+pub mod bulk_load;
+pub mod scan_history;
 
-// constant vector of recognized extensions
-pub const RECOGNIZED_EXTENSIONS: &[&str] = &["mp3", "flac", "m4a"];
+// extensions of every format tag_parser can read
+lazy_static! {
+    pub static ref RECOGNIZED_EXTENSIONS: Vec<&'static str> = {
+        tag_parser::format::FORMAT_PARSERS.iter().flat_map(|parser| parser.extensions()).copied().collect()
+    };
+}
 
 // constant vector of images extensions
 pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif"];
\ No newline at end of file
@@ -1,111 +1,473 @@
 use std::{
     fs::{remove_dir_all, create_dir},
-    path::Path,
-    collections::HashMap
+    path::{Path, PathBuf, Component},
+    collections::{HashMap, BTreeMap},
+    sync::Arc,
+    future::Future,
+    fmt,
 };
 use axum::{
     http::StatusCode,
-    extract::{Extension}
+    response::Json,
+    extract::{Extension, Query, Path as UrlPath},
 };
 use tower::BoxError;
 use sqlx::{
-    postgres::PgPool,
+    Executor, Postgres,
+    postgres::{PgPool, PgConnection},
     types::time::PrimitiveDateTime
 };
-use walkdir::WalkDir;
+use tokio::{
+    sync::{
+        Mutex,
+        mpsc::{channel, Sender},
+    },
+    task::JoinHandle,
+};
 
 use crate::{
-    utils::{SharedState, AlbumCache, Config},
+    utils::{SharedState, AlbumCache, Config, Library, ReloadStatus, ReloadPhase, ScanError, ScanErrorPage, ScanTrigger, ScanCounts, internal_error},
     handlers::{
-        RECOGNIZED_EXTENSIONS, 
-        tag_parser::{TrackInfo, parse_tag}
+        exclude::{ExcludeFilter, walk_audio_files},
+        art_gc::collect_art_garbage,
+        scan_history::{start_scan_run, finish_scan_run, page_params},
+        bulk_load::{BulkLoader, BULK_BATCH_SIZE},
+        tag_parser::{TrackInfo, ParsedTrack, parse_tag, parse_fingerprint, store_art, modified_time}
     },
 };
 
-// reload_handler for loading database metadata from music directory
+// how many of the latest jobs keep their errors in the db, older errors are deleted once a job ends
+const KEPT_ERROR_JOBS: i64 = 50;
+
+// tracks whose file disappeared during a scan, by audio fingerprint, along with their old path
+// they are matched against new files so that moved tracks keep their id
+pub type MissingTracks = HashMap<Vec<u8>, Vec<(i32, String)>>;
+
+// what happened to a file found during a scan
+#[derive(Debug, PartialEq)]
+pub enum AddResult {
+    AlreadyExists,
+    Added,
+    Moved,
+}
+
+// error a job returns when it stopped early because it was cancelled
+#[derive(Debug)]
+pub struct ReloadCancelled;
+impl fmt::Display for ReloadCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reload job was cancelled")
+    }
+}
+impl std::error::Error for ReloadCancelled {}
+
+// what a reload is limited to, either a single library or everything
+// a scope is relative to the library's root, so it only comes with a library
+#[derive(Clone, Debug, Default)]
+pub struct ReloadTarget {
+    pub library: Option<Library>,
+    pub scope: Option<PathBuf>,
+}
+impl ReloadTarget {
+    // libraries the reload goes through
+    pub fn libraries<'a>(&'a self, config: &'a Config) -> Vec<&'a Library> {
+        match &self.library {
+            Some(library) => vec![library],
+            None => config.libraries.iter().collect(),
+        }
+    }
+
+    // whether the reload leaves some of the tracks alone
+    pub fn is_partial(&self, config: &Config) -> bool {
+        self.scope.is_some() || (self.library.is_some() && config.libraries.len() > 1)
+    }
+}
+
+// reload_handler for loading database metadata from the library roots
+// ?library=name only reloads that library, and ?path=Artist/Album only the tracks at or below that path
 pub async fn reload_handler(
     Extension(pool): Extension<PgPool>,
     Extension(config): Extension<Config>,
-    Extension(state): Extension<SharedState>
-) -> Result<(), (StatusCode, String)> {
+    Extension(state): Extension<SharedState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ReloadStatus>, (StatusCode, String)> {
+    let target = parse_target(&config, params.get("library").map(String::as_str), params.get("path").map(String::as_str))?;
+    let partial = target.is_partial(&config);
+
     // start reload only if one isn't already running
-    {
-        let state_read = state.read().await;
+    let job_id = start_job(&pool, &state, ScanTrigger::Manual, false, false).await?;
+    set_job_target(&state, job_id, &target).await;
 
-        if state_read.reload_running {
-            return Err((StatusCode::SERVICE_UNAVAILABLE, "A Reload task is already running".to_string()));
-        }
+    // if function did not early return start reloading in separate thread
+    spawn_job(pool.clone(), state.clone(), job_id, run_job(pool, config, state.clone(), job_id, false, target));
+
+    // outdate the cache, a partial reload outdates the albums it touches as it goes
+    if !partial {
+        state.write().await.album_cache = AlbumCache {
+            list_album_cache_outdated: true,
+            list_album_cache: None,
+        };
+        state.write().await.album_id_cache = HashMap::new();
     }
 
-    // update state to say a reload is running
-    state.write().await.reload_running = true;
+    job_status(&state, job_id).await
+}
+
+// same as above but with wiping the db beforehand
+pub async fn hard_reload_handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(config): Extension<Config>,
+    Extension(state): Extension<SharedState>
+) -> Result<Json<ReloadStatus>, (StatusCode, String)> {
+    // start reload only if one isn't already running
+    let job_id = start_job(&pool, &state, ScanTrigger::Hard, true, false).await?;
 
     // if function did not early return start reloading in separate thread
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-            load_db(pool.clone(), config.clone(), state_clone.clone()).await.expect("Panicked on load_db in reload");
-            // update state to say reload finished
-            state_clone.write().await.reload_running = false;
-        }
-    );
+    spawn_job(pool.clone(), state.clone(), job_id, run_job(pool, config, state.clone(), job_id, true, ReloadTarget::default()));
 
-    // outdate the cache
+    // recreate cache
     state.write().await.album_cache = AlbumCache {
         list_album_cache_outdated: true,
         list_album_cache: None,
     };
     state.write().await.album_id_cache = HashMap::new();
 
-    Ok(())
+    job_status(&state, job_id).await
 }
 
-// same as above but with wiping the db beforehand
-pub async fn hard_reload_handler(
-    Extension(pool): Extension<PgPool>,
-    Extension(config): Extension<Config>,
+// status of the latest reload job, or null if no reload has been started yet
+pub async fn reload_status_handler(
     Extension(state): Extension<SharedState>
-) -> Result<(), (StatusCode, String)> {
-    // start reload only if one isn't already running
-    {
-        let state_read = state.read().await;
+) -> Json<Option<ReloadStatus>> {
+    let state_read = state.read().await;
+    Json(state_read.latest_reload_job_id.and_then(|id| state_read.reload_jobs.get(&id).cloned()))
+}
+
+// status of a specific reload job
+pub async fn reload_job_handler(
+    Extension(state): Extension<SharedState>,
+    UrlPath(params): UrlPath<HashMap<String, String>>,
+) -> Result<Json<ReloadStatus>, (StatusCode, String)> {
+    let job_id = parse_job_id(&params)?;
+
+    job_status(&state, job_id).await
+}
+
+// errors collected across all reload jobs, most recent first
+// ?limit= sets the page size, and ?before=id only returns errors older than that one
+pub async fn reload_errors_handler(
+    Extension(pool): Extension<PgPool>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ScanErrorPage>, (StatusCode, String)> {
+    let (limit, before) = page_params(&params, "error")?;
 
-        if state_read.reload_running {
-            return Err((StatusCode::SERVICE_UNAVAILABLE, "A Reload task is already running".to_string()));
+    // one more than asked for, to know whether there is a next page
+    let mut errors = sqlx::query_as!(ScanError, r#"SELECT scan_error_id as id, job_id, path, phase, error,
+        EXTRACT(EPOCH FROM occurred_at)::bigint as "occurred_at!" FROM scan_error
+        WHERE ($1::integer IS NULL OR scan_error_id < $1)
+        ORDER BY scan_error_id DESC LIMIT $2"#, before, limit + 1)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| internal_error(e.into()))?;
+
+    let next = if errors.len() as i64 > limit {
+        errors.truncate(limit as usize);
+        errors.last().map(|error| error.id)
+    } else {
+        None
+    };
+
+    Ok(Json(ScanErrorPage { errors, next }))
+}
+
+// errors collected during a specific reload job
+pub async fn reload_job_errors_handler(
+    Extension(pool): Extension<PgPool>,
+    UrlPath(params): UrlPath<HashMap<String, String>>,
+) -> Result<Json<Vec<ScanError>>, (StatusCode, String)> {
+    let job_id = parse_job_id(&params)?;
+
+    let errors = sqlx::query_as!(ScanError, r#"SELECT scan_error_id as id, job_id, path, phase, error,
+        EXTRACT(EPOCH FROM occurred_at)::bigint as "occurred_at!" FROM scan_error
+        WHERE job_id = ($1)
+        ORDER BY scan_error_id"#, job_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| internal_error(e.into()))?;
+
+    Ok(Json(errors))
+}
+
+// ask a running job to stop
+// the job finishes the file it is on, cleans up, then ends in the cancelled phase
+pub async fn reload_cancel_handler(
+    Extension(state): Extension<SharedState>,
+    UrlPath(params): UrlPath<HashMap<String, String>>,
+) -> Result<Json<ReloadStatus>, (StatusCode, String)> {
+    let job_id = parse_job_id(&params)?;
+
+    match state.write().await.reload_jobs.get_mut(&job_id) {
+        Some(status) if status.end_time.is_some() =>
+            return Err((StatusCode::CONFLICT, format!("Reload job {} has already ended", job_id))),
+        Some(status) => status.cancel_requested = true,
+        None => return Err((StatusCode::NOT_FOUND, format!("No reload job with id {}", job_id))),
+    }
+
+    job_status(&state, job_id).await
+}
+
+// check a path given to a scoped reload, returning it relative to the library's root
+// only plain components are allowed so that it can't point outside of the root
+// none means the whole library
+pub fn parse_scope(path: &str) -> Result<Option<PathBuf>, (StatusCode, String)> {
+    let mut scope = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(c) => scope.push(c),
+            Component::CurDir => (),
+            _ => return Err((StatusCode::BAD_REQUEST, format!("Invalid path {}, must be relative to the library", path))),
         }
     }
 
+    Ok(if scope.as_os_str().is_empty() { None } else { Some(scope) })
+}
+
+// check the library and path a reload is limited to
+// a path needs a library to be relative to, unless there is only one
+pub fn parse_target(config: &Config, library: Option<&str>, path: Option<&str>) -> Result<ReloadTarget, (StatusCode, String)> {
+    let library = match library {
+        Some(name) => Some(config.library(name).cloned()
+            .ok_or((StatusCode::NOT_FOUND, format!("No library named {}", name)))?),
+        None => None,
+    };
+    let scope = match path {
+        Some(path) => parse_scope(path)?,
+        None => None,
+    };
+
+    let library = match (library, &scope, config.libraries.as_slice()) {
+        (None, Some(_), [only]) => Some(only.clone()),
+        (None, Some(_), _) => return Err((StatusCode::BAD_REQUEST, "A path needs a library when there are several".to_string())),
+        (library, _, _) => library,
+    };
+
+    Ok(ReloadTarget { library, scope })
+}
+
+// show what a job is limited to in its status
+pub async fn set_job_target(state: &SharedState, job_id: i32, target: &ReloadTarget) {
+    let library = target.library.as_ref().map(|l| l.name.clone());
+    let scope = target.scope.as_ref().map(|s| s.to_string_lossy().to_string());
+    update_job(state, job_id, |s| {
+        s.library = library;
+        s.scope = scope;
+    }).await;
+}
+
+pub fn parse_job_id(params: &HashMap<String, String>) -> Result<i32, (StatusCode, String)> {
+    let id = params.get("job_id").expect("key job_id not found in parameter");
+    id.parse::<i32>()
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid job id {}", id)))
+}
+
+// register a new reload job in the state then return its id
+// fails if a reload is already running, unless the job is a dry run since those don't write anything
+pub async fn start_job(
+    pool: &PgPool,
+    state: &SharedState,
+    trigger: ScanTrigger,
+    hard: bool,
+    dry_run: bool,
+) -> Result<i32, (StatusCode, String)> {
+    let already_running = || (StatusCode::SERVICE_UNAVAILABLE, "A Reload task is already running".to_string());
+    if state.read().await.reload_running && !dry_run {
+        return Err(already_running());
+    }
+
+    // the id is taken without holding the lock so that other requests don't wait on the db
+    // the check is made again once locked, a job refused then leaving a gap in the ids
+    let job_id = new_job_id(pool).await.map_err(|e| internal_error(e.into()))?;
+    let mut state_write = state.write().await;
+    if state_write.reload_running && !dry_run {
+        return Err(already_running());
+    }
+
     // update state to say a reload is running
-    state.write().await.reload_running = true;
+    if !dry_run {
+        state_write.reload_running = true;
+    }
+    state_write.latest_reload_job_id = Some(job_id);
+    state_write.reload_jobs.insert(job_id, ReloadStatus::new(job_id, trigger, hard, dry_run));
 
-    // if function did not early return start reloading in separate thread
-    let state_clone = state.clone();
+    Ok(job_id)
+}
+
+// job ids come from the db so that they stay unique across restarts
+// batches of watcher changes take one too, for their errors
+pub async fn new_job_id(pool: &PgPool) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT nextval('reload_job_id_seq')::integer as "job_id!""#)
+        .fetch_one(pool)
+        .await
+}
+
+// run the job in a separate task, returning a handle that resolves once the job's status is final
+// the running flag is always cleared afterwards, even if the job errored or panicked
+// jobs other than dry runs are kept in the scan history, so their target has to be set before this
+pub fn spawn_job<F>(pool: PgPool, state: SharedState, job_id: i32, job: F) -> JoinHandle<()>
+where
+    F: Future<Output = Result<(), BoxError>> + Send + 'static,
+{
     tokio::spawn(async move {
-            // silently ignore error here for now
-            clear_data(pool.clone(), config.clone(), state_clone.clone()).await.expect("Panicked on clear_data in hard_reload");
-            load_db(pool.clone(), config.clone(), state_clone.clone()).await.expect("Panicked on load_data in hard_reload");
+        let status = state.read().await.reload_jobs.get(&job_id).cloned();
+        let dry_run = matches!(&status, Some(s) if s.dry_run);
+        let scan_run_id = match status {
+            Some(status) if !status.dry_run =>
+                start_scan_run(&pool, status.trigger, Some(job_id), status.library.as_deref(), status.scope.as_deref()).await,
+            _ => None,
+        };
+
+        let result = tokio::spawn(job).await;
 
-            // update state to say reload finished
-            state_clone.write().await.reload_running = false;
+        // errors that made it here stopped the whole job, apart from a cancellation
+        let failure: Option<BoxError> = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(e) => Some(Box::new(e)),
+        };
+        let end_phase = match &failure {
+            None => ReloadPhase::Finished,
+            Some(e) if e.is::<ReloadCancelled>() => {
+                tracing::info!("Reload job {} cancelled", job_id);
+                ReloadPhase::Cancelled
+            },
+            Some(_) => ReloadPhase::Failed,
+        };
+        if let (Some(e), ReloadPhase::Failed) = (&failure, end_phase) {
+            tracing::error!("Reload job {} failed: {}", job_id, error_chain(e.as_ref()));
+            // dry runs stay out of the db
+            if !dry_run {
+                let phase = state.read().await.reload_jobs.get(&job_id).map_or(ReloadPhase::Queued, |s| s.phase);
+                record_scan_error(&pool, &state, job_id, None, phase, e.as_ref()).await;
+            }
         }
-    );
 
-    // recreate cache
-    state.write().await.album_cache = AlbumCache {
-        list_album_cache_outdated: true,
-        list_album_cache: None,
-    };
-    state.write().await.album_id_cache = HashMap::new();
+        update_job(&state, job_id, |s| s.finish(end_phase)).await;
+        // the run is recorded before the next reload can start, so the history stays in order
+        if let Some(scan_run_id) = scan_run_id {
+            let counts = state.read().await.reload_jobs.get(&job_id).map(ScanCounts::from).unwrap_or_default();
+            finish_scan_run(&pool, scan_run_id, end_phase.as_str(), counts).await;
+        }
+        if !dry_run {
+            prune_scan_errors(&pool).await;
+        }
 
-    Ok(())
+        // update state to say reload finished, forgetting the oldest finished jobs now that this one is recorded
+        let mut state = state.write().await;
+        if !dry_run {
+            state.reload_running = false;
+        }
+        state.prune_jobs();
+    })
+}
+
+pub async fn run_job(
+    pool: PgPool,
+    config: Config,
+    state: SharedState,
+    job_id: i32,
+    hard: bool,
+    target: ReloadTarget,
+) -> Result<(), BoxError> {
+    if hard {
+        // the wipe is all or nothing, so only stop before it starts
+        if job_cancelled(&state, job_id).await {
+            Err(ReloadCancelled)?;
+        }
+        clear_data(pool.clone(), config.clone(), state.clone(), job_id).await?;
+    }
+    load_db(pool, config, state, job_id, &target).await
+}
+
+// apply an update to a job's progress
+pub async fn update_job<F: FnOnce(&mut ReloadStatus)>(state: &SharedState, job_id: i32, f: F) {
+    if let Some(status) = state.write().await.reload_jobs.get_mut(&job_id) {
+        f(status);
+    }
+}
+
+// whether the job has been asked to stop
+pub async fn job_cancelled(state: &SharedState, job_id: i32) -> bool {
+    matches!(state.read().await.reload_jobs.get(&job_id), Some(s) if s.cancel_requested)
+}
+
+pub async fn job_status(state: &SharedState, job_id: i32) -> Result<Json<ReloadStatus>, (StatusCode, String)> {
+    match state.read().await.reload_jobs.get(&job_id) {
+        Some(status) => Ok(Json(status.clone())),
+        None => Err((StatusCode::NOT_FOUND, format!("No reload job with id {}", job_id))),
+    }
+}
+
+// persist an error for the job so the scan can carry on
+// failing to persist is only logged, since there is nowhere else to report it
+pub async fn record_scan_error(
+    pool: &PgPool,
+    state: &SharedState,
+    job_id: i32,
+    path: Option<&Path>,
+    phase: ReloadPhase,
+    err: &(dyn std::error::Error + Send + Sync + 'static),
+) {
+    let path_str = path.map(|p| p.to_string_lossy().to_string());
+    let chain = error_chain(err);
+    tracing::warn!("Reload job {} error on {:?}: {}", job_id, path_str, chain);
+
+    let inserted = sqlx::query!("INSERT INTO scan_error (job_id, path, phase, error) VALUES ($1, $2, $3, $4)",
+        job_id, path_str, phase.as_str(), chain)
+        .execute(pool)
+        .await;
+    if let Err(e) = inserted {
+        tracing::error!("Failed to record scan error for job {}: {}", job_id, e);
+    }
+
+    update_job(state, job_id, |s| s.errors += 1).await;
+}
+
+// delete the errors of all but the latest KEPT_ERROR_JOBS jobs that had any
+// only logged on failure, the errors are left for the next job to prune
+pub async fn prune_scan_errors(pool: &PgPool) {
+    let deleted = sqlx::query!("DELETE FROM scan_error WHERE job_id < \
+        (SELECT MIN(job_id) FROM (SELECT DISTINCT job_id FROM scan_error ORDER BY job_id DESC LIMIT $1) AS kept)",
+        KEPT_ERROR_JOBS)
+        .execute(pool)
+        .await;
+    match deleted {
+        Ok(deleted) if deleted.rows_affected() > 0 =>
+            tracing::info!("Deleted {} errors of old reload jobs", deleted.rows_affected()),
+        Ok(_) => (),
+        Err(e) => tracing::error!("Failed to delete the errors of old reload jobs: {}", e),
+    }
+}
+
+// format an error together with all of its sources
+fn error_chain(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(e) = source {
+        chain.push_str(&format!(": {}", e));
+        source = e.source();
+    }
+    chain
 }
 
 // wipe the database
-async fn clear_data(pool: PgPool, config: Config, state: SharedState) -> Result<(), BoxError> {
+async fn clear_data(pool: PgPool, config: Config, state: SharedState, job_id: i32) -> Result<(), BoxError> {
     // tables to clear from
     let tables = [
         "album_track",
         "artist_album",
         "artist_track",
+        "track_genre",
+        "genre",
         "album",
         "artist",
         "track",
@@ -114,13 +476,18 @@ async fn clear_data(pool: PgPool, config: Config, state: SharedState) -> Result<
         "artist_art",
         "album_art",
     ];
+    update_job(&state, job_id, |s| s.start_phase(ReloadPhase::Clear, Some(tables.len() as u64))).await;
 
     // iterate over tables then delete from them
+    // in a single transaction so a failure can't leave tracks pointing at deleted rows
+    let mut tx = pool.begin().await?;
     for table in tables.iter() {
         sqlx::query(format!("DELETE FROM {}", table).as_str())
-            .execute(&pool)
+            .execute(&mut tx)
             .await?;
+        update_job(&state, job_id, |s| s.tick()).await;
     };
+    tx.commit().await?;
 
     // delete art folder then recreate empty art folder
     remove_dir_all(&config.art_directory)?;
@@ -136,213 +503,784 @@ async fn clear_data(pool: PgPool, config: Config, state: SharedState) -> Result<
     Ok(())
 }
 
-// load database metadata from path
-async fn load_db(pool: PgPool, config: Config, state: SharedState) -> Result<(), BoxError> {    
-    update_old_metadata(&pool, &config, &state).await?;
-    load_new_metadata(&pool, &config, &state).await?;
+// load database metadata from the library roots
+// only the tracks of the target are looked at
+async fn load_db(pool: PgPool, config: Config, state: SharedState, job_id: i32, target: &ReloadTarget) -> Result<(), BoxError> {
+    // albums of tracks about to change or go away need their cache invalidated too
+    let partial = target.is_partial(&config);
+    let affected_albums = match &target.library {
+        Some(library) if partial => affected_album_ids(&pool, &library.name, target.scope.as_deref()).await?,
+        _ => Vec::new(),
+    };
+
+    let mut missing = update_old_metadata(&pool, &config, &state, job_id, target).await?;
+    if !job_cancelled(&state, job_id).await {
+        load_new_metadata(&pool, &config, &state, job_id, target, &mut missing).await?;
+    }
+
+    // tracks that weren't found anywhere else are really gone
+    // unless the job was cancelled, as they may have moved to a file that wasn't scanned
+    let cancelled = job_cancelled(&state, job_id).await;
+    if !cancelled {
+        for (track_id, _) in missing.into_values().flatten() {
+            delete_track(&pool, track_id).await?;
+            update_job(&state, job_id, |s| s.files_deleted += 1).await;
+        }
+    }
+    // every track is written in its own transaction, so only albums and artists may be left behind by a cancellation
+    delete_empty_albums_and_artists(&pool).await?;
+
+    // art of deleted tracks, albums and artists is left behind until now
+    if !cancelled {
+        update_job(&state, job_id, |s| s.start_phase(ReloadPhase::ArtGc, None)).await;
+        let report = collect_art_garbage(&pool, &config).await?;
+        state.write().await.art_gc_reports.insert(job_id, report);
+    }
 
     // recreate cache
-    state.write().await.album_cache = AlbumCache {
-        list_album_cache_outdated: true,
-        list_album_cache: None,
-    };
-    state.write().await.album_id_cache = HashMap::new();
+    if !partial {
+        state.write().await.album_cache = AlbumCache {
+            list_album_cache_outdated: true,
+            list_album_cache: None,
+        };
+        state.write().await.album_id_cache = HashMap::new();
+    }
+    for album_id in affected_albums.iter() {
+        invalidate_album_cache(&state, *album_id).await;
+    }
 
+    if cancelled {
+        Err(ReloadCancelled)?;
+    }
     Ok(())
 }
 
 // update old metadata from files that have been changed, or files that have been deleted
-async fn update_old_metadata(pool: &PgPool, config: &Config, state: &SharedState) -> Result<(), BoxError> {
+// tracks whose file is gone are returned instead of deleted, in case they were moved somewhere else in the library
+async fn update_old_metadata(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    job_id: i32,
+    target: &ReloadTarget,
+) -> Result<MissingTracks, BoxError> {
     // struct for interfacing with the database
     struct DBTrack {
         track_id: i32,
+        library: String,
         path: String,
         last_modified: PrimitiveDateTime,
+        fingerprint: Option<Vec<u8>>,
     }
 
-    // get all paths
-    let tracks = sqlx::query_as!(DBTrack, "SELECT path, track_id, last_modified FROM track")
+    // get all paths of the target's libraries, or the ones in scope
+    let libraries = target.libraries(config);
+    let library_names: Vec<String> = libraries.iter().map(|l| l.name.clone()).collect();
+    let (scope_str, dir_prefix) = scope_prefix(target.scope.as_deref());
+    let tracks = sqlx::query_as!(DBTrack, "SELECT path, library, track_id, last_modified, fingerprint FROM track \
+        WHERE library = ANY($1) AND ($2::text IS NULL OR path = ($2) OR starts_with(path, $3))",
+        &library_names, scope_str, dir_prefix)
         .fetch_all(pool)
         .await?;
+    update_job(state, job_id, |s| s.start_phase(ReloadPhase::UpdateOldMetadata, Some(tracks.len() as u64))).await;
 
     // iterate over paths, delete tracks that are invalid and update tracks with differing last modified date
+    let mut excludes = HashMap::new();
+    for library in libraries.iter() {
+        excludes.insert(library.name.as_str(), (*library, ExcludeFilter::new(config, library)?));
+    }
+    let mut missing = MissingTracks::new();
     for track in tracks.iter() {
+        if job_cancelled(state, job_id).await {
+            break;
+        }
+
+        // the query only returns tracks of these libraries
+        let (library, exclude) = match excludes.get_mut(track.library.as_str()) {
+            Some(e) => e,
+            None => continue,
+        };
+        let library = *library;
         let path = Path::new(&track.path);
-        let path_full = Path::new(&config.music_directory).join(path);
+        let path_full = Path::new(&library.path).join(path);
         let track_id = track.track_id;
         let last_modified = track.last_modified;
 
-        if !path_full.exists() {
-            // delete metadata if track no longer exists
+        if exclude.is_excluded(path, false) {
+            // tracks that have been excluded since they were loaded are dropped from the library
             delete_track(pool, track_id).await?;
+            update_job(state, job_id, |s| s.files_deleted += 1).await;
+        }
+        else if !path_full.exists() {
+            match &track.fingerprint {
+                // hold on to the track in case it was moved
+                Some(fingerprint) => missing.entry(fingerprint.clone()).or_default().push((track_id, track.path.clone())),
+                // can't be recognized elsewhere, so delete metadata since track no longer exists
+                None => {
+                    delete_track(pool, track_id).await?;
+                    update_job(state, job_id, |s| s.files_deleted += 1).await;
+                },
+            }
         } 
         else {
-            let new_modified = PrimitiveDateTime::from(path_full.metadata()?.modified()?);
-            if last_modified < new_modified {
-                // update metadata if track's modified time is later
-                delete_track(pool, track_id).await?;
-                add_track_from_path(pool, config, state, path).await?;
+            // errors on a single file are recorded and the file is skipped
+            match update_track(pool, config, state, library, track_id, last_modified, path).await {
+                Ok(true) => update_job(state, job_id, |s| s.files_updated += 1).await,
+                // unchanged tracks stored before fingerprints get theirs now, so that they can be recognized once moved
+                Ok(false) if track.fingerprint.is_none() => {
+                    if let Err(e) = backfill_fingerprint(pool, library, track_id, path).await {
+                        record_scan_error(pool, state, job_id, Some(path), ReloadPhase::UpdateOldMetadata, e.as_ref()).await;
+                    }
+                },
+                Ok(false) => (),
+                Err(e) => record_scan_error(pool, state, job_id, Some(path), ReloadPhase::UpdateOldMetadata, e.as_ref()).await,
             }
         }
+        update_job(state, job_id, |s| s.tick()).await;
     };
 
-    // get all empty albums and artists
-    let empty_albums = sqlx::query_scalar!("SELECT DISTINCT album.album_id AS album_id 
-        FROM album LEFT OUTER JOIN album_track ON album.album_id = album_track.album_id
-        WHERE album_track.track_id IS NULL")
+    // recreate cache
+    if !target.is_partial(config) {
+        state.write().await.album_cache = AlbumCache {
+            list_album_cache_outdated: true,
+            list_album_cache: None,
+        };
+        state.write().await.album_id_cache = HashMap::new();
+    }
+
+    Ok(missing)
+}
+
+// store the fingerprint of a track that has none, without parsing its tags again
+pub async fn backfill_fingerprint(pool: &PgPool, library: &Library, track_id: i32, path: &Path) -> Result<(), BoxError> {
+    let fingerprint = parse_fingerprint(library, path).await?;
+    sqlx::query!("UPDATE track SET fingerprint = ($2) WHERE track_id = ($1)", track_id, fingerprint)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// path of a scope as stored in the db, along with the prefix of the paths below it
+pub fn scope_prefix(scope: Option<&Path>) -> (Option<String>, Option<String>) {
+    let scope_str = scope.map(|s| s.to_string_lossy().to_string());
+    let dir_prefix = scope_str.as_ref().map(|s| format!("{}/", s));
+    (scope_str, dir_prefix)
+}
+
+// albums of the tracks of a library at or below path, which is relative to the library's root and can be either a file or a directory
+// the whole library if path is none
+pub async fn affected_album_ids(pool: &PgPool, library_name: &str, path: Option<&Path>) -> Result<Vec<i32>, BoxError> {
+    let (path_str, dir_prefix) = scope_prefix(path);
+    let album_ids = sqlx::query_scalar!("SELECT DISTINCT album_id FROM album_track \
+        JOIN track ON (track.track_id = album_track.track_id) \
+        WHERE library = ($1) AND ($2::text IS NULL OR path = ($2) OR starts_with(path, $3))",
+        library_name, path_str, dir_prefix)
         .fetch_all(pool)
         .await?;
 
-    let empty_artists = sqlx::query_scalar!("SELECT DISTINCT album.album_id AS album_id 
+    Ok(album_ids)
+}
+
+// delete albums and artists left without any track after tracks have been deleted
+// genres without any track go too
+// returns the number of albums and artists deleted
+pub async fn delete_empty_albums_and_artists(pool: &PgPool) -> Result<(usize, usize), BoxError> {
+    // get all empty albums and artists
+    let empty_albums = sqlx::query_scalar!("SELECT DISTINCT album.album_id AS album_id 
         FROM album LEFT OUTER JOIN album_track ON album.album_id = album_track.album_id
         WHERE album_track.track_id IS NULL")
         .fetch_all(pool)
         .await?;
 
-    // delete albums/artists with 0 tracks
+    // delete albums with 0 tracks
     for album_id in empty_albums.iter() {
         delete_album(pool, *album_id).await?;
     }
 
+    // artists are only empty once their albums are gone, since album artists may have no tracks of their own
+    let empty_artists = sqlx::query_scalar!("SELECT DISTINCT artist.artist_id AS artist_id 
+        FROM artist LEFT OUTER JOIN artist_track ON artist.artist_id = artist_track.artist_id
+        LEFT OUTER JOIN artist_album ON artist.artist_id = artist_album.artist_id
+        WHERE artist_track.track_id IS NULL AND artist_album.album_id IS NULL")
+        .fetch_all(pool)
+        .await?;
+
     for artist_id in empty_artists.iter() {
         delete_artist(pool, *artist_id).await?;
     }
 
+    sqlx::query!("DELETE FROM genre WHERE NOT EXISTS (SELECT 1 FROM track_genre WHERE track_genre.genre_id = genre.genre_id)")
+        .execute(pool)
+        .await?;
+
+    Ok((empty_albums.len(), empty_artists.len()))
+}
+
+// load new metadata from the target's libraries
+// runs as a pipeline: a producer walks the libraries, workers parse tags on blocking threads,
+// and a single writer adds the parsed tracks to the db
+// the writer adds tracks in the order they were found, so the result is the same as scanning one file at a time
+// when the db starts out empty, the writer adds tracks in batches instead
+async fn load_new_metadata(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    job_id: i32,
+    target: &ReloadTarget,
+    missing: &mut MissingTracks,
+) -> Result<(), BoxError> {
+    // total is only known once the producer is done walking
+    update_job(state, job_id, |s| s.start_phase(ReloadPhase::LoadNewMetadata, None)).await;
+
+    // nothing to look up in an empty db, so tracks can skip the existence check and be written in batches
+    let mut bulk = match config.bulk_load && BulkLoader::db_is_empty(pool).await? {
+        true => Some((BulkLoader::default(), Vec::with_capacity(BULK_BATCH_SIZE))),
+        false => None,
+    };
+    let skip_existing_check = bulk.is_some();
+
+    let workers = scan_worker_count(config);
+    let (path_tx, path_rx) = channel::<(u64, Library, PathBuf)>(workers * 2);
+    let (result_tx, mut result_rx) = channel::<(u64, PathBuf, Result<Option<ParsedTrack>, BoxError>)>(workers * 2);
+
+    // producer
+    let producer_config = config.clone();
+    let producer_target = target.clone();
+    let producer_state = state.clone();
+    let producer = tokio::spawn(async move {
+        let total = tokio::task::spawn_blocking(move || walk_libraries(&producer_config, &producer_target, path_tx)).await??;
+        update_job(&producer_state, job_id, |s| s.phase_total = Some(total)).await;
+        Ok::<(), BoxError>(())
+    });
+
+    // workers. they share the receiving end of the path channel
+    let path_rx = Arc::new(Mutex::new(path_rx));
+    for _ in 0..workers {
+        let pool = pool.clone();
+        let path_rx = path_rx.clone();
+        let result_tx = result_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let next = path_rx.lock().await.recv().await;
+                let (index, library, path) = match next {
+                    Some(n) => n,
+                    None => break,
+                };
+                let result = match skip_existing_check {
+                    true => parse_tag(&library, &path).await.map(Some),
+                    false => read_new_track(&pool, &library, &path).await,
+                };
+                if result_tx.send((index, path, result)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    // results channel closes once every worker is done, and the path channel once every worker has stopped
+    drop(result_tx);
+    drop(path_rx);
+
+    // writer. results that arrive ahead of their turn wait in pending
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    while let Some((index, path, result)) = result_rx.recv().await {
+        pending.insert(index, (path, result));
+
+        // stopping the writer drops the receiver, which stops the workers and then the producer
+        if job_cancelled(state, job_id).await {
+            break;
+        }
+
+        while let Some((path, result)) = pending.remove(&next_index) {
+            next_index += 1;
+
+            if let Some((loader, batch)) = &mut bulk {
+                match result {
+                    Ok(Some(parsed)) => batch.push(parsed),
+                    Ok(None) => (),
+                    Err(e) => record_scan_error(pool, state, job_id, Some(&path), ReloadPhase::LoadNewMetadata, e.as_ref()).await,
+                }
+                update_job(state, job_id, |s| {
+                    s.files_seen += 1;
+                    s.tick();
+                }).await;
+                if batch.len() >= BULK_BATCH_SIZE {
+                    write_bulk(pool, config, state, job_id, loader, batch).await?;
+                }
+                continue;
+            }
+
+            // errors on a single file are recorded and the file is skipped
+            let added = match write_new_track(pool, config, state, result, missing).await {
+                Ok(added) => added,
+                Err(e) => {
+                    record_scan_error(pool, state, job_id, Some(&path), ReloadPhase::LoadNewMetadata, e.as_ref()).await;
+                    AddResult::AlreadyExists
+                },
+            };
+            update_job(state, job_id, |s| {
+                s.files_seen += 1;
+                match added {
+                    AddResult::Added => s.files_added += 1,
+                    AddResult::Moved => s.files_moved += 1,
+                    AddResult::AlreadyExists => (),
+                }
+                s.tick();
+            }).await;
+        }
+    }
+    drop(result_rx);
+    producer.await??;
+
+    // tracks parsed before a cancellation are still written
+    if let Some((loader, batch)) = &mut bulk {
+        write_bulk(pool, config, state, job_id, loader, batch).await?;
+    }
+
     // recreate cache
+    if !target.is_partial(config) {
+        state.write().await.album_cache = AlbumCache {
+            list_album_cache_outdated: true,
+            list_album_cache: None,
+        };
+        state.write().await.album_id_cache = HashMap::new();
+    }
+
+    Ok(())
+}
+
+// write a batch of parsed tracks then empty it
+// if the batch can't be written as a whole, its tracks are written one at a time so that only the failing ones are skipped
+async fn write_bulk(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    job_id: i32,
+    loader: &mut BulkLoader,
+    batch: &mut Vec<ParsedTrack>,
+) -> Result<(), BoxError> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    match loader.insert_batch(pool, config, batch).await {
+        Ok(()) => {
+            let added = batch.len() as u64;
+            update_job(state, job_id, |s| s.files_added += added).await;
+            batch.clear();
+        },
+        Err(e) => {
+            tracing::warn!("Failed to write a batch of {} tracks, writing them one at a time: {}", batch.len(), e);
+            for parsed in batch.drain(..) {
+                let path = PathBuf::from(&parsed.info.path_str);
+                match insert_parsed_track(pool, config, parsed, None).await {
+                    Ok(_) => update_job(state, job_id, |s| s.files_added += 1).await,
+                    Err(e) => record_scan_error(pool, state, job_id, Some(&path), ReloadPhase::LoadNewMetadata, e.as_ref()).await,
+                }
+            }
+            loader.reload_ids(pool).await?;
+        },
+    }
+
+    // only new albums were added, so no single album was cached
     state.write().await.album_cache = AlbumCache {
         list_album_cache_outdated: true,
         list_album_cache: None,
     };
-    state.write().await.album_id_cache = HashMap::new();
 
     Ok(())
 }
 
-// load new metadata from given music directory path
-// basically recursively going down the directory then calling add_track_from_path on audio files
-async fn load_new_metadata(pool: &PgPool, config: &Config, state: &SharedState) -> Result<(), BoxError> {
-    // silently discards of errors
-    for dir in WalkDir::new(&config.music_directory).follow_links(true).into_iter().filter_map(|e| e.ok()) {
-        // only care if it has an extension
-        let extension = dir.path().extension();
-        if let Some(ext) = extension {
-            // if extension is recognized we add the music track
-            if RECOGNIZED_EXTENSIONS.iter().any(|i| i == &ext) {
-                add_track_from_path(
-                    pool, config, state,
-                    dir.path().strip_prefix(&config.music_directory)?,
-                ).await?;
-            };
-        };
-    };
+// number of tag parsing workers
+// each worker may hold a db connection, so leave one for the writer
+pub fn scan_worker_count(config: &Config) -> usize {
+    let max_workers = (config.max_db_connections as usize).saturating_sub(1).max(1);
+    config.scan_workers.clamp(1, max_workers)
+}
 
-    // recreate cache
-    state.write().await.album_cache = AlbumCache {
-        list_album_cache_outdated: true,
-        list_album_cache: None,
+// send the path of every audio file in the target's libraries, relative to its library's root, along with its library and position
+// returns the number of audio files found
+// excluded files are left out, and a scope that doesn't exist anymore has no files
+fn walk_libraries(config: &Config, target: &ReloadTarget, path_tx: Sender<(u64, Library, PathBuf)>) -> Result<u64, BoxError> {
+    let mut total = 0;
+
+    for library in target.libraries(config) {
+        for path in walk_audio_files(config, library, target.scope.as_deref())? {
+            if path_tx.blocking_send((total, library.clone(), path)).is_err() {
+                return Ok(total);
+            }
+            total += 1;
+        }
+    }
+
+    Ok(total)
+}
+
+// read the tags of a track that isn't in the db yet
+// returns none if the track is already in the db
+async fn read_new_track(pool: &PgPool, library: &Library, path: &Path) -> Result<Option<ParsedTrack>, BoxError> {
+    let already_exists = sqlx::query_scalar!("SELECT (track_id) FROM track WHERE library = ($1) AND path = ($2)",
+        library.name, &path.to_string_lossy())
+        .fetch_optional(pool)
+        .await?;
+    if already_exists.is_some() {
+        return Ok(None);
     };
-    state.write().await.album_id_cache = HashMap::new();
+
+    Ok(Some(parse_tag(library, path).await?))
+}
+
+// add a track read by a worker to the db
+async fn write_new_track(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    result: Result<Option<ParsedTrack>, BoxError>,
+    missing: &mut MissingTracks,
+) -> Result<AddResult, BoxError> {
+    match result? {
+        Some(parsed) => add_or_move_parsed_track(pool, config, state, parsed, missing).await,
+        None => Ok(AddResult::AlreadyExists),
+    }
+}
+
+// reparse a track if its file has been modified since it was last loaded
+// returns whether the track was updated
+pub async fn update_track(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    library: &Library,
+    track_id: i32,
+    last_modified: PrimitiveDateTime,
+    path: &Path,
+) -> Result<bool, BoxError> {
+    let new_modified = modified_time(&Path::new(&library.path).join(path))?;
+    if last_modified < new_modified {
+        // update metadata if track's modified time is later
+        reparse_track(pool, config, state, library, track_id, path).await?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+// replace a track's metadata with what its file holds now
+// old track is only deleted once the new one is known to insert fine
+pub async fn reparse_track(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    library: &Library,
+    track_id: i32,
+    path: &Path,
+) -> Result<(), BoxError> {
+    let parsed = parse_tag(library, path).await?;
+    let album_id = insert_parsed_track(pool, config, parsed, Some(track_id)).await?;
+    invalidate_album_cache(state, album_id).await;
 
     Ok(())
 }
 
 // given a path to a track, add the track's metadata to the database
 // if path's track already in the database, it's assumed the track is correct, so we skip it
-async fn add_track_from_path(pool: &PgPool, config: &Config, state: &SharedState, path: &Path) -> Result<(), BoxError> {
+// if the track is one of the missing tracks, it is moved to its new path instead
+pub async fn add_track_from_path(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    library: &Library,
+    path: &Path,
+    missing: &mut MissingTracks,
+) -> Result<AddResult, BoxError> {
     // check if track is already in database
     // procesing 
-    let already_exists = sqlx::query_scalar!("SELECT (track_id) FROM track WHERE path = ($1)",
-        &path.to_string_lossy())
+    let already_exists = sqlx::query_scalar!("SELECT (track_id) FROM track WHERE library = ($1) AND path = ($2)",
+        library.name, &path.to_string_lossy())
         .fetch_optional(pool)
         .await?;
     if already_exists.is_some() {
-        return Ok(()); // early return
+        return Ok(AddResult::AlreadyExists); // early return
     };
 
     // parse track's tag then add based on info
-    add_track_from_info(pool, parse_tag(pool, config, path).await?).await?;
+    add_or_move_parsed_track(pool, config, state, parse_tag(library, path).await?, missing).await
+}
 
-    // recreate cache
-    state.write().await.album_cache = AlbumCache {
-        list_album_cache_outdated: true,
-        list_album_cache: None,
+// add a parsed track to the db, or point a missing track with the same audio to its new path
+async fn add_or_move_parsed_track(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    parsed: ParsedTrack,
+    missing: &mut MissingTracks,
+) -> Result<AddResult, BoxError> {
+    let moved_track_id = parsed.info.fingerprint.as_ref()
+        .and_then(|fingerprint| take_missing(missing, fingerprint, &parsed.info.path_str));
+
+    let (album_ids, result) = match moved_track_id {
+        Some(track_id) => (move_track(pool, config, track_id, parsed).await?, AddResult::Moved),
+        None => (vec![insert_parsed_track(pool, config, parsed, None).await?], AddResult::Added),
     };
-    state.write().await.album_id_cache = HashMap::new();
-    Ok(())
+
+    // outdate the cache of the albums the track was added to or moved from
+    for album_id in album_ids {
+        invalidate_album_cache(state, album_id).await;
+    }
+    Ok(result)
 }
 
-// given all track's information, add the track to the db
-async fn add_track_from_info(pool: &PgPool, track_info: TrackInfo) -> Result<(), BoxError> {
+// remove then return a missing track with the given fingerprint
+// identical audio can appear more than once, in which case a track with the same file name is preferred
+pub fn take_missing(missing: &mut MissingTracks, fingerprint: &[u8], path_str: &str) -> Option<i32> {
+    let tracks = missing.get_mut(fingerprint)?;
+    let file_name = Path::new(path_str).file_name();
+    let index = tracks.iter().position(|(_, old_path)| Path::new(old_path).file_name() == file_name).unwrap_or(0);
+    let (track_id, _) = tracks.swap_remove(index);
+    if tracks.is_empty() {
+        missing.remove(fingerprint);
+    }
+
+    Some(track_id)
+}
+
+// point an existing track to a new path, keeping its id
+// the file may have been retagged on the way, so the track's metadata is replaced with what it holds now
+// the new path may be in another library
+// returns the ids of the albums the track was on and is now on
+async fn move_track(pool: &PgPool, config: &Config, track_id: i32, parsed: ParsedTrack) -> Result<Vec<i32>, BoxError> {
+    let mut tx = pool.begin().await?;
+
+    let mut album_ids = sqlx::query_scalar!("SELECT album_id FROM album_track WHERE track_id = ($1)", track_id)
+        .fetch_all(&mut tx)
+        .await?;
+    let (track_info, new_art) = store_art(&mut tx, config, parsed).await?;
+    let clean = clean_track_names(&track_info);
+    sqlx::query!("UPDATE track SET track_name = ($1), library = ($2), path = ($3), last_modified = ($4), length_seconds = ($5), \
+        fingerprint = ($6), recording_date = ($7), release_date = ($8) WHERE track_id = ($9)",
+        clean.track_name,
+        track_info.library,
+        track_info.path_str,
+        track_info.last_modified,
+        track_info.length_seconds as i32,
+        track_info.fingerprint,
+        track_info.recording_date,
+        track_info.release_date,
+        track_id)
+        .execute(&mut tx)
+        .await?;
+
+    // links are made again from the new tags. albums and artists left without a track are deleted with the others later on
+    sqlx::query!("DELETE FROM artist_track WHERE track_id = ($1)", track_id).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM album_track WHERE track_id = ($1)", track_id).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM track_genre WHERE track_id = ($1)", track_id).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM track_art WHERE track_id = ($1)", track_id).execute(&mut tx).await?;
+    album_ids.push(link_track(&mut tx, track_id, &track_info, &clean, &config.genre_separators).await?);
+
+    tx.commit().await?;
+    if let Some(new_art) = new_art {
+        new_art.write()?;
+    }
+    Ok(album_ids)
+}
+
+// add a parsed track along with its art in a single transaction, so a failure leaves no partial rows behind
+// nor art files
+// if replaced_track_id is given, that track is deleted in the same transaction
+// returns the id of the album the track was added to
+async fn insert_parsed_track(pool: &PgPool, config: &Config, parsed: ParsedTrack, replaced_track_id: Option<i32>) -> Result<i32, BoxError> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(track_id) = replaced_track_id {
+        delete_track(&mut tx, track_id).await?;
+    }
+    let (track_info, new_art) = store_art(&mut tx, config, parsed).await?;
+    let album_id = add_track_from_info(&mut tx, track_info, &config.genre_separators).await?;
+
+    tx.commit().await?;
+    if let Some(new_art) = new_art {
+        new_art.write()?;
+    }
+    Ok(album_id)
+}
+
+// names of a track as they are stored in the db
+pub struct CleanNames {
+    pub track_name: String,
+    // artists are distinct and in the order the tags list them, never empty
+    pub artist_names: Vec<String>,
+    pub album_artist_names: Vec<String>,
+    pub album_name: String,
+    // everyone else credited on the track by role, roles and names in the order the tags list them
+    pub credits: Vec<(String, Vec<String>)>,
+}
+
+// role of the artists a track is credited to in its artist tag
+// other credits, composers and the like, are stored alongside them under their own role
+pub const ARTIST_ROLE: &str = "artist";
+
+// clean up the names read from a track's tags before they are stored
+pub fn clean_track_names(track_info: &TrackInfo) -> CleanNames {
     // trim null characters from texts
     let clean_track_name = &(track_info.track_name.replace(char::from(0), ""));
-    let clean_artist_name_temp = &(track_info.artist_name.replace(char::from(0), ""));
-    let clean_album_artist_name_temp = &(track_info.album_artist_name.replace(char::from(0), ""));
     let clean_album_name = &(track_info.album_name.replace(char::from(0), ""));
+    let clean_artist_names_temp = clean_artist_names(&track_info.artist_names);
+    let clean_album_artist_names_temp = clean_artist_names(&track_info.album_artist_names);
 
-    // in the case either artist_name or album_artist_name is empty, go with the other one
-    let clean_artist_name;
-    let clean_album_artist_name;
-    if clean_artist_name_temp == "Unknown Artist" {
-        clean_artist_name = clean_album_artist_name_temp;
-        clean_album_artist_name = clean_album_artist_name_temp;
+    // in the case either the artists or the album artists are empty, go with the other ones
+    // the first artist stands for all of them as album artist
+    let unknown = |names: &[String]| names.is_empty() || names == ["Unknown Artist"];
+    let (clean_artist_names, clean_album_artist_names) = if unknown(&clean_artist_names_temp) {
+        let names = match clean_album_artist_names_temp.is_empty() {
+            true => vec![String::from("Unknown Artist")],
+            false => clean_album_artist_names_temp,
+        };
+        (names.clone(), names)
+    } else if unknown(&clean_album_artist_names_temp) {
+        let first = clean_artist_names_temp[0].clone();
+        (clean_artist_names_temp, vec![first])
     } else {
-        clean_artist_name = clean_artist_name_temp;
-        if clean_album_artist_name_temp == "Unknown Artist" {
-            clean_album_artist_name = clean_artist_name;
-        } else {
-            clean_album_artist_name = clean_album_artist_name_temp;
+        (clean_artist_names_temp, clean_album_artist_names_temp)
+    };
+
+    CleanNames {
+        track_name: clean_track_name.to_string(),
+        artist_names: clean_artist_names,
+        album_artist_names: clean_album_artist_names,
+        album_name: clean_album_name.to_string(),
+        credits: clean_credits(&track_info.credits),
+    }
+}
+
+// credits grouped by role, without the artist role which the artist tag alone fills
+fn clean_credits(credits: &[(String, String)]) -> Vec<(String, Vec<String>)> {
+    let mut clean: Vec<(String, Vec<String>)> = Vec::new();
+    for (role, name) in credits.iter() {
+        let role = role.trim();
+        if role.is_empty() || role == ARTIST_ROLE {
+            continue;
+        }
+        match clean.iter_mut().find(|(r, _)| r == role) {
+            Some((_, names)) => names.push(name.clone()),
+            None => clean.push((role.to_string(), vec![name.clone()])),
+        }
+    }
+    clean.into_iter()
+        .map(|(role, names)| (role, clean_artist_names(&names)))
+        .filter(|(_, names)| !names.is_empty())
+        .collect()
+}
+
+// genres of a track as they are stored in the db, distinct and in the order the tags list them
+// a genre tag holding several genres is split on null characters as well as on any of the separators
+pub fn clean_genres(track_info: &TrackInfo, separators: &[String]) -> Vec<String> {
+    let mut genres: Vec<String> = track_info.genres.iter()
+        .flat_map(|genre| genre.split(char::from(0)))
+        .map(str::to_string)
+        .collect();
+    for separator in separators.iter().filter(|separator| !separator.is_empty()) {
+        genres = genres.iter().flat_map(|genre| genre.split(separator.as_str())).map(str::to_string).collect();
+    }
+
+    let mut clean_genres: Vec<String> = Vec::new();
+    for genre in genres.iter().map(|genre| genre.trim()) {
+        if !genre.is_empty() && !clean_genres.iter().any(|g| g == genre) {
+            clean_genres.push(genre.to_string());
         }
     }
+    clean_genres
+}
+
+// null characters that are left in artist names separate several artists
+fn clean_artist_names(names: &[String]) -> Vec<String> {
+    let mut clean_names: Vec<String> = Vec::new();
+    for name in names.iter().flat_map(|name| name.split(char::from(0))) {
+        if !name.is_empty() && !clean_names.iter().any(|n| n == name) {
+            clean_names.push(name.to_string());
+        }
+    }
+    clean_names
+}
+
+// given all track's information, add the track to the db
+// returns the id of the album the track was added to
+async fn add_track_from_info(conn: &mut PgConnection, track_info: TrackInfo, genre_separators: &[String]) -> Result<i32, BoxError> {
+    let clean = clean_track_names(&track_info);
+    let clean_track_name = &clean.track_name;
 
     // insert track
-    let track_id = sqlx::query_scalar!("INSERT INTO track (track_name, path, last_modified, length_seconds) \
-        VALUES ($1, $2, $3, $4) RETURNING track_id",
+    let track_id = sqlx::query_scalar!("INSERT INTO track (track_name, library, path, last_modified, length_seconds, fingerprint, recording_date, release_date) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING track_id",
         clean_track_name,
+        track_info.library,
         track_info.path_str,
         track_info.last_modified,
-        track_info.length_seconds as i32)
-        .fetch_one(pool)
+        track_info.length_seconds as i32,
+        track_info.fingerprint,
+        track_info.recording_date,
+        track_info.release_date)
+        .fetch_one(&mut *conn)
         .await?;
 
+    link_track(conn, track_id, &track_info, &clean, genre_separators).await
+}
+
+// link a track with its art, artists, album and genres, adding the ones that aren't in the db yet
+// returns the id of the track's album
+async fn link_track(
+    conn: &mut PgConnection,
+    track_id: i32,
+    track_info: &TrackInfo,
+    clean: &CleanNames,
+    genre_separators: &[String],
+) -> Result<i32, BoxError> {
+    let clean_album_name = &clean.album_name;
+
     // connect art with track if track has art
     if let Some(curr_art_id) = track_info.art_id {
         sqlx::query!("INSERT INTO track_art (track_id, art_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
             track_id, curr_art_id)
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     };
     
-    // insert artist if artist not in database. there is an unique constraint on artist_name
-    let artist_id = insert_artist_from_name(pool, clean_artist_name).await?;
-
-    // update artisttrack table if not already in database
-    // track_id is unique in artist_track table
-    // in other words, each track should only have 1 artist tag associated with it
-    sqlx::query!("INSERT INTO artist_track (artist_id, track_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-        artist_id, track_id)
-        .execute(pool)
-        .await?;
+    for (ordinal, clean_artist_name) in clean.artist_names.iter().enumerate() {
+        // insert artist if artist not in database. there is an unique constraint on artist_name
+        let artist_id = insert_artist_from_name(conn, clean_artist_name).await?;
 
-    // update artistart table if not already in database
-    // similarly, artist_id is unique in artist_art table
-    if let Some(curr_art_id) = track_info.art_id {
-        sqlx::query!("INSERT INTO artist_art (artist_id, art_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-            artist_id, curr_art_id)
-            .execute(pool)
+        // link the artist with the track, in the position its tag lists it at
+        sqlx::query!("INSERT INTO artist_track (artist_id, track_id, ordinal, role) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+            artist_id, track_id, ordinal as i32, ARTIST_ROLE)
+            .execute(&mut *conn)
             .await?;
-    };
+
+        // update artistart table if not already in database
+        // artist_id is unique in artist_art table
+        if let Some(curr_art_id) = track_info.art_id {
+            sqlx::query!("INSERT INTO artist_art (artist_id, art_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                artist_id, curr_art_id)
+                .execute(&mut *conn)
+                .await?;
+        };
+    }
         
     // insert album. note that album with same name by different artists should be treated
     // as different albums
-    // first we get an album_id where both album_name and artist_id matches what we have
-    // insert album artist first in case album artist isn't already in artist
-    let album_artist_id = insert_artist_from_name(pool, clean_album_artist_name).await?;
+    // first we get an album_id where both album_name and all of the album artists match what we have
+    // album artists match in any order, so that tracks listing them differently stay on the same album
+    // insert album artists first in case they aren't already in artist
+    let mut album_artist_ids = Vec::with_capacity(clean.album_artist_names.len());
+    for clean_album_artist_name in clean.album_artist_names.iter() {
+        album_artist_ids.push(insert_artist_from_name(conn, clean_album_artist_name).await?);
+    }
+    let mut sorted_album_artist_ids = album_artist_ids.clone();
+    sorted_album_artist_ids.sort_unstable();
     let album_id_with_same_name = sqlx::query_scalar!("SELECT (album.album_id) FROM album \
-        JOIN artist_album ON (album.album_id = artist_album.album_id) \
-        WHERE album_name = ($1) AND artist_id = ($2)",
+        WHERE album_name = ($1) \
+        AND ARRAY(SELECT artist_id FROM artist_album WHERE artist_album.album_id = album.album_id ORDER BY artist_id) = ($2)",
         clean_album_name,
-        album_artist_id)
-        .fetch_optional(pool)
+        &sorted_album_artist_ids)
+        .fetch_optional(&mut *conn)
         .await?;
     
     // if album_id exists, it's the same album as our current track's
@@ -354,52 +1292,89 @@ async fn add_track_from_info(pool: &PgPool, track_info: TrackInfo) -> Result<(),
             album_id = a;
         },
         None => {
-            // no album exists with both the same name and the same album artist
+            // no album exists with both the same name and the same album artists
             // so this album should be separate from others
             album_id = sqlx::query_scalar!("INSERT INTO album (album_name) VALUES ($1) RETURNING album_id",
                 clean_album_name)
-                .fetch_one(pool)
+                .fetch_one(&mut *conn)
                 .await?;
 
-            // insert into artist_album table
-            sqlx::query!("INSERT INTO artist_album (artist_id, album_id) VALUES ($1, $2)",
-                album_artist_id, album_id)
-                .execute(pool)
-                .await?;
+            // insert into artist_album table, in the order the tags of the album's first track list them
+            for (ordinal, album_artist_id) in album_artist_ids.iter().enumerate() {
+                sqlx::query!("INSERT INTO artist_album (artist_id, album_id, ordinal) VALUES ($1, $2, $3)",
+                    album_artist_id, album_id, ordinal as i32)
+                    .execute(&mut *conn)
+                    .await?;
+            }
         },
     };
 
+    // link the track with everyone else it credits, under their role
+    for (role, names) in clean.credits.iter() {
+        for (ordinal, name) in names.iter().enumerate() {
+            let artist_id = insert_artist_from_name(conn, name).await?;
+            sqlx::query!("INSERT INTO artist_track (artist_id, track_id, ordinal, role) VALUES ($1, $2, $3, $4)",
+                artist_id, track_id, ordinal as i32, role)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+
     // insert into the album_track table
     sqlx::query!("INSERT INTO album_track (album_id, track_id, track_no, disc_no) VALUES ($1, $2, $3, $4)",
         album_id, track_id, track_info.track_number as i32, track_info.disc_number as i32)
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
     // insert into album_art table
     if let Some(curr_art_id) = track_info.art_id {
         sqlx::query!("INSERT INTO album_art (album_id, art_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
             album_id, curr_art_id)
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     };
 
-    Ok(())
+    // link the track with its genres, in the order its tags list them
+    for (ordinal, genre_name) in clean_genres(track_info, genre_separators).iter().enumerate() {
+        let genre_id = insert_genre_from_name(conn, genre_name).await?;
+        sqlx::query!("INSERT INTO track_genre (genre_id, track_id, ordinal) VALUES ($1, $2, $3)",
+            genre_id, track_id, ordinal as i32)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(album_id)
+}
+
+// given a genre name, either insert the genre into the db or return the id of the pre-existing entry
+async fn insert_genre_from_name(conn: &mut PgConnection, name: &str) -> Result<i32, BoxError> {
+    let genre_id = sqlx::query_scalar!("INSERT INTO genre (genre_name) VALUES ($1) \
+        ON CONFLICT DO NOTHING RETURNING genre_id",
+        name)
+        .fetch_optional(&mut *conn)
+        .await?;
+    match genre_id {
+        Some(id) => Ok(id),
+        None => Ok(sqlx::query_scalar!("SELECT genre_id FROM genre WHERE genre_name = ($1)", name)
+            .fetch_one(&mut *conn)
+            .await?),
+    }
 }
 
 // given an artist name, either insert the artist into the db or return the id of the pre-existing entry
-async fn insert_artist_from_name(pool: &PgPool, name: &str) -> Result<i32, BoxError> {
+async fn insert_artist_from_name(conn: &mut PgConnection, name: &str) -> Result<i32, BoxError> {
     let artist_id: i32;
     let artist_id_optional = sqlx::query_scalar!("INSERT INTO artist (artist_name) VALUES ($1) \
         ON CONFLICT DO NOTHING RETURNING artist_id",
         name)
-        .fetch_optional(pool)
+        .fetch_optional(&mut *conn)
         .await?;
     match artist_id_optional {
         Some(id) => artist_id = id,
         None => {
             artist_id = sqlx::query_scalar!("SELECT (artist_id) FROM artist WHERE artist_name = ($1)",
                 name)
-                .fetch_one(pool)
+                .fetch_one(&mut *conn)
                 .await?;
         },
     };
@@ -407,11 +1382,21 @@ async fn insert_artist_from_name(pool: &PgPool, name: &str) -> Result<i32, BoxEr
     Ok(artist_id)
 }
 
+// outdate the album listing as well as the cached listing of a single album
+pub async fn invalidate_album_cache(state: &SharedState, album_id: i32) {
+    let mut state_write = state.write().await;
+    state_write.album_cache = AlbumCache {
+        list_album_cache_outdated: true,
+        list_album_cache: None,
+    };
+    state_write.album_id_cache.remove(&album_id.to_string());
+}
+
 // given a track id, remove the track's metadata from the database
-async fn delete_track(pool: &PgPool, track_id: i32) -> Result<(), BoxError> {
+pub async fn delete_track<'e, E: Executor<'e, Database = Postgres>>(executor: E, track_id: i32) -> Result<(), BoxError> {
     // delete the actual track record - should also delete other relations due to ON DELETE CASCADE
     sqlx::query!("DELETE FROM track WHERE track_id = ($1)", track_id)
-        .execute(pool)
+        .execute(executor)
         .await?;
     
     Ok(())
@@ -433,4 +1418,269 @@ async fn delete_artist(pool: &PgPool, artist_id: i32) -> Result<(), BoxError> {
         .await?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{TestDb, test_config, test_track, test_state, write_test_mp3};
+
+    // rows a track leaves behind in the tables linking to it
+    async fn assert_no_track_rows(db: &TestDb) {
+        for table in ["track", "artist_track", "album_track", "track_genre", "track_art", "album", "artist", "art"] {
+            assert_eq!(db.count(table).await, 0, "{} has rows left", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_insert_leaves_no_rows_nor_art_file() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), dir.path());
+
+        // album_track is written after the track, its artists, album and art
+        db.fail_inserts_into("album_track").await;
+        let result = insert_parsed_track(&db.pool, &config, test_track("a.mp3", "A", Some(b"cover")), None).await;
+
+        assert!(result.is_err());
+        assert_no_track_rows(&db).await;
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0, "art file written for a rolled back track");
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn failed_add_track_from_info_rolls_back() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+
+        // genres are linked last
+        db.fail_inserts_into("track_genre").await;
+        let mut tx = db.pool.begin().await.unwrap();
+        let result = add_track_from_info(&mut tx, test_track("a.mp3", "A", None).info, &[]).await;
+        assert!(result.is_err());
+        drop(tx);
+
+        assert_no_track_rows(&db).await;
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn failed_replace_keeps_old_track() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), dir.path());
+
+        insert_parsed_track(&db.pool, &config, test_track("a.mp3", "Old", Some(b"old cover")), None).await.unwrap();
+        let old_id = sqlx::query_scalar!("SELECT track_id FROM track").fetch_one(&db.pool).await.unwrap();
+        assert!(dir.path().join(blake3::hash(b"old cover").to_hex().as_str()).exists());
+
+        db.fail_inserts_into("album_track").await;
+        let result = insert_parsed_track(&db.pool, &config, test_track("a.mp3", "New", Some(b"new cover")), Some(old_id)).await;
+        assert!(result.is_err());
+
+        let track = sqlx::query!("SELECT track_id, track_name FROM track").fetch_all(&db.pool).await.unwrap();
+        assert_eq!(track.len(), 1);
+        assert_eq!((track[0].track_id, track[0].track_name.as_str()), (old_id, "Old"));
+        // two artists and a composer
+        assert_eq!(db.count("artist_track").await, 3);
+        assert_eq!(db.count("album_track").await, 1);
+        assert_eq!(db.count("art").await, 1);
+        assert!(!dir.path().join(blake3::hash(b"new cover").to_hex().as_str()).exists());
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn moved_tracks_take_their_new_tags() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), dir.path());
+        let state = test_state();
+
+        let old = test_track("a.mp3", "Old", None);
+        let fingerprint = old.info.fingerprint.clone().unwrap();
+        insert_parsed_track(&db.pool, &config, old, None).await.unwrap();
+        let track_id = sqlx::query_scalar!("SELECT track_id FROM track").fetch_one(&db.pool).await.unwrap();
+
+        // moved and retagged at once
+        let mut new = test_track("dir/b.mp3", "New", Some(b"cover"));
+        new.info.fingerprint = Some(fingerprint.clone());
+        new.info.album_name = "Other Album".to_string();
+        new.info.artist_names = vec!["Other Artist".to_string()];
+        new.info.genres = vec!["Jazz".to_string()];
+        let mut missing = MissingTracks::new();
+        missing.insert(fingerprint, vec![(track_id, "a.mp3".to_string())]);
+        let result = add_or_move_parsed_track(&db.pool, &config, &state, new, &mut missing).await.unwrap();
+        assert!(matches!(result, AddResult::Moved));
+        delete_empty_albums_and_artists(&db.pool).await.unwrap();
+
+        let track = sqlx::query!("SELECT track_id, track_name, path FROM track").fetch_one(&db.pool).await.unwrap();
+        assert_eq!((track.track_id, track.track_name.as_str(), track.path.as_str()), (track_id, "New", "dir/b.mp3"));
+        let album = sqlx::query_scalar!("SELECT album_name FROM album JOIN album_track USING (album_id) WHERE track_id = ($1)", track_id)
+            .fetch_all(&db.pool).await.unwrap();
+        assert_eq!(album, vec!["Other Album"]);
+        let artists = sqlx::query_scalar!("SELECT artist_name FROM artist JOIN artist_track USING (artist_id) \
+            WHERE track_id = ($1) AND role = 'artist'", track_id)
+            .fetch_all(&db.pool).await.unwrap();
+        assert_eq!(artists, vec!["Other Artist"]);
+        let genres = sqlx::query_scalar!("SELECT genre_name FROM genre JOIN track_genre USING (genre_id) WHERE track_id = ($1)", track_id)
+            .fetch_all(&db.pool).await.unwrap();
+        assert_eq!(genres, vec!["Jazz"]);
+        assert_eq!(db.count("track_art").await, 1);
+        assert!(dir.path().join(blake3::hash(b"cover").to_hex().as_str()).exists());
+        // the old album and artist are gone
+        assert_eq!(db.count("album").await, 1);
+        assert_eq!(db.count("genre").await, 1);
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn album_dates_follow_their_tracks() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), dir.path());
+        let album_dates = || async {
+            sqlx::query!(r#"SELECT recording_date, release_date FROM album_date"#)
+                .fetch_all(&db.pool)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|dates| (dates.recording_date, dates.release_date))
+                .collect::<Vec<_>>()
+        };
+        let dated = |path: &str, recording_date: &str, release_date: Option<&str>| {
+            let mut parsed = test_track(path, path, None);
+            parsed.info.recording_date = Some(recording_date.to_string());
+            parsed.info.release_date = release_date.map(str::to_string);
+            parsed
+        };
+
+        insert_parsed_track(&db.pool, &config, dated("a.mp3", "2001-05", None), None).await.unwrap();
+        insert_parsed_track(&db.pool, &config, dated("b.mp3", "1999", Some("2002")), None).await.unwrap();
+        assert_eq!(album_dates().await, vec![(Some("1999".to_string()), Some("2002".to_string()))]);
+
+        // retagged
+        let b_id = sqlx::query_scalar!("SELECT track_id FROM track WHERE path = 'b.mp3'").fetch_one(&db.pool).await.unwrap();
+        insert_parsed_track(&db.pool, &config, dated("b.mp3", "2003", None), Some(b_id)).await.unwrap();
+        assert_eq!(album_dates().await, vec![(Some("2001-05".to_string()), None)]);
+
+        let a_id = sqlx::query_scalar!("SELECT track_id FROM track WHERE path = 'a.mp3'").fetch_one(&db.pool).await.unwrap();
+        delete_track(&db.pool, a_id).await.unwrap();
+        assert_eq!(album_dates().await, vec![(Some("2003".to_string()), None)]);
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn scan_errors_are_paged_and_pruned() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        // two errors for each job, oldest job first
+        let jobs = KEPT_ERROR_JOBS as i32 + 5;
+        for job_id in 1..=jobs {
+            for n in 0..2 {
+                sqlx::query!("INSERT INTO scan_error (job_id, path, phase, error) VALUES ($1, $2, 'load_new_metadata', 'broken')",
+                    job_id, format!("{}-{}.mp3", job_id, n))
+                    .execute(&db.pool)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        prune_scan_errors(&db.pool).await;
+        assert_eq!(db.count("scan_error").await, KEPT_ERROR_JOBS * 2);
+
+        // pages of 30 until there is no next one
+        let mut paths = Vec::new();
+        let mut before: Option<i32> = None;
+        loop {
+            let mut params = HashMap::from([("limit".to_string(), "30".to_string())]);
+            if let Some(before) = before {
+                params.insert("before".to_string(), before.to_string());
+            }
+            let Json(page) = reload_errors_handler(Extension(db.pool.clone()), Query(params)).await.unwrap();
+            assert!(page.errors.len() <= 30);
+            paths.extend(page.errors.into_iter().map(|error| error.path.unwrap()));
+            before = match page.next {
+                Some(next) => Some(next),
+                None => break,
+            };
+        }
+        let expected: Vec<String> = (6..=jobs).rev()
+            .flat_map(|job_id| [format!("{}-1.mp3", job_id), format!("{}-0.mp3", job_id)])
+            .collect();
+        assert_eq!(paths, expected);
+
+        let params = HashMap::from([("limit".to_string(), "0".to_string())]);
+        assert!(reload_errors_handler(Extension(db.pool.clone()), Query(params)).await.is_err());
+        db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn tracks_without_fingerprints_keep_their_ids_once_moved() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let music = dir.path().join("music");
+        std::fs::create_dir_all(music.join("old")).unwrap();
+        std::fs::create_dir(dir.path().join("art")).unwrap();
+        let config = test_config(&music, &dir.path().join("art"));
+        let state = test_state();
+        let reload = || async {
+            let job_id = start_job(&db.pool, &state, ScanTrigger::Manual, false, false).await.unwrap();
+            let job = run_job(db.pool.clone(), config.clone(), state.clone(), job_id, false, ReloadTarget::default());
+            spawn_job(db.pool.clone(), state.clone(), job_id, job).await.unwrap();
+            assert_eq!(state.read().await.reload_jobs[&job_id].errors, 0);
+        };
+        let tracks = || async {
+            sqlx::query!("SELECT track_id, path FROM track ORDER BY path")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|track| (track.track_id, track.path))
+                .collect::<Vec<_>>()
+        };
+
+        write_test_mp3(&music.join("old/a.mp3"), "A", 1);
+        write_test_mp3(&music.join("old/b.mp3"), "B", 2);
+        reload().await;
+        // as loaded before tracks had fingerprints
+        sqlx::query!("UPDATE track SET fingerprint = NULL").execute(&db.pool).await.unwrap();
+        let before = tracks().await;
+
+        // nothing changed, but the fingerprints are filled in
+        reload().await;
+        let fingerprints = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM track WHERE fingerprint IS NOT NULL"#)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(fingerprints, 2);
+        assert_eq!(tracks().await, before);
+
+        std::fs::rename(music.join("old"), music.join("new")).unwrap();
+        reload().await;
+        let after = tracks().await;
+        assert_eq!(after, vec![
+            (before[0].0, "new/a.mp3".to_string()),
+            (before[1].0, "new/b.mp3".to_string()),
+        ]);
+        db.drop_db().await;
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use axum::{
+    http::StatusCode,
+    response::Json,
+    extract::{Extension, Query},
+};
+use sqlx::postgres::PgPool;
+
+use crate::utils::{ScanTrigger, ScanCounts, ScanRun, ScanRunPage, internal_error};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+// past scans, most recent first
+// ?limit= sets the page size, and ?before=id only returns scans older than that one
+pub async fn scans_handler(
+    Extension(pool): Extension<PgPool>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ScanRunPage>, (StatusCode, String)> {
+    let (limit, before) = page_params(&params, "scan")?;
+
+    // one more than asked for, to know whether there is a next page
+    let mut runs = sqlx::query_as!(ScanRun, r#"SELECT scan_run_id as id, job_id, trigger, status, library, scope,
+        EXTRACT(EPOCH FROM started_at)::bigint as "started_at!",
+        EXTRACT(EPOCH FROM finished_at)::bigint as finished_at,
+        files_added, files_updated, files_moved, files_deleted, errors FROM scan_run
+        WHERE ($1::integer IS NULL OR scan_run_id < $1)
+        ORDER BY scan_run_id DESC LIMIT $2"#, before, limit + 1)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| internal_error(e.into()))?;
+
+    let next = if runs.len() as i64 > limit {
+        runs.truncate(limit as usize);
+        runs.last().map(|run| run.id)
+    } else {
+        None
+    };
+
+    Ok(Json(ScanRunPage { runs, next }))
+}
+
+// the ?limit= and ?before= of a paged endpoint, what being what the ids are of
+pub fn page_params(
+    params: &HashMap<String, String>,
+    what: &str,
+) -> Result<(i64, Option<i32>), (StatusCode, String)> {
+    let limit = match params.get("limit") {
+        Some(limit) => match limit.parse::<i64>() {
+            Ok(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => limit,
+            _ => return Err((StatusCode::BAD_REQUEST, format!("Invalid limit {}, expected 1 to {}", limit, MAX_PAGE_SIZE))),
+        },
+        None => DEFAULT_PAGE_SIZE,
+    };
+    let before = match params.get("before") {
+        Some(before) => Some(before.parse::<i32>()
+            .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid {} id {}", what, before)))?),
+        None => None,
+    };
+    Ok((limit, before))
+}
+
+// record the start of a scan, returning the id of its run
+// the history is only informative, so failing to write to it is logged and the scan goes on
+pub async fn start_scan_run(
+    pool: &PgPool,
+    trigger: ScanTrigger,
+    job_id: Option<i32>,
+    library: Option<&str>,
+    scope: Option<&str>,
+) -> Option<i32> {
+    let inserted = sqlx::query_scalar!("INSERT INTO scan_run (job_id, trigger, status, library, scope) \
+        VALUES ($1, $2, 'running', $3, $4) RETURNING scan_run_id",
+        job_id, trigger.as_str(), library, scope)
+        .fetch_one(pool)
+        .await;
+    match inserted {
+        Ok(scan_run_id) => Some(scan_run_id),
+        Err(e) => {
+            tracing::error!("Failed to record the start of a {} scan: {}", trigger.as_str(), e);
+            None
+        },
+    }
+}
+
+// record how a scan ended, status being finished, failed or cancelled
+pub async fn finish_scan_run(pool: &PgPool, scan_run_id: i32, status: &str, counts: ScanCounts) {
+    let updated = sqlx::query!("UPDATE scan_run SET finished_at = now(), status = ($2), \
+        files_added = ($3), files_updated = ($4), files_moved = ($5), files_deleted = ($6), errors = ($7) \
+        WHERE scan_run_id = ($1)",
+        scan_run_id, status,
+        counts.files_added as i32, counts.files_updated as i32, counts.files_moved as i32,
+        counts.files_deleted as i32, counts.errors as i32)
+        .execute(pool)
+        .await;
+    if let Err(e) = updated {
+        tracing::error!("Failed to record the end of scan {}: {}", scan_run_id, e);
+    }
+}
+
+// scans still running at startup were stopped along with the previous server
+pub async fn mark_interrupted_scan_runs(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let updated = sqlx::query!("UPDATE scan_run SET status = 'interrupted' WHERE finished_at IS NULL AND status = 'running'")
+        .execute(pool)
+        .await?;
+    Ok(updated.rows_affected())
+}
@@ -0,0 +1,341 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use axum::{
+    response::Json,
+    extract::Extension,
+};
+use chrono::{DateTime, Utc};
+use tower::BoxError;
+use sqlx::postgres::PgPool;
+
+use crate::{
+    utils::{SharedState, Config, ReloadSchedule, ScheduleStatus, ScheduledRun, ReloadPhase, ScanTrigger},
+    handlers::reload::{start_job, spawn_job, run_job, parse_target, set_job_target},
+};
+
+// source of the current time, so that schedules can be run against a fake clock
+// the scheduler sleeps with tokio::time, so a fake clock should follow tokio's clock for tokio::time::pause to work
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// when a schedule's runs happen
+pub enum Trigger {
+    Cron(Box<cron::Schedule>),
+    Interval(chrono::Duration),
+}
+impl Trigger {
+    pub fn parse(schedule: &ReloadSchedule) -> Result<Trigger, BoxError> {
+        match (&schedule.cron, schedule.interval_seconds) {
+            (Some(expression), None) => Ok(Trigger::Cron(Box::new(cron::Schedule::from_str(expression)
+                .map_err(|e| format!("Invalid cron expression {}: {}", expression, e))?))),
+            (None, Some(0)) => Err("Reload schedule interval_seconds must be more than 0".into()),
+            (None, Some(seconds)) => Ok(Trigger::Interval(chrono::Duration::seconds(seconds as i64))),
+            _ => Err("Reload schedule needs exactly one of cron or interval_seconds".into()),
+        }
+    }
+
+    // first run strictly after the given time
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Trigger::Cron(schedule) => schedule.after(&after).next(),
+            Trigger::Interval(interval) => Some(after + *interval),
+        }
+    }
+}
+
+// start a task for every schedule in config
+// fails on an invalid schedule so that a typo doesn't go unnoticed
+pub async fn spawn_scheduler(pool: PgPool, config: Config, state: SharedState) -> Result<(), BoxError> {
+    spawn_scheduler_with_clock(pool, config, state, Arc::new(SystemClock)).await
+}
+
+pub async fn spawn_scheduler_with_clock(pool: PgPool, config: Config, state: SharedState, clock: Arc<dyn Clock>) -> Result<(), BoxError> {
+    let mut triggers = Vec::new();
+    for schedule in config.reload_schedules.iter() {
+        // a hard reload wipes every library first
+        if schedule.hard && (schedule.library.is_some() || schedule.path.is_some()) {
+            Err("Hard reload schedule can't be limited to a library or path")?;
+        }
+        parse_target(&config, schedule.library.as_deref(), schedule.path.as_deref()).map_err(|(_, e)| e)?;
+        triggers.push(Trigger::parse(schedule)?);
+    }
+
+    let now = clock.now();
+    state.write().await.schedules = config.reload_schedules.iter().zip(triggers.iter())
+        .map(|(schedule, trigger)| ScheduleStatus {
+            schedule: schedule.clone(),
+            next_run: trigger.next_after(now).map(|t| t.timestamp()),
+            last_run: None,
+        })
+        .collect();
+
+    for (index, trigger) in triggers.into_iter().enumerate() {
+        tracing::debug!("Scheduling reload {:?}", config.reload_schedules[index]);
+        tokio::spawn(run_schedule(pool.clone(), config.clone(), state.clone(), clock.clone(), index, trigger, now));
+    }
+
+    Ok(())
+}
+
+// status of every schedule, in the order they appear in config
+pub async fn schedules_handler(
+    Extension(state): Extension<SharedState>,
+) -> Json<Vec<ScheduleStatus>> {
+    Json(state.read().await.schedules.clone())
+}
+
+// wait for each run of a schedule then start it
+async fn run_schedule(
+    pool: PgPool,
+    config: Config,
+    state: SharedState,
+    clock: Arc<dyn Clock>,
+    index: usize,
+    trigger: Trigger,
+    started: DateTime<Utc>,
+) {
+    let schedule = &config.reload_schedules[index];
+    let mut next_run = trigger.next_after(started);
+
+    while let Some(run_time) = next_run {
+        // a negative duration means the run is already due
+        let wait = (run_time - clock.now()).to_std().unwrap_or(Duration::ZERO);
+        tokio::time::sleep(wait).await;
+
+        let last_run = run_scheduled(&pool, &config, &state, schedule, clock.now()).await;
+
+        // runs that were due while this one was going are skipped
+        let now = clock.now();
+        next_run = trigger.next_after(run_time).filter(|next| *next > now).or_else(|| trigger.next_after(now));
+        if let Some(status) = state.write().await.schedules.get_mut(index) {
+            status.next_run = next_run.map(|t| t.timestamp());
+            status.last_run = Some(last_run);
+        }
+    }
+}
+
+// start a reload then wait for it to end
+// skipped if another reload is already running
+async fn run_scheduled(pool: &PgPool, config: &Config, state: &SharedState, schedule: &ReloadSchedule, now: DateTime<Utc>) -> ScheduledRun {
+    let mut run = ScheduledRun {
+        time: now.timestamp(),
+        job_id: None,
+        phase: None,
+        skipped_reason: None,
+    };
+
+    // library and path were checked when the scheduler started
+    let target = parse_target(config, schedule.library.as_deref(), schedule.path.as_deref()).unwrap_or_default();
+
+    let job_id = match start_job(pool, state, ScanTrigger::Scheduled, schedule.hard, false).await {
+        Ok(job_id) => job_id,
+        Err((_, reason)) => {
+            tracing::info!("Skipped scheduled reload: {}", reason);
+            run.skipped_reason = Some(reason);
+            return run;
+        },
+    };
+    set_job_target(state, job_id, &target).await;
+    tracing::info!("Started scheduled reload job {}", job_id);
+
+    let job = run_job(pool.clone(), config.clone(), state.clone(), job_id, schedule.hard, target);
+    // spawn_job catches panics itself, so this only fails if the runtime is shutting down
+    let _ = spawn_job(pool.clone(), state.clone(), job_id, job).await;
+
+    run.job_id = Some(job_id);
+    run.phase = state.read().await.reload_jobs.get(&job_id).map(|s| s.phase);
+    if run.phase == Some(ReloadPhase::Failed) {
+        tracing::warn!("Scheduled reload job {} failed", job_id);
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use chrono::TimeZone;
+    use sqlx::postgres::PgPoolOptions;
+    use super::*;
+    use crate::utils::{
+        ReloadPhase,
+        testing::{TestDb, test_config, test_state},
+    };
+
+    // follows tokio's clock from start, plus however far it was moved forward
+    struct TestClock {
+        start: DateTime<Utc>,
+        origin: tokio::time::Instant,
+        skipped: Mutex<chrono::Duration>,
+    }
+    impl TestClock {
+        fn new(start: DateTime<Utc>) -> Arc<TestClock> {
+            Arc::new(TestClock { start, origin: tokio::time::Instant::now(), skipped: Mutex::new(chrono::Duration::zero()) })
+        }
+
+        // as when the machine was suspended, without tokio's timers noticing
+        fn skip(&self, duration: chrono::Duration) {
+            let mut skipped = self.skipped.lock().unwrap();
+            *skipped += duration;
+        }
+    }
+    impl Clock for TestClock {
+        fn now(&self) -> DateTime<Utc> {
+            let elapsed = chrono::Duration::from_std(tokio::time::Instant::now() - self.origin).unwrap();
+            self.start + elapsed + *self.skipped.lock().unwrap()
+        }
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 10, 7, 0).unwrap()
+    }
+
+    fn schedule(cron: Option<&str>, interval_seconds: Option<u64>) -> ReloadSchedule {
+        ReloadSchedule { cron: cron.map(str::to_string), interval_seconds, hard: false, library: None, path: None }
+    }
+
+    // config with the given schedules, over an empty library
+    fn config_with(dir: &tempfile::TempDir, schedules: Vec<ReloadSchedule>) -> Config {
+        let mut config = test_config(dir.path(), dir.path());
+        config.reload_schedules = schedules;
+        config
+    }
+
+    // a pool for schedules that never get to start a job
+    fn unused_pool() -> PgPool {
+        PgPoolOptions::new().connect_lazy("postgres://localhost/unused").unwrap()
+    }
+
+    async fn status(state: &SharedState, index: usize) -> ScheduleStatus {
+        state.read().await.schedules[index].clone()
+    }
+
+    async fn sleep_secs(seconds: u64) {
+        tokio::time::sleep(Duration::from_secs(seconds)).await;
+    }
+
+    #[test]
+    fn next_after_interval_and_cron() {
+        let interval = Trigger::parse(&schedule(None, Some(90))).unwrap();
+        assert_eq!(interval.next_after(start()), Some(start() + chrono::Duration::seconds(90)));
+
+        // every day at 4am
+        let cron = Trigger::parse(&schedule(Some("0 0 4 * * *"), None)).unwrap();
+        assert_eq!(cron.next_after(start()), Some(Utc.with_ymd_and_hms(2024, 1, 2, 4, 0, 0).unwrap()));
+        // strictly after
+        let four = Utc.with_ymd_and_hms(2024, 1, 1, 4, 0, 0).unwrap();
+        assert_eq!(cron.next_after(four - chrono::Duration::seconds(1)), Some(four));
+        assert_eq!(cron.next_after(four), Some(four + chrono::Duration::days(1)));
+
+        // cron expressions can run out of times
+        let past = Trigger::parse(&schedule(Some("0 0 4 1 1 * 2020"), None)).unwrap();
+        assert_eq!(past.next_after(start()), None);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_schedules() {
+        assert!(Trigger::parse(&schedule(None, None)).is_err());
+        assert!(Trigger::parse(&schedule(Some("0 0 4 * * *"), Some(60))).is_err());
+        assert!(Trigger::parse(&schedule(None, Some(0))).is_err());
+        assert!(Trigger::parse(&schedule(Some("every day"), None)).is_err());
+    }
+
+    #[tokio::test]
+    async fn runs_are_skipped_while_a_reload_is_running() {
+        tokio::time::pause();
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_with(&dir, vec![schedule(None, Some(60)), schedule(Some("0 */15 * * * *"), None)]);
+        let state = test_state();
+        state.write().await.reload_running = true;
+
+        spawn_scheduler_with_clock(unused_pool(), config, state.clone(), TestClock::new(start())).await.unwrap();
+        let interval = status(&state, 0).await;
+        assert_eq!(interval.next_run, Some(start().timestamp() + 60));
+        assert!(interval.last_run.is_none());
+        // 10:15
+        let cron = status(&state, 1).await;
+        assert_eq!(cron.next_run, Some(start().timestamp() + 8 * 60));
+
+        sleep_secs(61).await;
+        let interval = status(&state, 0).await;
+        let last_run = interval.last_run.unwrap();
+        assert_eq!(last_run.time, start().timestamp() + 60);
+        assert_eq!(last_run.job_id, None);
+        assert!(last_run.skipped_reason.is_some());
+        assert_eq!(interval.next_run, Some(start().timestamp() + 120));
+        assert!(status(&state, 1).await.last_run.is_none());
+
+        sleep_secs(8 * 60).await;
+        let interval = status(&state, 0).await;
+        assert_eq!(interval.last_run.unwrap().time, start().timestamp() + 9 * 60);
+        let cron = status(&state, 1).await;
+        let last_run = cron.last_run.unwrap();
+        assert_eq!(last_run.time, start().timestamp() + 8 * 60);
+        assert!(last_run.skipped_reason.is_some());
+        // 10:30
+        assert_eq!(cron.next_run, Some(start().timestamp() + 23 * 60));
+        assert!(state.read().await.reload_jobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn overdue_runs_are_skipped() {
+        tokio::time::pause();
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_with(&dir, vec![schedule(None, Some(60))]);
+        let state = test_state();
+        state.write().await.reload_running = true;
+        let clock = TestClock::new(start());
+
+        spawn_scheduler_with_clock(unused_pool(), config, state.clone(), clock.clone()).await.unwrap();
+        sleep_secs(30).await;
+        // the runs at 60, 120, 180 and 240 seconds all come due at once
+        clock.skip(chrono::Duration::seconds(200));
+        sleep_secs(31).await;
+
+        let status = status(&state, 0).await;
+        assert_eq!(status.last_run.unwrap().time, start().timestamp() + 260);
+        // the next one is a whole interval after that single run rather than right away
+        assert_eq!(status.next_run, Some(start().timestamp() + 320));
+    }
+
+    #[tokio::test]
+    async fn scheduled_runs_start_reload_jobs() {
+        let db = match TestDb::create().await {
+            Some(db) => db,
+            None => return,
+        };
+        tokio::time::pause();
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_with(&dir, vec![schedule(None, Some(60))]);
+        let state = test_state();
+
+        spawn_scheduler_with_clock(db.pool.clone(), config, state.clone(), TestClock::new(start())).await.unwrap();
+        sleep_secs(60).await;
+        // the job talks to the db, so wait without letting the paused clock move on
+        while status(&state, 0).await.last_run.is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        let status = status(&state, 0).await;
+        let last_run = status.last_run.unwrap();
+        assert_eq!(last_run.time, start().timestamp() + 60);
+        assert_eq!(last_run.phase, Some(ReloadPhase::Finished));
+        assert!(last_run.skipped_reason.is_none());
+        assert_eq!(status.next_run, Some(start().timestamp() + 120));
+        let state_read = state.read().await;
+        assert!(!state_read.reload_running);
+        assert_eq!(state_read.reload_jobs.get(&last_run.job_id.unwrap()).unwrap().trigger, ScanTrigger::Scheduled);
+        drop(state_read);
+        db.drop_db().await;
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+use tower::BoxError;
+
+use super::{
+    format::{FormatParser, ParsedFile, Tags, EmbeddedPicture, id3v2_size},
+    apev2::{read_apev2_tags, read_apev2_pictures, apev2_audio_ranges},
+};
+
+// the formats tagged with apev2, each with its own stream header to read the duration from
+
+pub struct ApeParser;
+
+impl FormatParser for ApeParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ape"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.starts_with(b"MAC ")
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        read_apev2_tags(file)
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        ape_length(&mut file.file)
+    }
+
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        read_apev2_pictures(file)
+    }
+
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        apev2_audio_ranges(file)
+    }
+}
+
+pub struct WavPackParser;
+
+impl FormatParser for WavPackParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wv"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.starts_with(b"wvpk")
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        read_apev2_tags(file)
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        wavpack_length(&mut file.file)
+    }
+
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        read_apev2_pictures(file)
+    }
+
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        apev2_audio_ranges(file)
+    }
+}
+
+pub struct MusepackParser;
+
+impl FormatParser for MusepackParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mpc"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.starts_with(b"MP+") || header.starts_with(b"MPCK")
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        read_apev2_tags(file)
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        musepack_length(&mut file.file)
+    }
+
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        read_apev2_pictures(file)
+    }
+
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        apev2_audio_ranges(file)
+    }
+}
+
+// first bytes of the file after any id3v2 tag, which some taggers put in front of the stream
+fn read_stream_start(file: &mut File, size: usize) -> Result<Vec<u8>, BoxError> {
+    let start = id3v2_size(file)?;
+
+    let mut data = Vec::with_capacity(size);
+    file.seek(SeekFrom::Start(start))?;
+    file.take(size as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn u16_le(data: &[u8], offset: usize) -> Result<u16, BoxError> {
+    Ok(u16::from_le_bytes(data.get(offset..offset + 2).ok_or("Stream header is too short")?.try_into()?))
+}
+
+fn u32_le(data: &[u8], offset: usize) -> Result<u32, BoxError> {
+    Ok(u32::from_le_bytes(data.get(offset..offset + 4).ok_or("Stream header is too short")?.try_into()?))
+}
+
+// monkey's audio, whose header layout changed with version 3.98
+fn ape_length(file: &mut File) -> Result<u64, BoxError> {
+    let data = read_stream_start(file, 128)?;
+    if !data.starts_with(b"MAC ") {
+        Err("Missing MAC marker")?;
+    }
+    let version = u16_le(&data, 4)?;
+
+    let (blocks_per_frame, final_frame_blocks, total_frames, sample_rate) = if version >= 3980 {
+        // a descriptor, then the header at the offset the descriptor gives
+        let header = u32_le(&data, 8)? as usize;
+        (u32_le(&data, header + 4)?, u32_le(&data, header + 8)?, u32_le(&data, header + 12)?, u32_le(&data, header + 20)?)
+    } else {
+        let compression = u16_le(&data, 6)?;
+        let blocks_per_frame = match version {
+            v if v >= 3950 => 73728 * 4,
+            v if v >= 3900 || (v >= 3800 && compression == 4000) => 73728,
+            _ => 9216,
+        };
+        (blocks_per_frame, u32_le(&data, 28)?, u32_le(&data, 24)?, u32_le(&data, 12)?)
+    };
+
+    if total_frames == 0 || sample_rate == 0 {
+        return Ok(0);
+    }
+    let samples = (total_frames as u64 - 1) * blocks_per_frame as u64 + final_frame_blocks as u64;
+    Ok(samples / sample_rate as u64)
+}
+
+// sample rates wavpack block flags can point to, the last index meaning a rate given elsewhere
+const WAVPACK_SAMPLE_RATES: [u32; 15] = [
+    6000, 8000, 9600, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000, 192000,
+];
+
+// wavpack, from the header of its first block
+fn wavpack_length(file: &mut File) -> Result<u64, BoxError> {
+    let data = read_stream_start(file, 32)?;
+    if !data.starts_with(b"wvpk") {
+        Err("Missing wvpk marker")?;
+    }
+
+    // all ones means the encoder didn't know the length
+    let total_low = u32_le(&data, 12)?;
+    if total_low == u32::MAX {
+        return Ok(0);
+    }
+    let total_samples = ((data[11] as u64) << 32) | total_low as u64;
+    let flags = u32_le(&data, 24)?;
+    match WAVPACK_SAMPLE_RATES.get(((flags >> 23) & 0xf) as usize) {
+        Some(rate) => Ok(total_samples / *rate as u64),
+        None => Ok(0),
+    }
+}
+
+// sample rates of musepack headers
+const MUSEPACK_SAMPLE_RATES: [u32; 4] = [44100, 48000, 37800, 32000];
+// samples in a musepack frame
+const MUSEPACK_FRAME_SAMPLES: u64 = 1152;
+
+// musepack, either stream version 7 or stream version 8
+fn musepack_length(file: &mut File) -> Result<u64, BoxError> {
+    let data = read_stream_start(file, 1024)?;
+
+    if data.starts_with(b"MP+") {
+        // sv7 counts frames
+        let frames = u32_le(&data, 4)? as u64;
+        let rate_index = ((u32_le(&data, 8)? >> 16) & 0b11) as usize;
+        let rate = MUSEPACK_SAMPLE_RATES.get(rate_index).ok_or("Unknown musepack sample rate")?;
+        let samples = frames.checked_mul(MUSEPACK_FRAME_SAMPLES).ok_or("Musepack frame count is too large")?;
+        return Ok(samples / *rate as u64);
+    }
+    if !data.starts_with(b"MPCK") {
+        Err("Missing musepack marker")?;
+    }
+
+    // sv8 is a list of packets, each a 2 letter key followed by its size, key and size included
+    let mut position = 4;
+    while position + 2 < data.len() {
+        let key = &data[position..position + 2];
+        let (size, size_len) = read_varint(&data[position + 2..])?;
+        if key == b"SH" {
+            // crc and version, then the sample count and the silence at the start
+            let mut offset = position + 2 + size_len + 5;
+            let (samples, len) = read_varint(data.get(offset..).ok_or("Stream header is too short")?)?;
+            offset += len;
+            let (silence, len) = read_varint(data.get(offset..).ok_or("Stream header is too short")?)?;
+            offset += len;
+            let rate_index = (*data.get(offset).ok_or("Stream header is too short")? >> 5) as usize;
+            let rate = MUSEPACK_SAMPLE_RATES.get(rate_index).ok_or("Unknown musepack sample rate")?;
+            return Ok(samples.saturating_sub(silence) / *rate as u64);
+        }
+        if size == 0 {
+            break;
+        }
+        position += size as usize;
+    }
+
+    Err("Missing musepack stream header")?
+}
+
+// 7 bits per byte, most significant first, the top bit saying another byte follows
+fn read_varint(data: &[u8]) -> Result<(u64, usize), BoxError> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate().take(9) {
+        value = (value << 7) | (*byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err("Invalid musepack size")?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    fn write(dir: &Path, name: &str, data: &[u8]) -> File {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        File::open(&path).unwrap()
+    }
+
+    fn put_u16(data: &mut [u8], offset: usize, value: u16) {
+        data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // a monkey's audio header from 3.98 on, a 52 byte descriptor followed by the header
+    fn ape_header(blocks_per_frame: u32, final_frame_blocks: u32, total_frames: u32, sample_rate: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 76];
+        data[0..4].copy_from_slice(b"MAC ");
+        put_u16(&mut data, 4, 3990);
+        put_u32(&mut data, 8, 52);
+        put_u32(&mut data, 52 + 4, blocks_per_frame);
+        put_u32(&mut data, 52 + 8, final_frame_blocks);
+        put_u32(&mut data, 52 + 12, total_frames);
+        put_u32(&mut data, 52 + 20, sample_rate);
+        data
+    }
+
+    // a monkey's audio header from before 3.98, whose frame size depends on the version
+    fn old_ape_header(version: u16, compression: u16, final_frame_blocks: u32, total_frames: u32, sample_rate: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 32];
+        data[0..4].copy_from_slice(b"MAC ");
+        put_u16(&mut data, 4, version);
+        put_u16(&mut data, 6, compression);
+        put_u32(&mut data, 12, sample_rate);
+        put_u32(&mut data, 24, total_frames);
+        put_u32(&mut data, 28, final_frame_blocks);
+        data
+    }
+
+    // the header of a wavpack block
+    fn wavpack_header(total_samples: u64, rate_index: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 32];
+        data[0..4].copy_from_slice(b"wvpk");
+        put_u16(&mut data, 8, 0x410);
+        data[11] = (total_samples >> 32) as u8;
+        put_u32(&mut data, 12, total_samples as u32);
+        put_u32(&mut data, 24, rate_index << 23);
+        data
+    }
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.insert(0, (value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes
+    }
+
+    // a musepack sv8 packet, its size counting its key and the size itself
+    fn sv8_packet(key: &[u8], body: &[u8]) -> Vec<u8> {
+        // sizes here always fit a single byte
+        let size = 2 + 1 + body.len();
+        [key, &varint(size as u64), body].concat()
+    }
+
+    // the body of a musepack sv8 stream header
+    fn sv8_stream_header(samples: u64, silence: u64, rate_index: u8) -> Vec<u8> {
+        [&[0u8; 4][..], &[8], &varint(samples), &varint(silence), &[rate_index << 5, 0]].concat()
+    }
+
+    #[test]
+    fn ape_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        // two full frames and one of 27576 blocks, 617400 samples
+        let mut file = write(dir.path(), "new.ape", &ape_header(73728 * 4, 27576, 3, 44100));
+        assert_eq!(ape_length(&mut file).unwrap(), 14);
+
+        // one full frame of 73728 * 4 blocks and one of 146088 blocks, 441000 samples
+        let mut file = write(dir.path(), "old.ape", &old_ape_header(3970, 2000, 146088, 2, 44100));
+        assert_eq!(ape_length(&mut file).unwrap(), 10);
+        // frames of 73728 blocks from 3.90, or from 3.80 at the extra high compression level
+        let mut file = write(dir.path(), "old.ape", &old_ape_header(3800, 4000, 73728, 6, 73728));
+        assert_eq!(ape_length(&mut file).unwrap(), 6);
+        // and of 9216 blocks before
+        let mut file = write(dir.path(), "old.ape", &old_ape_header(3800, 2000, 9216, 5, 9216));
+        assert_eq!(ape_length(&mut file).unwrap(), 5);
+
+        // no frames, or no sample rate
+        let mut file = write(dir.path(), "empty.ape", &ape_header(73728 * 4, 0, 0, 44100));
+        assert_eq!(ape_length(&mut file).unwrap(), 0);
+        let mut file = write(dir.path(), "empty.ape", &ape_header(73728 * 4, 100, 1, 0));
+        assert_eq!(ape_length(&mut file).unwrap(), 0);
+    }
+
+    #[test]
+    fn wavpack_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = write(dir.path(), "a.wv", &wavpack_header(44100 * 30, 9));
+        assert_eq!(wavpack_length(&mut file).unwrap(), 30);
+        // the sample count goes past 32 bits with its high byte
+        let mut file = write(dir.path(), "a.wv", &wavpack_header(192000 * 30000, 14));
+        assert_eq!(wavpack_length(&mut file).unwrap(), 30000);
+
+        // the encoder didn't know the length
+        let mut file = write(dir.path(), "a.wv", &wavpack_header(u32::MAX as u64, 9));
+        assert_eq!(wavpack_length(&mut file).unwrap(), 0);
+        // a sample rate given elsewhere than in the flags
+        let mut file = write(dir.path(), "a.wv", &wavpack_header(44100 * 30, 15));
+        assert_eq!(wavpack_length(&mut file).unwrap(), 0);
+    }
+
+    #[test]
+    fn musepack_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        // sv7, 1000 frames of 1152 samples at 48kHz
+        let mut data = vec![0u8; 16];
+        data[0..4].copy_from_slice(b"MP+\x17");
+        put_u32(&mut data, 4, 1000);
+        put_u32(&mut data, 8, 1 << 16);
+        let mut file = write(dir.path(), "sv7.mpc", &data);
+        assert_eq!(musepack_length(&mut file).unwrap(), 24);
+
+        // sv8, with the silence at the start left out and a packet before the stream header
+        let data = [
+            &b"MPCK"[..],
+            &sv8_packet(b"XX", &[1, 2, 3]),
+            &sv8_packet(b"SH", &sv8_stream_header(44100 * 20 + 1000, 1000, 0)),
+        ].concat();
+        let mut file = write(dir.path(), "sv8.mpc", &data);
+        assert_eq!(musepack_length(&mut file).unwrap(), 20);
+
+        // sv8 without a stream header
+        let data = [&b"MPCK"[..], &sv8_packet(b"XX", &[1, 2, 3])].concat();
+        let mut file = write(dir.path(), "sv8.mpc", &data);
+        assert!(musepack_length(&mut file).is_err());
+    }
+
+    #[test]
+    fn varints() {
+        assert_eq!(read_varint(&[0x05]).unwrap(), (5, 1));
+        assert_eq!(read_varint(&[0x81, 0x00, 0xff]).unwrap(), (128, 2));
+        assert_eq!(read_varint(&varint(44100 * 20)).unwrap(), (44100 * 20, 3));
+        // never ending, or longer than 9 bytes
+        assert!(read_varint(&[0x81]).is_err());
+        assert!(read_varint(&[0x80; 10]).is_err());
+        assert!(read_varint(&[]).is_err());
+    }
+
+    #[test]
+    fn truncated_headers_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = write(dir.path(), "a.ape", &ape_header(73728 * 4, 27576, 3, 44100)[..60]);
+        assert!(ape_length(&mut file).is_err());
+        let mut file = write(dir.path(), "a.ape", &old_ape_header(3970, 2000, 146088, 2, 44100)[..20]);
+        assert!(ape_length(&mut file).is_err());
+        let mut file = write(dir.path(), "a.wv", &wavpack_header(44100 * 30, 9)[..14]);
+        assert!(wavpack_length(&mut file).is_err());
+        let mut file = write(dir.path(), "a.mpc", b"MP+\x17\x00\x01");
+        assert!(musepack_length(&mut file).is_err());
+
+        let header = sv8_packet(b"SH", &sv8_stream_header(44100 * 20, 0, 0));
+        let data = [&b"MPCK"[..], &header[..header.len() - 3]].concat();
+        let mut file = write(dir.path(), "a.mpc", &data);
+        assert!(musepack_length(&mut file).is_err());
+    }
+
+    #[test]
+    fn stream_after_an_id3v2_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        // a tag of 20 bytes after its 10 byte header
+        let mut id3 = b"ID3\x04\x00\x00\x00\x00\x00\x14".to_vec();
+        id3.extend([0; 20]);
+
+        let mut file = write(dir.path(), "a.ape", &[&id3[..], &ape_header(73728 * 4, 27576, 3, 44100)].concat());
+        assert_eq!(ape_length(&mut file).unwrap(), 14);
+        let mut file = write(dir.path(), "a.wv", &[&id3[..], &wavpack_header(44100 * 30, 9)].concat());
+        assert_eq!(wavpack_length(&mut file).unwrap(), 30);
+    }
+}
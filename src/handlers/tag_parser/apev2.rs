@@ -0,0 +1,237 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    collections::HashMap,
+};
+use tower::BoxError;
+
+use super::{
+    format::{ParsedFile, SharedError, Tags, EmbeddedPicture, id3v2_size, untagged_range, parse_number},
+    credit::field_credits,
+};
+
+// apev2 tags, found at the end of monkey's audio, wavpack and musepack files
+// https://wiki.hydrogenaud.io/index.php?title=APEv2_specification
+
+const PREAMBLE: &[u8] = b"APETAGEX";
+const FOOTER_SIZE: u64 = 32;
+const ID3V1_SIZE: u64 = 128;
+// bit 31 of the flags says the tag also has a header, as long as the footer
+const HAS_HEADER: u32 = 1 << 31;
+// bits 1 and 2 of an item's flags say what its value holds
+const ITEM_TYPE_MASK: u32 = 0b110;
+const ITEM_TYPE_TEXT: u32 = 0;
+const ITEM_TYPE_BINARY: u32 = 0b010;
+
+#[derive(Debug, Default)]
+struct ApeTag {
+    // text items by lowercased key, multiple values being null separated
+    text: HashMap<String, Vec<String>>,
+    // binary items by lowercased key
+    binary: HashMap<String, Vec<u8>>,
+}
+
+// what the end of a file using apev2 holds
+#[derive(Debug)]
+struct ApeFile {
+    // none if the file has no apev2 tag
+    // a broken tag still leaves where the audio is
+    tag: Result<Option<ApeTag>, SharedError>,
+    audio: (u64, u64),
+}
+
+// tags of the formats using apev2, whose duration is read from their own stream header
+pub fn read_apev2_tags(file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+    match &file.parsed(read_apev2)?.tag {
+        Ok(tag) => Ok(tag.as_ref().map(apev2_tags)),
+        Err(e) => Err(e.clone())?,
+    }
+}
+
+pub fn read_apev2_pictures(file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+    match &file.parsed(read_apev2)?.tag {
+        Ok(tag) => Ok(tag.as_ref().map(apev2_pictures).unwrap_or_default()),
+        Err(e) => Err(e.clone())?,
+    }
+}
+
+// everything between the id3v2 tag and the apev2 tag, or the id3v1 tag if there is no apev2 tag
+pub fn apev2_audio_ranges(file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+    Ok(vec![file.parsed(read_apev2)?.audio])
+}
+
+// the footer is only looked for once, then the tag is read from where it says
+fn read_apev2(file: &mut File) -> Result<ApeFile, BoxError> {
+    match ape_tag_range(file)? {
+        Some(range) => {
+            let start = id3v2_size(file)?;
+            Ok(ApeFile {
+                tag: read_ape_tag(file, range).map(Some).map_err(SharedError::new),
+                audio: (start, range.0.max(start)),
+            })
+        },
+        None => Ok(ApeFile { tag: Ok(None), audio: untagged_range(file)? }),
+    }
+}
+
+fn apev2_tags(tag: &ApeTag) -> Tags {
+    // multiple values of the same item are joined as for flac
+    let joined = |key: &str| tag.text.get(key).map(|values| values.join(", "));
+    let values = |keys: &[&str]| keys.iter().find_map(|key| tag.text.get(*key)).cloned().unwrap_or_default();
+    let number = |key: &str| tag.text.get(key).and_then(|values| parse_number(values.first()?));
+    let first = |keys: &[&str]| keys.iter().find_map(|key| tag.text.get(*key)?.first().cloned());
+
+    Tags {
+        title: joined("title"),
+        artists: values(&["artist"]),
+        album: joined("album"),
+        album_artists: values(&["album artist", "albumartist"]),
+        genres: values(&["genre"]),
+        recording_date: first(&["year"]),
+        release_date: first(&["originaldate", "originalyear"]),
+        credits: field_credits(|field| values(&[field.to_lowercase().as_str()])),
+        track_number: number("track"),
+        disc_number: number("disc"),
+    }
+}
+
+// cover art items are a file name, a null, then the image data
+fn apev2_pictures(tag: &ApeTag) -> Vec<EmbeddedPicture> {
+    tag.binary.iter()
+        .filter(|(key, _)| key.starts_with("cover art"))
+        .filter_map(|(key, item)| {
+            let name_end = item.iter().position(|b| *b == 0)?;
+            Some(EmbeddedPicture { front_cover: key == "cover art (front)", data: item[name_end + 1..].to_vec() })
+        })
+        .collect()
+}
+
+// where the tag starts, header included, and where its footer ends
+// none if the file has no apev2 tag at its end, either right before an id3v1 tag or without one
+fn ape_tag_range(file: &mut File) -> Result<Option<(u64, u64)>, BoxError> {
+    let len = file.metadata()?.len();
+    for footer_end in [len, len.saturating_sub(ID3V1_SIZE)] {
+        if footer_end < FOOTER_SIZE {
+            continue;
+        }
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(footer_end - FOOTER_SIZE))?;
+        file.read_exact(&mut footer)?;
+        if &footer[0..8] != PREAMBLE {
+            continue;
+        }
+
+        // size counts the items and the footer, but not the header
+        let size = u32::from_le_bytes(footer[12..16].try_into()?) as u64;
+        if size < FOOTER_SIZE {
+            return Err(format!("Apev2 tag size {} is smaller than its footer", size).into());
+        }
+        let flags = u32::from_le_bytes(footer[20..24].try_into()?);
+        let header_size = if flags & HAS_HEADER != 0 { FOOTER_SIZE } else { 0 };
+        let start = footer_end.checked_sub(size + header_size).ok_or("Apev2 tag is larger than the file")?;
+        return Ok(Some((start, footer_end)));
+    }
+
+    Ok(None)
+}
+
+// read the apev2 tag found at the end of the file
+fn read_ape_tag(file: &mut File, (start, end): (u64, u64)) -> Result<ApeTag, BoxError> {
+    let mut data = vec![0u8; (end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut data)?;
+    let footer = &data[data.len() - FOOTER_SIZE as usize..];
+    let item_count = u32::from_le_bytes(footer[16..20].try_into()?);
+    let mut items = match data.starts_with(PREAMBLE) {
+        true => &data[FOOTER_SIZE as usize..data.len() - FOOTER_SIZE as usize],
+        false => &data[..data.len() - FOOTER_SIZE as usize],
+    };
+
+    // each item is its value size, its flags, a null terminated key, then its value
+    let mut tag = ApeTag::default();
+    for _ in 0..item_count {
+        if items.len() < 8 {
+            break;
+        }
+        let value_size = u32::from_le_bytes(items[0..4].try_into()?) as usize;
+        let flags = u32::from_le_bytes(items[4..8].try_into()?);
+        let key_end = 8 + items[8..].iter().position(|b| *b == 0).ok_or("Apev2 item key isn't terminated")?;
+        let key = String::from_utf8_lossy(&items[8..key_end]).to_lowercase();
+        let value = items.get(key_end + 1..key_end + 1 + value_size).ok_or("Apev2 item is larger than the tag")?;
+        items = &items[key_end + 1 + value_size..];
+
+        match flags & ITEM_TYPE_MASK {
+            ITEM_TYPE_TEXT => {
+                let values = String::from_utf8_lossy(value).split('\0').map(str::to_string).collect();
+                tag.text.insert(key, values);
+            },
+            ITEM_TYPE_BINARY => {
+                tag.binary.insert(key, value.to_vec());
+            },
+            // links to external files aren't followed
+            _ => (),
+        }
+    }
+
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a footer of a tag of size bytes, items and footer, holding item_count items
+    fn footer(size: u32, item_count: u32, flags: u32) -> Vec<u8> {
+        let mut footer = PREAMBLE.to_vec();
+        footer.extend(2000u32.to_le_bytes());
+        footer.extend(size.to_le_bytes());
+        footer.extend(item_count.to_le_bytes());
+        footer.extend(flags.to_le_bytes());
+        footer.extend([0; 8]);
+        footer
+    }
+
+    // a text item
+    fn item(key: &str, value: &str) -> Vec<u8> {
+        let mut item = (value.len() as u32).to_le_bytes().to_vec();
+        item.extend(ITEM_TYPE_TEXT.to_le_bytes());
+        item.extend(key.as_bytes());
+        item.push(0);
+        item.extend(value.as_bytes());
+        item
+    }
+
+    fn read(dir: &std::path::Path, name: &str, data: &[u8]) -> ParsedFile {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        ParsedFile::new(File::open(&path).unwrap())
+    }
+
+    #[test]
+    fn reads_text_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let items = [item("Title", "Song"), item("Artist", "One\0Two")].concat();
+        let mut data = vec![0; 64];
+        data.extend(&items);
+        data.extend(footer(items.len() as u32 + FOOTER_SIZE as u32, 2, 0));
+        let mut parsed = read(dir.path(), "a.ape", &data);
+
+        let tags = read_apev2_tags(&mut parsed).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artists, vec!["One", "Two"]);
+        assert_eq!(apev2_audio_ranges(&mut parsed).unwrap(), vec![(0, 64)]);
+    }
+
+    #[test]
+    fn footer_smaller_than_itself_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, flags) in [("no_header.ape", 0), ("header.ape", HAS_HEADER)] {
+            let mut data = vec![0; 64];
+            data.extend(footer(8, 1, flags));
+            let mut parsed = read(dir.path(), name, &data);
+
+            assert!(read_apev2_tags(&mut parsed).is_err());
+            assert!(apev2_audio_ranges(&mut parsed).is_err());
+        }
+    }
+}
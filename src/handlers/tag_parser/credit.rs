@@ -0,0 +1,49 @@
+// people credited on a track besides its artists, each under a role
+
+pub const COMPOSER: &str = "composer";
+pub const CONDUCTOR: &str = "conductor";
+pub const LYRICIST: &str = "lyricist";
+pub const ARRANGER: &str = "arranger";
+pub const PERFORMER: &str = "performer";
+
+// fields holding credits, as vorbis comments and apev2 tags name them, case aside
+pub const CREDIT_FIELDS: &[(&str, &str)] = &[
+    ("COMPOSER", COMPOSER),
+    ("CONDUCTOR", CONDUCTOR),
+    ("LYRICIST", LYRICIST),
+    ("ARRANGER", ARRANGER),
+    ("PERFORMER", PERFORMER),
+];
+
+// role of an id3 involved people list entry, "Mix" or "producer" are stored as they are, lowercased
+pub fn involvement_role(involvement: &str) -> String {
+    involvement.trim().to_lowercase()
+}
+
+// a performer field is the name followed by the instrument in parentheses, "Jane Doe (violin)"
+// only the name is kept
+pub fn performer_name(value: &str) -> &str {
+    let value = value.trim();
+    match value.strip_suffix(')').and_then(|rest| rest.rfind(" (")) {
+        Some(start) if start > 0 => &value[..start],
+        _ => value,
+    }
+}
+
+// credits of the fields of CREDIT_FIELDS, given how to get the values of a field
+pub fn field_credits<F>(values: F) -> Vec<(String, String)>
+where
+    F: Fn(&str) -> Vec<String>,
+{
+    let mut credits = Vec::new();
+    for (field, role) in CREDIT_FIELDS.iter() {
+        for value in values(field) {
+            let name = match *role {
+                PERFORMER => performer_name(&value).to_string(),
+                _ => value,
+            };
+            credits.push((role.to_string(), name));
+        }
+    }
+    credits
+}
@@ -0,0 +1,34 @@
+// dates as tags store them, from a year alone to a full timestamp
+// they are kept as partial dates, "2001", "2001-05" or "2001-05-17", which sort chronologically as text
+
+// a partial date of a tag value, none if it doesn't start with a year
+// the month and day are kept as long as they are valid, anything after them like a time is dropped
+// "-", "/" and "." all separate the parts, as taggers don't agree on one
+pub fn partial_date(value: &str) -> Option<String> {
+    let mut parts = value.trim().splitn(4, ['-', '/', '.', 'T', ' ']);
+
+    let year = parts.next()?;
+    if year.len() != 4 || !year.bytes().all(|b| b.is_ascii_digit()) || year == "0000" {
+        return None;
+    }
+    let mut date = year.to_string();
+
+    let month = match parts.next().and_then(|part| number_in(part, 1, 12)) {
+        Some(month) => month,
+        None => return Some(date),
+    };
+    date.push_str(&format!("-{:02}", month));
+
+    if let Some(day) = parts.next().and_then(|part| number_in(part, 1, 31)) {
+        date.push_str(&format!("-{:02}", day));
+    }
+    Some(date)
+}
+
+// a one or two digit number between min and max
+fn number_in(part: &str, min: u32, max: u32) -> Option<u32> {
+    if part.is_empty() || part.len() > 2 || !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    part.parse().ok().filter(|n| (min..=max).contains(n))
+}
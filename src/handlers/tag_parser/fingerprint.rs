@@ -0,0 +1,22 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use blake3;
+use tower::BoxError;
+
+use super::format::{FormatParser, ParsedFile};
+
+// hash of a file's audio data, leaving out tags so that retagging or moving a file doesn't change it
+// files whose parser can't find the audio data are hashed whole
+pub fn audio_fingerprint(parser: &dyn FormatParser, file: &mut ParsedFile) -> Result<Vec<u8>, BoxError> {
+    let ranges = parser.audio_ranges(file).ok().filter(|ranges| !ranges.is_empty());
+    let file = &mut file.file;
+    let len = file.metadata()?.len();
+    let ranges = ranges.unwrap_or_else(|| vec![(0, len)]);
+
+    let mut hasher = blake3::Hasher::new();
+    for (start, end) in ranges {
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut (&mut *file).take(end.saturating_sub(start)), &mut hasher)?;
+    }
+
+    Ok(hasher.finalize().as_bytes().to_vec())
+}
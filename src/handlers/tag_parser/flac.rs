@@ -0,0 +1,103 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+};
+use tower::BoxError;
+
+use super::{
+    format::{FormatParser, ParsedFile, Tags, EmbeddedPicture, id3v2_size, parse_number},
+    credit::field_credits,
+};
+
+pub struct FlacParser;
+
+impl FormatParser for FlacParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["flac"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.starts_with(b"fLaC")
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        Ok(flac_tags(file.parsed(read_flac)?))
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        Ok(flac_length(file.parsed(read_flac)?))
+    }
+
+    // exact same interface as id3 apparently for pictures
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        Ok(file.parsed(read_flac)?.pictures()
+            .map(|picture| EmbeddedPicture {
+                front_cover: picture.picture_type == metaflac::block::PictureType::CoverFront,
+                data: picture.data.to_vec(),
+            })
+            .collect())
+    }
+
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        flac_audio_ranges(&mut file.file)
+    }
+}
+
+fn read_flac(file: &mut File) -> Result<metaflac::Tag, BoxError> {
+    file.rewind()?;
+    Ok(metaflac::Tag::read_from(&mut BufReader::new(file))?)
+}
+
+fn flac_tags(tag: &metaflac::Tag) -> Option<Tags> {
+    let comment = tag.vorbis_comments()?;
+
+    // multiple values of the same field are joined
+    let first = |fields: &[&str]| fields.iter().find_map(|field| comment.get(field)?.first().cloned());
+    Some(Tags {
+        title: comment.title().map(|values| values.join(", ")),
+        artists: comment.artist().cloned().unwrap_or_default(),
+        album: comment.album().map(|values| values.join(", ")),
+        album_artists: comment.album_artist().cloned().unwrap_or_default(),
+        genres: comment.get("GENRE").cloned().unwrap_or_default(),
+        recording_date: first(&["DATE"]),
+        release_date: first(&["ORIGINALDATE", "ORIGINALYEAR"]),
+        credits: field_credits(|field| comment.get(field).cloned().unwrap_or_default()),
+        track_number: comment.track(),
+        disc_number: comment.comments.get("DISCNUMBER").and_then(|values| parse_number(values.first()?)),
+    })
+}
+
+fn flac_length(tag: &metaflac::Tag) -> u64 {
+    match tag.get_streaminfo() {
+        Some(streaminfo) if streaminfo.sample_rate > 0 => streaminfo.total_samples / streaminfo.sample_rate as u64,
+        _ => 0,
+    }
+}
+
+// everything after the last metadata block
+fn flac_audio_ranges(file: &mut File) -> Result<Vec<(u64, u64)>, BoxError> {
+    let len = file.metadata()?.len();
+
+    let mut position = id3v2_size(file)?;
+    let mut marker = [0u8; 4];
+    file.seek(SeekFrom::Start(position))?;
+    file.read_exact(&mut marker)?;
+    if &marker != b"fLaC" {
+        Err("Missing fLaC marker")?;
+    }
+    position += 4;
+
+    // each block header is 1 byte of last-block flag and type followed by a 3 byte length
+    loop {
+        let mut header = [0u8; 4];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let block_len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        position += 4 + block_len;
+        if header[0] & 0x80 != 0 || position >= len {
+            break;
+        }
+    }
+
+    Ok(vec![(position.min(len), len)])
+}
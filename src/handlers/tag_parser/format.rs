@@ -0,0 +1,199 @@
+use std::{
+    path::Path,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    any::Any,
+    error::Error,
+    fmt,
+    sync::Arc,
+};
+use tower::BoxError;
+
+use super::{
+    mp3::Mp3Parser,
+    flac::FlacParser,
+    mp4::Mp4Parser,
+    ogg::OggParser,
+    riff::RiffParser,
+    ape_streams::{ApeParser, WavPackParser, MusepackParser},
+};
+
+// every format parse_tag can read
+// content sniffing tries them in this order, so formats with a weak signature like mp3 come last
+pub static FORMAT_PARSERS: &[&dyn FormatParser] = &[
+    &FlacParser,
+    &Mp4Parser,
+    &OggParser,
+    &RiffParser,
+    &ApeParser,
+    &WavPackParser,
+    &MusepackParser,
+    &Mp3Parser,
+];
+
+// how many bytes of the file sniffing looks at
+const SNIFF_SIZE: u64 = 64;
+
+// tags as read from the file, before falling back to defaults for what is missing
+#[derive(Debug, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub album_artists: Vec<String>,
+    pub genres: Vec<String>,
+    // when the track was recorded and when it was first released, as the tags write them
+    pub recording_date: Option<String>,
+    pub release_date: Option<String>,
+    // role and name of everyone credited besides the artists, in the order the tags list them
+    pub credits: Vec<(String, String)>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+}
+
+// a picture embedded in the file
+#[derive(Debug)]
+pub struct EmbeddedPicture {
+    pub front_cover: bool,
+    pub data: Vec<u8>,
+}
+
+// a file being read, along with what its parser got from it
+// a parser parses the file the first time one of its methods needs it, the others reuse what it got
+pub struct ParsedFile {
+    pub file: File,
+    parsed: Option<Box<dyn Any + Send>>,
+}
+
+impl ParsedFile {
+    pub fn new(file: File) -> Self {
+        ParsedFile { file, parsed: None }
+    }
+
+    // what parse got from the file, parsing it on first use
+    // an error is kept as well, so that each method asking for it gets it without parsing again
+    pub fn parsed<T: Any + Send>(&mut self, parse: fn(&mut File) -> Result<T, BoxError>) -> Result<&T, BoxError> {
+        let cached = matches!(&self.parsed, Some(parsed) if parsed.is::<Result<T, SharedError>>());
+        if !cached {
+            self.parsed = Some(Box::new(parse(&mut self.file).map_err(SharedError::new)));
+        }
+        match self.parsed.as_ref().and_then(|parsed| parsed.downcast_ref::<Result<T, SharedError>>()) {
+            Some(Ok(parsed)) => Ok(parsed),
+            Some(Err(e)) => Err(e.clone().into()),
+            None => Err("Parsed file holds another type")?,
+        }
+    }
+}
+
+// an error handed out to every part of a file read from the same data
+// the original error is shared rather than copied, so its source chain is kept
+#[derive(Debug, Clone)]
+pub struct SharedError(Arc<BoxError>);
+
+impl SharedError {
+    pub fn new(e: BoxError) -> Self {
+        SharedError(Arc::new(e))
+    }
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for SharedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+// a file format along with how to read its tags, duration and pictures
+pub trait FormatParser: Sync {
+    // lowercase extensions of the format
+    fn extensions(&self) -> &'static [&'static str];
+
+    // whether the first bytes of the file, after any id3v2 tag, look like the format
+    fn sniff(&self, header: &[u8]) -> bool;
+
+    // none if the file has no tags at all
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError>;
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError>;
+
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError>;
+
+    // byte ranges of the audio data, leaving out tags, for the fingerprint
+    // empty if the format can't tell, in which case the whole file is used
+    fn audio_ranges(&self, _file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        Ok(Vec::new())
+    }
+}
+
+// find the parser for a file, by content first and extension second
+// a file whose content matches another format than its extension is read as that format
+pub fn detect_format(path_full: &Path, file: &mut File) -> Result<&'static dyn FormatParser, BoxError> {
+    let extension = path_full.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+    let by_extension = extension.as_deref()
+        .and_then(|ext| FORMAT_PARSERS.iter().find(|parser| parser.extensions().contains(&ext)));
+
+    let header = read_header(file)?;
+    if let Some(parser) = by_extension.filter(|parser| parser.sniff(&header)) {
+        return Ok(*parser);
+    }
+    if let Some(parser) = FORMAT_PARSERS.iter().find(|parser| parser.sniff(&header)) {
+        return Ok(*parser);
+    }
+
+    // some formats can't always be recognized from their first bytes, like mp3s padded after their tag
+    match by_extension {
+        Some(parser) => Ok(*parser),
+        None => Err(format!("File at {} has unsupported format", path_full.to_string_lossy()))?,
+    }
+}
+
+// first bytes of the file after any id3v2 tag
+fn read_header(file: &mut File) -> Result<Vec<u8>, BoxError> {
+    let start = id3v2_size(file)?;
+    let mut header = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.take(SNIFF_SIZE).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+// size of the id3v2 tag at the start of the file, 0 if there is none
+pub fn id3v2_size(file: &mut File) -> Result<u64, BoxError> {
+    let mut header = [0u8; 10];
+    file.seek(SeekFrom::Start(0))?;
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(0);
+    }
+
+    // size is stored as a syncsafe integer, 7 bits per byte
+    let size = header[6..10].iter().fold(0u64, |acc, b| (acc << 7) | (*b as u64 & 0x7f));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Ok(10 + size + footer)
+}
+
+// everything after the id3v2 tag and before the id3v1 tag, for formats with no better idea of where their audio is
+pub fn untagged_range(file: &mut File) -> Result<(u64, u64), BoxError> {
+    let len = file.metadata()?.len();
+    let start = id3v2_size(file)?;
+
+    let mut end = len;
+    if len >= 128 {
+        let mut marker = [0u8; 3];
+        file.seek(SeekFrom::Start(len - 128))?;
+        file.read_exact(&mut marker)?;
+        if &marker == b"TAG" {
+            end = len - 128;
+        }
+    }
+
+    Ok((start, end.max(start)))
+}
+
+// first value of a numeric tag, accepting "3/12" as 3
+pub fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
@@ -0,0 +1,77 @@
+// genres as id3 stores them, either names or codes of the id3v1 genre list
+// https://id3.org/id3v2.3.0#TCON
+
+// id3v1 genres by code, the first 80 of the spec followed by the winamp extensions
+const ID3V1_GENRES: &[&str] = &[
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop",
+    "Jazz", "Metal", "New Age", "Oldies", "Other", "Pop", "R&B", "Rap",
+    "Reggae", "Rock", "Techno", "Industrial", "Alternative", "Ska", "Death Metal", "Pranks",
+    "Soundtrack", "Euro-Techno", "Ambient", "Trip-Hop", "Vocal", "Jazz+Funk", "Fusion", "Trance",
+    "Classical", "Instrumental", "Acid", "House", "Game", "Sound Clip", "Gospel", "Noise",
+    "Alternative Rock", "Bass", "Soul", "Punk", "Space", "Meditative", "Instrumental Pop", "Instrumental Rock",
+    "Ethnic", "Gothic", "Darkwave", "Techno-Industrial", "Electronic", "Pop-Folk", "Eurodance", "Dream",
+    "Southern Rock", "Comedy", "Cult", "Gangsta", "Top 40", "Christian Rap", "Pop/Funk", "Jungle",
+    "Native American", "Cabaret", "New Wave", "Psychedelic", "Rave", "Showtunes", "Trailer", "Lo-Fi",
+    "Tribal", "Acid Punk", "Acid Jazz", "Polka", "Retro", "Musical", "Rock & Roll", "Hard Rock",
+    "Folk", "Folk-Rock", "National Folk", "Swing", "Fast Fusion", "Bebop", "Latin", "Revival",
+    "Celtic", "Bluegrass", "Avantgarde", "Gothic Rock", "Progressive Rock", "Psychedelic Rock", "Symphonic Rock", "Slow Rock",
+    "Big Band", "Chorus", "Easy Listening", "Acoustic", "Humour", "Speech", "Chanson", "Opera",
+    "Chamber Music", "Sonata", "Symphony", "Booty Bass", "Primus", "Porn Groove", "Satire", "Slow Jam",
+    "Club", "Tango", "Samba", "Folklore", "Ballad", "Power Ballad", "Rhythmic Soul", "Freestyle",
+    "Duet", "Punk Rock", "Drum Solo", "A Cappella", "Euro-House", "Dance Hall", "Goa", "Drum & Bass",
+    "Club-House", "Hardcore", "Terror", "Indie", "BritPop", "Afro-Punk", "Polsk Punk", "Beat",
+    "Christian Gangsta Rap", "Heavy Metal", "Black Metal", "Crossover", "Contemporary Christian", "Christian Rock", "Merengue", "Salsa",
+    "Thrash Metal", "Anime", "JPop", "Synthpop", "Abstract", "Art Rock", "Baroque", "Bhangra",
+    "Big Beat", "Breakbeat", "Chillout", "Downtempo", "Dub", "EBM", "Eclectic", "Electro",
+    "Electroclash", "Emo", "Experimental", "Garage", "Global", "IDM", "Illbient", "Industro-Goth",
+    "Jam Band", "Krautrock", "Leftfield", "Lounge", "Math Rock", "New Romantic", "Nu-Breakz", "Post-Punk",
+    "Post-Rock", "Psytrance", "Shoegaze", "Space Rock", "Trop Rock", "World Music", "Neoclassical", "Audiobook",
+    "Audio Theatre", "Neue Deutsche Welle", "Podcast", "Indie Rock", "G-Funk", "Dubstep", "Garage Rock", "Psybient",
+];
+
+// name of an id3v1 genre code
+pub fn id3v1_genre(code: usize) -> Option<&'static str> {
+    ID3V1_GENRES.get(code).copied()
+}
+
+// genres of a single TCON value
+// "17" and "(17)" are codes, "(RX)" and "(CR)" stand for remix and cover,
+// and text after references refines the last of them, so "(17)Hard Rock" is Hard Rock
+// a reference that isn't one, as in "(Live) Rock", is left as text
+pub fn resolve_id3_genre(value: &str) -> Vec<String> {
+    let value = value.trim();
+    if let Ok(code) = value.parse::<usize>() {
+        return vec![id3v1_genre(code).unwrap_or(value).to_string()];
+    }
+
+    let mut genres = Vec::new();
+    let mut rest = value;
+    while let Some(after) = rest.strip_prefix('(') {
+        // "((" escapes a text starting with a parenthesis
+        if after.starts_with('(') {
+            rest = after;
+            break;
+        }
+        let reference = match after.find(')') {
+            Some(end) => &after[..end],
+            None => break,
+        };
+        let genre = match reference {
+            "RX" => "Remix",
+            "CR" => "Cover",
+            code => match code.parse::<usize>().ok().and_then(id3v1_genre) {
+                Some(genre) => genre,
+                None => break,
+            },
+        };
+        genres.push(genre.to_string());
+        rest = &after[reference.len() + 1..];
+    }
+
+    let text = rest.trim();
+    if !text.is_empty() {
+        genres.pop();
+        genres.push(text.to_string());
+    }
+    genres
+}
@@ -1,320 +1,204 @@
 use std::{
     path::{Path, PathBuf},
-    time::Duration,
+    time::{Duration, UNIX_EPOCH},
     fs::{File, DirEntry, read, read_dir},
     io::Write,
 };
-use itertools::Itertools;
 use sqlx::{
     types::time::PrimitiveDateTime,
-    PgPool
+    postgres::PgConnection,
 };
 use blake3;
 use anyhow::{Context, Result};
 use tower::BoxError;
 
-use id3::TagLike;
-use mp3_duration;
-use metaflac;
-use mp4ameta;
 use crate::{
     handlers::IMAGE_EXTENSIONS,
-    utils::Config
+    utils::{Config, Library},
 };
 
+mod credit;
+mod date;
+mod fingerprint;
+pub mod format;
+mod genre;
+mod mp3;
+mod flac;
+mod mp4;
+mod ogg;
+mod riff;
+mod apev2;
+mod ape_streams;
+use fingerprint::audio_fingerprint;
+use format::{FormatParser, ParsedFile, Tags, detect_format};
+
 // helper struct
 #[derive(Debug)]
 pub struct TrackInfo {
     pub track_name: String,
-    pub artist_name: String,
+    // in the order the tags list them
+    pub artist_names: Vec<String>,
     pub album_name: String,
-    pub album_artist_name: String,
+    // in the order the tags list them
+    pub album_artist_names: Vec<String>,
+    // as the tags hold them, before they are split on config's genre_separators
+    pub genres: Vec<String>,
+    // partial dates, "2001", "2001-05" or "2001-05-17"
+    pub recording_date: Option<String>,
+    pub release_date: Option<String>,
+    // role and name, as the tags hold them
+    pub credits: Vec<(String, String)>,
     pub track_number: u32,
     pub disc_number: u32,
     pub length_seconds: u64,
     pub art_id: Option<i32>,
+    pub fingerprint: Option<Vec<u8>>,
+    // name of the library the track is in, path_str being relative to its root
+    pub library: String,
     pub path_str: String,
     pub last_modified: PrimitiveDateTime,
 }
 
-pub async fn parse_tag(pool: &PgPool, config: &Config, path: &Path) -> Result<TrackInfo, BoxError> {
-    // get track's last modified date
-    let path_full = Path::new(&config.music_directory).join(path);
-    let last_modified = PrimitiveDateTime::from(path_full.metadata()?.modified()?);
-
-    // read relevant tags information
-    // supporting only mp3 and flac for now
-    let extension = path
-        .extension()
-        .ok_or(format!("File at {} has no extension", path.to_string_lossy()))?
-        .to_str();
-
-    match extension {
-        Some("mp3") => {
-            parse_mp3(pool, path, &path_full, last_modified, &config.art_directory).await
-        },
-        Some("flac") => {
-            parse_flac(pool, path, &path_full, last_modified, &config.art_directory).await
-        },
-        Some("m4a") => {
-            parse_m4a(pool, path, &path_full, last_modified, &config.art_directory).await
-        },
-        _ => {
-            Err(format!("File at {0} has unsupported extension", path.to_string_lossy()))?
-        },
-    }
+// track info along with the raw cover art, before the art is stored
+#[derive(Debug)]
+pub struct ParsedTrack {
+    pub info: TrackInfo,
+    pub picture: Option<Vec<u8>>,
 }
 
-async fn parse_mp3(
-    pool: &PgPool, 
-    path: &Path, 
-    path_full: &Path,
-    last_modified: PrimitiveDateTime,
-    art_dir: &str
-) -> Result<TrackInfo, BoxError> {
-    // get tag
-    let tag_optional = id3::Tag::read_from_path(path_full).ok();
-
-    // get length
-    let track_length = mp3_duration::from_path(path_full).unwrap_or(Duration::new(0, 0)).as_secs();
+// read a track's tags, with path relative to the root of library
+pub async fn parse_tag(library: &Library, path: &Path) -> Result<ParsedTrack, BoxError> {
+    // reading tags is blocking io so keep it off the async runtime
+    let library = library.clone();
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || read_tag(&library, &path)).await?
+}
 
-    // get path
-    let path_str = path.to_string_lossy().to_string();
+// read a track's tags without touching the db
+// blocking, so should be called from a blocking thread
+pub fn read_tag(library: &Library, path: &Path) -> Result<ParsedTrack, BoxError> {
+    // get track's last modified date
+    let path_full = Path::new(&library.path).join(path);
+    let last_modified = modified_time(&path_full)?;
+
+    // read relevant tags information, the file being opened and parsed only once
+    // a tag or duration that can't be read is left to the defaults rather than failing the whole track
+    let mut file = File::open(&path_full)?;
+    let parser = detect_format(&path_full, &mut file)?;
+    let mut file = ParsedFile::new(file);
+    let tags = parser.read_tags(&mut file).ok().flatten();
+    let length_seconds = parser.read_duration(&mut file).unwrap_or(0);
+    // files whose tags can't be read get no art, not even from their directory
+    let picture = match tags {
+        Some(_) => read_cover(parser, &mut file, &path_full)?,
+        None => None,
+    };
+    let mut parsed = ParsedTrack {
+        info: track_info(path, tags, length_seconds, last_modified),
+        picture,
+    };
 
-    Ok(
-        match tag_optional {
-            Some(tag) => {
-                // get picture
-                let mut art_id = None;
-                let mut pictures_iter = tag.pictures();
-                loop {
-                    if let Some(picture_curr) = pictures_iter.next() {
-                        if picture_curr.picture_type == id3::frame::PictureType::CoverFront {
-                            // if cover front we can stop
-                            art_id = Some(get_art_id(&picture_curr.data, pool, art_dir).await?);
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                };
+    // used to recognize the track if it gets moved
+    parsed.info.fingerprint = Some(audio_fingerprint(parser, &mut file)?);
+    parsed.info.library = library.name.clone();
 
-                // in case the track has no embedded cover find it in dir
-                if art_id.is_none() {
-                    // get image file in parent dir
-                    let picture = get_picture_in_dir(path_full)?;
-                    if let Some(picture_dir) = picture {
-                        art_id = Some(get_art_id(&read(picture_dir)?, pool, art_dir).await?);
-                    };
-                };
+    Ok(parsed)
+}
 
-                TrackInfo {
-                    track_name: tag.title().unwrap_or(&path_str).to_string(),
-                    artist_name: tag.artist().unwrap_or("Unknown Artist").to_string(),
-                    album_name: tag.album().unwrap_or("Unknown Album").to_string(),
-                    album_artist_name: tag.album_artist().unwrap_or("Unknown Artist").to_string(),
-                    track_number: tag.track().unwrap_or(0),
-                    disc_number: tag.disc().unwrap_or(0),
-                    length_seconds: track_length,
-                    art_id: art_id,
-                    path_str: path.to_string_lossy().to_string(),
-                    last_modified: last_modified,
-                }
-            },
-            None => {
-                TrackInfo {
-                    track_name: path_str,
-                    artist_name: String::from("Unknown Artist"),
-                    album_name: String::from("Unknown Album"),
-                    album_artist_name: String::from("Unknown Artist"),
-                    track_number: 0,
-                    disc_number: 0,
-                    length_seconds: track_length,
-                    art_id: None,
-                    path_str: path.to_string_lossy().to_string(),
-                    last_modified: last_modified,
-                }
-            }             
-        }           
-    )
+// fingerprint of a track's audio alone, with path relative to the root of library
+// for tracks stored before they had one, whose file hasn't changed since
+pub async fn parse_fingerprint(library: &Library, path: &Path) -> Result<Vec<u8>, BoxError> {
+    let path_full = Path::new(&library.path).join(path);
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(&path_full)?;
+        let parser = detect_format(&path_full, &mut file)?;
+        audio_fingerprint(parser, &mut ParsedFile::new(file))
+    }).await?
 }
 
-async fn parse_flac(
-    pool: &PgPool, 
-    path: &Path, 
-    path_full: &Path,
-    last_modified: PrimitiveDateTime,
-    art_dir: &str
-) -> Result<TrackInfo, BoxError> {
-    // get tag
-    let tag_optional = metaflac::Tag::read_from_path(path_full).ok();
+// get a file's last modified date, at the microsecond precision the db stores it at
+// so that it can be compared against the stored one
+pub fn modified_time(path_full: &Path) -> Result<PrimitiveDateTime, BoxError> {
+    let since_epoch = path_full.metadata()?.modified()?.duration_since(UNIX_EPOCH)?;
+    Ok(PrimitiveDateTime::from(UNIX_EPOCH + Duration::from_micros(since_epoch.as_micros() as u64)))
+}
 
-    // get path
-    let path_str = path.to_string_lossy().to_string();
-    
-    Ok(
-        match tag_optional {
-            Some(tag) => {
-                // get length
-                let track_length;
-                if let Some(streaminfo) = tag.get_streaminfo() {
-                    track_length = streaminfo.total_samples / streaminfo.sample_rate as u64;
-                } else {
-                    track_length = 0;
-                };
+// art whose row has been inserted but whose file is yet to be written
+// the file is only written once the row is committed, so that a rolled back insert leaves no file behind
+#[derive(Debug)]
+pub struct NewArt {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+}
 
-                // get picture
-                // exact same interface as id3 apparently for pictures
-                let mut art_id = None;
-                let mut pictures_iter = tag.pictures();
-                loop {
-                    if let Some(picture_curr) = pictures_iter.next() {
-                        if picture_curr.picture_type == metaflac::block::PictureType::CoverFront {
-                            // if cover front we can stop
-                            art_id = Some(get_art_id(&picture_curr.data, pool, art_dir).await?);
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                };
+impl NewArt {
+    pub fn write(&self) -> Result<(), BoxError> {
+        let mut file = File::create(&self.path)
+            .context(format!("Creation of {} error. Maybe the arts directory in config.json does not exist?", self.path.to_string_lossy()))?;
+        file.write_all(&self.data)?;
+        Ok(())
+    }
+}
 
-                // in case the track has no embedded cover find it in dir
-                if art_id.is_none() {
-                    // get image file in parent dir
-                    let picture = get_picture_in_dir(path_full)?;
-                    if let Some(picture_dir) = picture {
-                        art_id = Some(get_art_id(&read(picture_dir)?, pool, art_dir).await?);
-                    };
-                };
+// store the track's art if it has any, then return the track info linked to the art
+// along with the art file to write once the transaction is committed, if the art is new
+pub async fn store_art(conn: &mut PgConnection, config: &Config, parsed: ParsedTrack) -> Result<(TrackInfo, Option<NewArt>), BoxError> {
+    let mut info = parsed.info;
+    let mut new_art = None;
+    if let Some(picture) = parsed.picture {
+        let (art_id, art) = get_art_id(picture, conn, &config.art_directory).await?;
+        info.art_id = Some(art_id);
+        new_art = art;
+    }
 
-                match tag.vorbis_comments() {
-                    Some(comment) => {
-                        TrackInfo {
-                            track_name: comment.title().unwrap_or(&vec![path_str]).join(", "),
-                            artist_name: comment.artist().unwrap_or(&vec!["Unknown Artist".to_string()]).join(", "),
-                            album_name: comment.album().unwrap_or(&vec!["Unknown Album".to_string()]).join(", "),
-                            album_artist_name: comment.album_artist().unwrap_or(&vec!["Unknown Artist".to_string()]).join(", "),
-                            track_number: comment.track().unwrap_or(0),
-                            disc_number: comment.comments.get("DISCNUMBER").unwrap_or(&Vec::new()).get(0).unwrap_or(&"0".to_string()).to_string().parse::<u32>().unwrap_or(0),
-                            length_seconds: track_length,
-                            art_id: art_id,
-                            path_str: path.to_string_lossy().to_string(),
-                            last_modified: last_modified,
-                        }
-                    },
-                    None => {
-                        TrackInfo {
-                            track_name: path_str,
-                            artist_name: String::from("Unknown Artist"),
-                            album_name: String::from("Unknown Album"),
-                            album_artist_name: String::from("Unknown Artist"),
-                            track_number: 0,
-                            disc_number: 0,
-                            length_seconds: track_length,
-                            art_id: art_id,
-                            path_str: path.to_string_lossy().to_string(),
-                            last_modified: last_modified,
-                        }   
-                    }
-                }
-            },
-            None => {
-                TrackInfo {
-                    track_name: path_str,
-                    artist_name: String::from("Unknown Artist"),
-                    album_name: String::from("Unknown Album"),
-                    album_artist_name: String::from("Unknown Artist"),
-                    track_number: 0,
-                    disc_number: 0,
-                    length_seconds: 0,
-                    art_id: None,
-                    path_str: path.to_string_lossy().to_string(),
-                    last_modified: last_modified,
-                }
-            }
-        }
-    )
+    Ok((info, new_art))
 }
 
-async fn parse_m4a(
-    pool: &PgPool, 
-    path: &Path, 
-    path_full: &Path,
-    last_modified: PrimitiveDateTime,
-    art_dir: &str
-) -> Result<TrackInfo, BoxError> {
-    // get tag
-    let tag_optional = mp4ameta::Tag::read_from_path(path_full).ok();
-
-    // get path
+// fill in what the tags are missing
+fn track_info(path: &Path, tags: Option<Tags>, length_seconds: u64, last_modified: PrimitiveDateTime) -> TrackInfo {
     let path_str = path.to_string_lossy().to_string();
+    let tags = tags.unwrap_or_default();
+    let names = |names: Vec<String>| match names.is_empty() {
+        true => vec![String::from("Unknown Artist")],
+        false => names,
+    };
 
-    Ok(
-        match tag_optional {
-            Some(tag) => {
-                // get picture
-                let mut art_id = None;
-                if let Some(art) = tag.artwork() {
-                    art_id = Some(get_art_id(art.data, pool, art_dir).await?);
-                }
-
-                // in case the track has no embedded cover find it in dir
-                if art_id.is_none() {
-                    // get image file in parent dir
-                    let picture = get_picture_in_dir(path_full)?;
-                    if let Some(picture_dir) = picture {
-                        art_id = Some(get_art_id(&read(picture_dir)?, pool, art_dir).await?);
-                    };
-                };
-
-                // get all artists and album artists
-                let artists;
-                if tag.artists().count() > 0 {
-                    artists = tag.artists().join(", ");
-                } else {
-                    artists = "Unknown Artist".to_string();
-                }
+    TrackInfo {
+        track_name: tags.title.unwrap_or_else(|| path_str.clone()),
+        artist_names: names(tags.artists),
+        album_name: tags.album.unwrap_or_else(|| String::from("Unknown Album")),
+        album_artist_names: names(tags.album_artists),
+        genres: tags.genres,
+        recording_date: tags.recording_date.as_deref().and_then(date::partial_date),
+        release_date: tags.release_date.as_deref().and_then(date::partial_date),
+        credits: tags.credits,
+        track_number: tags.track_number.unwrap_or(0),
+        disc_number: tags.disc_number.unwrap_or(0),
+        length_seconds,
+        art_id: None,
+        fingerprint: None,
+        library: String::new(),
+        path_str,
+        last_modified,
+    }
+}
 
-                let album_artists;
-                if tag.album_artists().count() > 0 {
-                    album_artists = tag.album_artists().join(", ");
-                } else {
-                    album_artists = "Unknown Artist".to_string();
-                }
+// the embedded front cover, or in case the track has none an image file in its directory
+fn read_cover(parser: &dyn FormatParser, file: &mut ParsedFile, path_full: &Path) -> Result<Option<Vec<u8>>, BoxError> {
+    let embedded = parser.read_pictures(file).unwrap_or_default()
+        .into_iter()
+        .find(|picture| picture.front_cover);
+    if let Some(picture) = embedded {
+        return Ok(Some(picture.data));
+    }
 
-                TrackInfo {
-                    track_name: tag.title().unwrap_or(&path_str).to_string(),
-                    artist_name: artists,
-                    album_name: tag.album().unwrap_or("Unknown Album").to_string(),
-                    album_artist_name: album_artists,
-                    track_number: tag.track_number().unwrap_or(0) as u32,
-                    disc_number: tag.disc_number().unwrap_or(0) as u32,
-                    length_seconds: tag.duration().unwrap_or(Duration::new(0,0)).as_secs(),
-                    art_id: art_id,
-                    path_str: path.to_string_lossy().to_string(),
-                    last_modified: last_modified,
-                }
-            },
-            None => {
-                TrackInfo {
-                    track_name: path_str,
-                    artist_name: String::from("Unknown Artist"),
-                    album_name: String::from("Unknown Album"),
-                    album_artist_name: String::from("Unknown Artist"),
-                    track_number: 0,
-                    disc_number: 0,
-                    length_seconds: 0,
-                    art_id: None,
-                    path_str: path.to_string_lossy().to_string(),
-                    last_modified: last_modified,
-                }
-            }             
-        }           
-    )
+    // get image file in parent dir
+    match get_picture_in_dir(path_full)? {
+        Some(picture_dir) => Ok(Some(read(picture_dir)?)),
+        None => Ok(None),
+    }
 }
 
-
 // get an image file in the current directory
 fn get_picture_in_dir(path: &Path) -> Result<Option<PathBuf>, BoxError> {
     // get parent
@@ -359,19 +243,20 @@ fn get_picture_in_dir(path: &Path) -> Result<Option<PathBuf>, BoxError> {
 }
 
 // check if picture's already in the database
-// insert new art if there isn't one
-async fn get_art_id(picture_data: &[u8], pool: &PgPool, art_dir: &str) -> Result<i32, BoxError> {
+// insert new art if there isn't one, returning the file to write for it
+async fn get_art_id(picture_data: Vec<u8>, conn: &mut PgConnection, art_dir: &str) -> Result<(i32, Option<NewArt>), BoxError> {
     let art_id: i32;
+    let mut new_art = None;
 
     // calculate hash
-    let art_hash = blake3::hash(picture_data);
+    let art_hash = blake3::hash(&picture_data);
     let art_hash_bytes = art_hash.as_bytes().to_vec();
 
     // check if hash in database
     let existing_art_id = sqlx::query_scalar!("SELECT art_id FROM art \
         WHERE hash = ($1)",
         art_hash_bytes)
-        .fetch_optional(pool)
+        .fetch_optional(&mut *conn)
         .await?;
 
     if existing_art_id.is_some() {
@@ -379,19 +264,18 @@ async fn get_art_id(picture_data: &[u8], pool: &PgPool, art_dir: &str) -> Result
         art_id = existing_art_id.unwrap(); // guarantee to not be none
     } else {
         // else insert new art
-        // write to directory
         let new_art_name = art_hash.to_hex().to_string();
-        let new_art_directory = format!("{0}/{1}", art_dir, new_art_name);
-        let mut file = File::create(&new_art_directory)
-            .context(format!("Creation of {} error. Maybe the arts directory in config.json does not exist?", &new_art_directory))?;
-        file.write_all(picture_data)?;
-
-        // insert to db
         art_id = sqlx::query_scalar!("INSERT INTO art (hash, path) VALUES ($1, $2) RETURNING art_id",
             art_hash_bytes, new_art_name)
-            .fetch_one(pool)
+            .fetch_one(&mut *conn)
             .await?;
+
+        // the file goes in the arts directory once the row is committed
+        new_art = Some(NewArt {
+            path: Path::new(art_dir).join(&new_art_name),
+            data: picture_data,
+        });
     };
 
-    Ok(art_id)
+    Ok((art_id, new_art))
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+use std::{
+    fs::File,
+    io::{BufReader, Seek},
+};
+use id3::TagLike;
+use tower::BoxError;
+
+use super::{
+    format::{FormatParser, ParsedFile, Tags, EmbeddedPicture, untagged_range},
+    genre::resolve_id3_genre,
+    credit::{involvement_role, COMPOSER, CONDUCTOR, LYRICIST, PERFORMER},
+};
+
+pub struct Mp3Parser;
+
+impl FormatParser for Mp3Parser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mp3"]
+    }
+
+    // frame sync, 11 set bits
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.len() >= 2 && header[0] == 0xff && header[1] & 0xe0 == 0xe0
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        Ok(file.parsed(read_id3)?.as_ref().map(id3_tags))
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        mp3_length(&mut file.file)
+    }
+
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        Ok(file.parsed(read_id3)?.as_ref().map(id3_pictures).unwrap_or_default())
+    }
+
+    // everything between the id3v2 tag and the id3v1 tag
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        Ok(vec![untagged_range(&mut file.file)?])
+    }
+}
+
+// the id3v2 tag at the start of the file, none if there isn't one
+fn read_id3(file: &mut File) -> Result<Option<id3::Tag>, BoxError> {
+    file.rewind()?;
+    match id3::Tag::read_from(BufReader::new(file)) {
+        Ok(tag) => Ok(Some(tag)),
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(None),
+        Err(e) => Err(e)?,
+    }
+}
+
+fn mp3_length(file: &mut File) -> Result<u64, BoxError> {
+    file.rewind()?;
+    Ok(mp3_duration::from_file(file)?.as_secs())
+}
+
+// tags of an id3 tag, wherever it was found
+pub fn id3_tags(tag: &id3::Tag) -> Tags {
+    Tags {
+        title: tag.title().map(str::to_string),
+        artists: id3_text_values(tag, "TPE1"),
+        album: tag.album().map(str::to_string),
+        album_artists: id3_text_values(tag, "TPE2"),
+        genres: id3_text_values(tag, "TCON").iter().flat_map(|value| resolve_id3_genre(value)).collect(),
+        recording_date: id3_recording_date(tag),
+        // id3v2.3 only has the original release year
+        release_date: id3_text(tag, "TDRL").or_else(|| id3_text(tag, "TDOR")).or_else(|| id3_text(tag, "TORY")),
+        credits: id3_credits(tag),
+        track_number: tag.track(),
+        disc_number: tag.disc(),
+    }
+}
+
+// values of a text frame, which id3v2.4 separates with nulls
+fn id3_text_values(tag: &id3::Tag, id: &str) -> Vec<String> {
+    tag.get(id)
+        .and_then(|frame| frame.content().text_values())
+        .map(|values| values.map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+// first value of a text frame
+fn id3_text(tag: &id3::Tag, id: &str) -> Option<String> {
+    id3_text_values(tag, id).into_iter().next()
+}
+
+// id3v2.4 has a TDRC timestamp, id3v2.3 splits it into a TYER year and a TDAT day and month, DDMM
+fn id3_recording_date(tag: &id3::Tag) -> Option<String> {
+    if let Some(date) = id3_text(tag, "TDRC") {
+        return Some(date);
+    }
+    let year = id3_text(tag, "TYER")?;
+    match id3_text(tag, "TDAT") {
+        Some(day_month) if day_month.len() == 4 && day_month.is_ascii() => {
+            Some(format!("{}-{}-{}", year, &day_month[2..], &day_month[..2]))
+        },
+        _ => Some(year),
+    }
+}
+
+// TCOM, TPE3 and TEXT list composers, conductors and lyricists
+// TIPL pairs an involvement with a person and TMCL an instrument with a musician, as successive values
+fn id3_credits(tag: &id3::Tag) -> Vec<(String, String)> {
+    let mut credits = Vec::new();
+    for (id, role) in [("TCOM", COMPOSER), ("TPE3", CONDUCTOR), ("TEXT", LYRICIST)] {
+        credits.extend(id3_text_values(tag, id).into_iter().map(|name| (role.to_string(), name)));
+    }
+    for pair in id3_text_values(tag, "TIPL").chunks_exact(2) {
+        credits.push((involvement_role(&pair[0]), pair[1].clone()));
+    }
+    for pair in id3_text_values(tag, "TMCL").chunks_exact(2) {
+        credits.push((PERFORMER.to_string(), pair[1].clone()));
+    }
+    credits
+}
+
+pub fn id3_pictures(tag: &id3::Tag) -> Vec<EmbeddedPicture> {
+    tag.pictures()
+        .map(|picture| EmbeddedPicture {
+            front_cover: picture.picture_type == id3::frame::PictureType::CoverFront,
+            data: picture.data.to_vec(),
+        })
+        .collect()
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+};
+use tower::BoxError;
+use mp4ameta::FreeformIdent;
+
+use super::{
+    format::{FormatParser, ParsedFile, Tags, EmbeddedPicture},
+    genre::id3v1_genre,
+    credit::field_credits,
+};
+
+pub struct Mp4Parser;
+
+impl FormatParser for Mp4Parser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["m4a"]
+    }
+
+    // the first atom is always ftyp
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.len() >= 8 && &header[4..8] == b"ftyp"
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        Ok(Some(mp4_tags(file.parsed(read_mp4)?)))
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        Ok(mp4_length(file.parsed(read_mp4)?))
+    }
+
+    // mp4 artwork has no picture type, so all of it counts as a front cover
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        Ok(file.parsed(read_mp4)?.artworks()
+            .map(|art| EmbeddedPicture { front_cover: true, data: art.data.to_vec() })
+            .collect())
+    }
+
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        mp4_audio_ranges(&mut file.file)
+    }
+}
+
+fn read_mp4(file: &mut File) -> Result<mp4ameta::Tag, BoxError> {
+    file.rewind()?;
+    Ok(mp4ameta::Tag::read_from(&mut BufReader::new(file))?)
+}
+
+fn mp4_tags(tag: &mp4ameta::Tag) -> Tags {
+    // itunes has no original release date, taggers add it as a freeform item
+    let original_date = FreeformIdent::new("com.apple.iTunes", "ORIGINALDATE");
+    let release_date = tag.strings_of(&original_date).next().map(str::to_string);
+    // only composers have an atom of their own, the other credits are freeform items too
+    let credits = field_credits(|field| match field {
+        "COMPOSER" => tag.composers().map(str::to_string).collect(),
+        _ => tag.strings_of(&FreeformIdent::new("com.apple.iTunes", field)).map(str::to_string).collect(),
+    });
+    Tags {
+        title: tag.title().map(str::to_string),
+        artists: tag.artists().map(str::to_string).collect(),
+        album: tag.album().map(str::to_string),
+        album_artists: tag.album_artists().map(str::to_string).collect(),
+        // standard genres are id3v1 codes plus one
+        genres: tag.standard_genres()
+            .filter_map(|code| id3v1_genre((code as usize).checked_sub(1)?))
+            .chain(tag.custom_genres())
+            .map(str::to_string)
+            .collect(),
+        recording_date: tag.year().map(str::to_string),
+        release_date,
+        credits,
+        track_number: tag.track_number().map(u32::from),
+        disc_number: tag.disc_number().map(u32::from),
+    }
+}
+
+fn mp4_length(tag: &mp4ameta::Tag) -> u64 {
+    tag.duration().map_or(0, |duration| duration.as_secs())
+}
+
+// contents of every top level mdat atom
+fn mp4_audio_ranges(file: &mut File) -> Result<Vec<(u64, u64)>, BoxError> {
+    let len = file.metadata()?.len();
+    let mut ranges = Vec::new();
+    let mut position = 0;
+
+    while position + 8 <= len {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+
+        // size 1 means a 64 bit size follows the type, size 0 means the atom runs to the end of the file
+        let (header_len, size) = match size32 {
+            0 => (8, len - position),
+            1 => {
+                let mut size64 = [0u8; 8];
+                file.read_exact(&mut size64)?;
+                (16, u64::from_be_bytes(size64))
+            },
+            s => (8, s),
+        };
+        if size < header_len {
+            Err("Invalid atom size")?;
+        }
+
+        if &header[4..8] == b"mdat" {
+            ranges.push((position + header_len, (position + size).min(len)));
+        }
+        position += size;
+    }
+
+    Ok(ranges)
+}
@@ -0,0 +1,511 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    collections::HashMap,
+};
+use tower::BoxError;
+
+use super::{
+    format::{FormatParser, ParsedFile, Tags, EmbeddedPicture, parse_number},
+    credit::field_credits,
+};
+
+// ogg vorbis and opus files, read by hand since they are only a few pages of headers in front of the audio
+// https://xiph.org/ogg/doc/framing.html, https://xiph.org/vorbis/doc/v-comment.html, rfc 7845 for opus
+
+// granule position of pages where no packet ends
+const NO_GRANULE: u64 = u64::MAX;
+// opus granule positions always count samples at 48kHz, whatever the input rate was
+const OPUS_GRANULE_RATE: u64 = 48000;
+// how far from the end of the file to look for the last page
+const TAIL_SIZE: u64 = 64 * 1024;
+// picture type of a front cover, as in flac and id3
+const COVER_FRONT: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OggCodec {
+    Vorbis,
+    Opus,
+}
+
+// header of an ogg page, with where its body is in the file
+#[derive(Debug)]
+struct OggPage {
+    granule: u64,
+    serial: u32,
+    body_start: u64,
+    body_len: u64,
+    // size of every segment of the body, a packet ends with the first segment shorter than 255
+    lacing: Vec<u8>,
+}
+
+// what the headers of the first logical stream hold
+#[derive(Debug)]
+struct OggInfo {
+    serial: u32,
+    // uppercased field names, as vorbis comments are case insensitive
+    comments: HashMap<String, Vec<String>>,
+    length_seconds: u64,
+    // offset of the first page after the header packets
+    audio_start: u64,
+}
+
+pub struct OggParser;
+
+impl FormatParser for OggParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ogg", "opus"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.starts_with(b"OggS")
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        Ok(Some(ogg_tags(file.parsed(read_ogg)?)))
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        Ok(file.parsed(read_ogg)?.length_seconds)
+    }
+
+    // pictures are embedded as base64 flac picture blocks
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        Ok(file.parsed(read_ogg)?.comments.get("METADATA_BLOCK_PICTURE").into_iter().flatten()
+            .filter_map(|block| base64::decode(block.trim()).ok())
+            .filter_map(|block| parse_picture_block(&block))
+            .map(|(picture_type, data)| EmbeddedPicture { front_cover: picture_type == COVER_FRONT, data })
+            .collect())
+    }
+
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        let info = file.parsed(read_ogg)?;
+        let (serial, audio_start) = (info.serial, info.audio_start);
+        ogg_audio_ranges(&mut file.file, serial, audio_start)
+    }
+}
+
+fn ogg_tags(info: &OggInfo) -> Tags {
+    // multiple values of the same field are joined as for flac
+    let joined = |field: &str| info.comments.get(field).map(|values| values.join(", "));
+    let values = |field: &str| info.comments.get(field).cloned().unwrap_or_default();
+    let number = |field: &str| info.comments.get(field).and_then(|values| parse_number(values.first()?));
+    let first = |fields: &[&str]| fields.iter().find_map(|field| info.comments.get(*field)?.first().cloned());
+
+    Tags {
+        title: joined("TITLE"),
+        artists: values("ARTIST"),
+        album: joined("ALBUM"),
+        album_artists: values("ALBUMARTIST"),
+        genres: values("GENRE"),
+        recording_date: first(&["DATE"]),
+        release_date: first(&["ORIGINALDATE", "ORIGINALYEAR"]),
+        credits: field_credits(values),
+        track_number: number("TRACKNUMBER"),
+        disc_number: number("DISCNUMBER"),
+    }
+}
+
+// bodies of the audio pages of the stream with serial, from audio_start on
+// page headers are left out, since their sequence numbers shift when the comment header grows by a page
+fn ogg_audio_ranges(file: &mut File, serial: u32, audio_start: u64) -> Result<Vec<(u64, u64)>, BoxError> {
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut ranges = Vec::new();
+    let mut position = audio_start;
+
+    while let Some(page) = read_page(&mut reader, position)? {
+        let end = (page.body_start + page.body_len).min(len);
+        if page.serial == serial {
+            ranges.push((page.body_start, end));
+        }
+        position = end;
+    }
+
+    Ok(ranges)
+}
+
+// read the page starting at position, none if there isn't one there
+fn read_page<R: Read + Seek>(reader: &mut R, position: u64) -> Result<Option<OggPage>, BoxError> {
+    let mut header = [0u8; 27];
+    reader.seek(SeekFrom::Start(position))?;
+    if reader.read_exact(&mut header).is_err() || &header[0..4] != b"OggS" {
+        return Ok(None);
+    }
+
+    let mut lacing = vec![0u8; header[26] as usize];
+    if reader.read_exact(&mut lacing).is_err() {
+        return Ok(None);
+    }
+    Ok(Some(OggPage {
+        granule: u64::from_le_bytes(header[6..14].try_into()?),
+        serial: u32::from_le_bytes(header[14..18].try_into()?),
+        body_start: position + 27 + lacing.len() as u64,
+        body_len: lacing.iter().map(|s| *s as u64).sum(),
+        lacing,
+    }))
+}
+
+// read the identification and comment headers of the first stream, then the duration from the last page
+fn read_ogg(file: &mut File) -> Result<OggInfo, BoxError> {
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    // a packet can span several pages, and a page can hold several packets
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut current: Vec<u8> = Vec::new();
+    let mut serial = None;
+    let mut codec = None;
+    let mut position = 0;
+    let audio_start = loop {
+        let page = read_page(&mut reader, position)?.ok_or("Invalid ogg page")?;
+        position = page.body_start + page.body_len;
+        // pages of other streams can be interleaved with the first one
+        if *serial.get_or_insert(page.serial) != page.serial {
+            continue;
+        }
+
+        let mut body = vec![0u8; page.body_len as usize];
+        reader.read_exact(&mut body)?;
+        let mut offset = 0;
+        for segment in page.lacing.iter() {
+            current.extend_from_slice(&body[offset..offset + *segment as usize]);
+            offset += *segment as usize;
+            if *segment < 255 {
+                packets.push(std::mem::take(&mut current));
+            }
+        }
+
+        if codec.is_none() {
+            codec = match packets.first() {
+                Some(packet) if packet.starts_with(b"\x01vorbis") => Some(OggCodec::Vorbis),
+                Some(packet) if packet.starts_with(b"OpusHead") => Some(OggCodec::Opus),
+                Some(_) => Err("Unsupported ogg codec")?,
+                None => None,
+            };
+        }
+        // vorbis has a setup header after the comments, opus doesn't
+        let header_count = match codec {
+            Some(OggCodec::Vorbis) => 3,
+            _ => 2,
+        };
+        if packets.len() >= header_count {
+            break position;
+        }
+        if position >= len {
+            Err("Ogg headers are incomplete")?;
+        }
+    };
+    let codec = codec.ok_or("Unsupported ogg codec")?;
+    let serial = serial.ok_or("Invalid ogg page")?;
+
+    let (comments, samples_per_second, pre_skip) = match codec {
+        OggCodec::Vorbis => {
+            let id = &packets[0];
+            let rate = u32::from_le_bytes(id.get(12..16).ok_or("Vorbis header is too short")?.try_into()?);
+            let comment = packets[1].strip_prefix(b"\x03vorbis").ok_or("Missing vorbis comment header")?;
+            (parse_comments(comment)?, rate as u64, 0)
+        },
+        OggCodec::Opus => {
+            let id = &packets[0];
+            let pre_skip = u16::from_le_bytes(id.get(10..12).ok_or("Opus header is too short")?.try_into()?);
+            let comment = packets[1].strip_prefix(b"OpusTags").ok_or("Missing opus tags header")?;
+            (parse_comments(comment)?, OPUS_GRANULE_RATE, pre_skip as u64)
+        },
+    };
+
+    let length_seconds = match (last_granule(&mut reader, len, serial)?, samples_per_second) {
+        (Some(granule), rate) if rate > 0 => granule.saturating_sub(pre_skip) / rate,
+        _ => 0,
+    };
+
+    Ok(OggInfo { serial, comments, length_seconds, audio_start })
+}
+
+// granule position of the last page of the stream that has one
+fn last_granule<R: Read + Seek>(reader: &mut R, len: u64, serial: u32) -> Result<Option<u64>, BoxError> {
+    let tail_start = len.saturating_sub(TAIL_SIZE);
+    let mut tail = Vec::new();
+    reader.seek(SeekFrom::Start(tail_start))?;
+    reader.read_to_end(&mut tail)?;
+
+    // the capture pattern can show up inside audio data too, so only trust the last real page of the stream
+    let mut granule = None;
+    let mut index = 0;
+    while let Some(found) = tail[index..].windows(4).position(|w| w == b"OggS") {
+        let position = tail_start + (index + found) as u64;
+        if let Some(page) = read_page(reader, position)? {
+            if page.serial == serial && page.granule != NO_GRANULE && page.body_start + page.body_len <= len {
+                granule = Some(page.granule);
+            }
+        }
+        index += found + 1;
+    }
+
+    Ok(granule)
+}
+
+// vendor string followed by a list of "FIELD=value" comments, all lengths being 32 bit little endian
+fn parse_comments(data: &[u8]) -> Result<HashMap<String, Vec<String>>, BoxError> {
+    let mut reader = data;
+    let vendor_len = read_u32_le(&mut reader)? as usize;
+    reader = reader.get(vendor_len..).ok_or("Vorbis comment vendor is too long")?;
+
+    let mut comments: HashMap<String, Vec<String>> = HashMap::new();
+    let count = read_u32_le(&mut reader)?;
+    for _ in 0..count {
+        let comment_len = read_u32_le(&mut reader)? as usize;
+        let comment = reader.get(..comment_len).ok_or("Vorbis comment is too long")?;
+        reader = &reader[comment_len..];
+
+        // comments without a field name are invalid, skip them rather than the whole track
+        let comment = String::from_utf8_lossy(comment);
+        if let Some((field, value)) = comment.split_once('=') {
+            comments.entry(field.to_uppercase()).or_default().push(value.to_string());
+        }
+    }
+
+    Ok(comments)
+}
+
+// type and data of a flac picture block
+// picture type, mime type, description, 4 numbers about the image, then the data, every length being 32 bit big endian
+fn parse_picture_block(block: &[u8]) -> Option<(u32, Vec<u8>)> {
+    let read_u32 = |offset: usize| Some(u32::from_be_bytes(block.get(offset..offset + 4)?.try_into().ok()?) as usize);
+
+    let picture_type = read_u32(0)? as u32;
+    let mime_end = 8 + read_u32(4)?;
+    let description_end = mime_end + 4 + read_u32(mime_end)?;
+    let data_start = description_end + 16 + 4;
+    let data_len = read_u32(description_end + 16)?;
+
+    Some((picture_type, block.get(data_start..data_start + data_len)?.to_vec()))
+}
+
+fn read_u32_le(reader: &mut &[u8]) -> Result<u32, BoxError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::write, path::{Path, PathBuf}};
+    use tempfile::TempDir;
+    use super::*;
+
+    const SERIAL: u32 = 7;
+
+    // writes every packet on pages of its own, without checksums since they aren't checked
+    struct OggWriter {
+        data: Vec<u8>,
+    }
+
+    impl OggWriter {
+        fn packet(&mut self, serial: u32, packet: &[u8], granule: u64) {
+            // a page holds at most 255 segments, longer packets continue on the next page
+            let mut segments: Vec<&[u8]> = packet.chunks(255).collect();
+            if packet.len() % 255 == 0 {
+                segments.push(&[]);
+            }
+            let page_count = segments.chunks(255).count();
+            for (i, page) in segments.chunks(255).enumerate() {
+                let page_granule = if i + 1 == page_count { granule } else { NO_GRANULE };
+                self.data.extend(b"OggS");
+                self.data.extend([0, if i > 0 { 0x01 } else { 0 }]);
+                self.data.extend(page_granule.to_le_bytes());
+                self.data.extend(serial.to_le_bytes());
+                self.data.extend([0u8; 8]);
+                self.data.push(page.len() as u8);
+                self.data.extend(page.iter().map(|segment| segment.len() as u8));
+                page.iter().for_each(|segment| self.data.extend(*segment));
+            }
+        }
+    }
+
+    fn comment_block(comments: &[String]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend(4u32.to_le_bytes());
+        block.extend(b"test");
+        block.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments.iter() {
+            block.extend((comment.len() as u32).to_le_bytes());
+            block.extend(comment.as_bytes());
+        }
+        block
+    }
+
+    fn picture_comment(picture_type: u32, data: &[u8]) -> String {
+        let mut block = Vec::new();
+        block.extend(picture_type.to_be_bytes());
+        block.extend((b"image/png".len() as u32).to_be_bytes());
+        block.extend(b"image/png");
+        block.extend(0u32.to_be_bytes());
+        block.extend([0u8; 16]);
+        block.extend((data.len() as u32).to_be_bytes());
+        block.extend(data);
+        format!("METADATA_BLOCK_PICTURE={}", base64::encode(&block))
+    }
+
+    // headers then audio pages, the last one ending at last_granule
+    fn write_file(dir: &TempDir, name: &str, headers: Vec<Vec<u8>>, last_granule: u64) -> PathBuf {
+        let mut writer = OggWriter { data: Vec::new() };
+        for header in headers.iter() {
+            writer.packet(SERIAL, header, 0);
+        }
+        for page in 1..=4 {
+            writer.packet(SERIAL, &[page as u8; 100], last_granule * page / 4);
+        }
+        let path = dir.path().join(name);
+        write(&path, writer.data).unwrap();
+        path
+    }
+
+    fn vorbis_file(dir: &TempDir, comments: &[String], rate: u32, last_granule: u64) -> PathBuf {
+        let mut id = b"\x01vorbis".to_vec();
+        id.extend(0u32.to_le_bytes());
+        id.push(2);
+        id.extend(rate.to_le_bytes());
+        id.extend([0u8; 14]);
+        let mut comment = b"\x03vorbis".to_vec();
+        comment.extend(comment_block(comments));
+        comment.push(1);
+        write_file(dir, "track.ogg", vec![id, comment, b"\x05vorbis".to_vec()], last_granule)
+    }
+
+    fn opus_file(dir: &TempDir, comments: &[String], pre_skip: u16, last_granule: u64) -> PathBuf {
+        let mut head = b"OpusHead".to_vec();
+        head.extend([1, 2]);
+        head.extend(pre_skip.to_le_bytes());
+        head.extend(44100u32.to_le_bytes());
+        head.extend([0, 0, 0]);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend(comment_block(comments));
+        write_file(dir, "track.opus", vec![head, tags], last_granule)
+    }
+
+    fn open(path: &Path) -> ParsedFile {
+        ParsedFile::new(File::open(path).unwrap())
+    }
+
+    fn comments(comments: &[&str]) -> Vec<String> {
+        comments.iter().map(|comment| comment.to_string()).collect()
+    }
+
+    #[test]
+    fn vorbis_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = vorbis_file(&dir, &comments(&[
+            "TITLE=Title",
+            "ARTIST=First",
+            // field names are case insensitive
+            "artist=Second",
+            "Album=Album",
+            "ALBUMARTIST=First",
+            "GENRE=Rock",
+            "GENRE=Jazz",
+            "DATE=2001-05-17",
+            "ORIGINALYEAR=1999",
+            "COMPOSER=Composer",
+            "PERFORMER=Player (violin)",
+            "TRACKNUMBER=3/10",
+            "DISCNUMBER=2",
+            "no field name",
+        ]), 44100, 44100 * 61);
+
+        let tags = OggParser.read_tags(&mut open(&path)).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artists, vec!["First", "Second"]);
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.album_artists, vec!["First"]);
+        assert_eq!(tags.genres, vec!["Rock", "Jazz"]);
+        assert_eq!(tags.recording_date.as_deref(), Some("2001-05-17"));
+        assert_eq!(tags.release_date.as_deref(), Some("1999"));
+        assert_eq!(tags.credits, vec![
+            ("composer".to_string(), "Composer".to_string()),
+            ("performer".to_string(), "Player".to_string()),
+        ]);
+        assert_eq!((tags.track_number, tags.disc_number), (Some(3), Some(2)));
+    }
+
+    #[test]
+    fn opus_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = opus_file(&dir, &comments(&["TITLE=Title", "ARTIST=First", "ARTIST=Second", "TITLE=Part 2"]), 312, 48000);
+
+        let info = read_ogg(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(info.serial, SERIAL);
+        assert_eq!(info.comments["ARTIST"], vec!["First", "Second"]);
+        let tags = OggParser.read_tags(&mut open(&path)).unwrap().unwrap();
+        // repeated fields other than the multi-valued ones are joined
+        assert_eq!(tags.title.as_deref(), Some("Title, Part 2"));
+        assert_eq!(tags.artists, vec!["First", "Second"]);
+    }
+
+    #[test]
+    fn pictures() {
+        let dir = tempfile::tempdir().unwrap();
+        // big enough for the comment header to span several pages
+        let cover: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let path = vorbis_file(&dir, &[
+            "TITLE=Title".to_string(),
+            picture_comment(4, b"back"),
+            picture_comment(COVER_FRONT, &cover),
+            "METADATA_BLOCK_PICTURE=not base64".to_string(),
+        ], 44100, 44100);
+
+        let mut file = open(&path);
+        let pictures = OggParser.read_pictures(&mut file).unwrap();
+        assert_eq!(pictures.len(), 2);
+        assert!(!pictures[0].front_cover);
+        assert_eq!(pictures[0].data, b"back");
+        assert!(pictures[1].front_cover);
+        assert_eq!(pictures[1].data, cover);
+        // the headers were all read
+        assert_eq!(OggParser.read_tags(&mut file).unwrap().unwrap().title.as_deref(), Some("Title"));
+    }
+
+    #[test]
+    fn vorbis_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = vorbis_file(&dir, &[], 44100, 44100 * 61 + 100);
+        assert_eq!(OggParser.read_duration(&mut open(&path)).unwrap(), 61);
+    }
+
+    #[test]
+    fn opus_duration_leaves_out_pre_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        // always counted at 48kHz, whatever the input rate
+        let path = opus_file(&dir, &[], 312, 312 + 48000 * 75);
+        assert_eq!(OggParser.read_duration(&mut open(&path)).unwrap(), 75);
+        let path = opus_file(&dir, &[], 312, 48000 * 75);
+        assert_eq!(OggParser.read_duration(&mut open(&path)).unwrap(), 74);
+    }
+
+    #[test]
+    fn duration_of_the_first_stream_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = vorbis_file(&dir, &[], 44100, 44100 * 10);
+        // another stream ending after the first one, longer
+        let mut writer = OggWriter { data: std::fs::read(&path).unwrap() };
+        writer.packet(SERIAL + 1, &[0; 10], 44100 * 1000);
+        write(&path, writer.data).unwrap();
+
+        assert_eq!(OggParser.read_duration(&mut open(&path)).unwrap(), 10);
+        // its pages aren't audio of the track either
+        let ranges = OggParser.audio_ranges(&mut open(&path)).unwrap();
+        assert_eq!(ranges.len(), 4);
+        assert!(ranges.iter().all(|(start, end)| end - start == 100));
+    }
+
+    #[test]
+    fn unsupported_codec() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(&dir, "track.ogg", vec![b"\x7fFLAC".to_vec()], 0);
+        assert!(read_ogg(&mut File::open(&path).unwrap()).is_err());
+        let mut file = open(&path);
+        assert!(OggParser.read_tags(&mut file).is_err());
+        assert!(OggParser.read_duration(&mut file).is_err());
+        assert!(OggParser.audio_ranges(&mut file).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    collections::HashMap,
+};
+use tower::BoxError;
+
+use super::{
+    format::{FormatParser, ParsedFile, Tags, EmbeddedPicture, parse_number},
+    mp3::{id3_tags, id3_pictures},
+};
+
+// wav and aiff files, both a list of chunks after a short header
+// wav is riff with little endian sizes, aiff is the big endian iff that riff comes from
+// chunks are padded to an even size in both
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkFormat {
+    Wav,
+    Aiff,
+}
+
+// what the chunks of the file hold
+#[derive(Debug, Default)]
+struct RiffInfo {
+    length_seconds: u64,
+    // contents of the embedded id3 chunk
+    id3: Option<id3::Tag>,
+    // text chunks by id, INAM, IART, IPRD... of the LIST INFO chunk for wav, NAME and AUTH for aiff
+    text: HashMap<String, String>,
+    // where the sample data is in the file
+    audio: Option<(u64, u64)>,
+}
+
+pub struct RiffParser;
+
+impl FormatParser for RiffParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wav", "aif", "aiff"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.len() >= 12 && matches!((&header[0..4], &header[8..12]), (b"RIFF", b"WAVE") | (b"FORM", b"AIFF") | (b"FORM", b"AIFC"))
+    }
+
+    fn read_tags(&self, file: &mut ParsedFile) -> Result<Option<Tags>, BoxError> {
+        Ok(riff_tags(file.parsed(read_riff)?))
+    }
+
+    fn read_duration(&self, file: &mut ParsedFile) -> Result<u64, BoxError> {
+        Ok(file.parsed(read_riff)?.length_seconds)
+    }
+
+    // only the id3 chunk holds pictures
+    fn read_pictures(&self, file: &mut ParsedFile) -> Result<Vec<EmbeddedPicture>, BoxError> {
+        Ok(file.parsed(read_riff)?.id3.as_ref().map(id3_pictures).unwrap_or_default())
+    }
+
+    // the sample data chunk
+    fn audio_ranges(&self, file: &mut ParsedFile) -> Result<Vec<(u64, u64)>, BoxError> {
+        Ok(file.parsed(read_riff)?.audio.into_iter().collect())
+    }
+}
+
+// the id3 chunk comes first, each field falling back on its own to the text chunks in case the id3 chunk is missing some
+fn riff_tags(info: &RiffInfo) -> Option<Tags> {
+    if info.id3.is_none() && info.text.is_empty() {
+        return None;
+    }
+
+    let mut tags = info.id3.as_ref().map(id3_tags).unwrap_or_default();
+    let text = |ids: &[&str]| ids.iter().find_map(|id| info.text.get(*id)).filter(|value| !value.is_empty()).cloned();
+    tags.title = tags.title.or_else(|| text(&["INAM", "NAME"]));
+    if tags.artists.is_empty() {
+        tags.artists = text(&["IART", "AUTH"]).into_iter().collect();
+    }
+    tags.album = tags.album.or_else(|| text(&["IPRD"]));
+    if tags.genres.is_empty() {
+        tags.genres = text(&["IGNR"]).into_iter().collect();
+    }
+    tags.recording_date = tags.recording_date.or_else(|| text(&["ICRD"]));
+    tags.track_number = tags.track_number.or_else(|| parse_number(&text(&["ITRK"])?));
+
+    Some(tags)
+}
+
+fn read_riff(file: &mut File) -> Result<RiffInfo, BoxError> {
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; 12];
+    reader.rewind()?;
+    reader.read_exact(&mut header)?;
+    let format = match (&header[0..4], &header[8..12]) {
+        (b"RIFF", b"WAVE") => ChunkFormat::Wav,
+        (b"FORM", b"AIFF") | (b"FORM", b"AIFC") => ChunkFormat::Aiff,
+        _ => Err("Not a wav or aiff file")?,
+    };
+
+    let mut info = RiffInfo::default();
+    // bytes per second for wav, sample frames and rate for aiff
+    let mut byte_rate = None;
+    let mut frames = None;
+    let mut position = 12;
+    while position + 8 <= len {
+        let mut chunk_header = [0u8; 8];
+        reader.seek(SeekFrom::Start(position))?;
+        reader.read_exact(&mut chunk_header)?;
+        let id = &chunk_header[0..4];
+        let size_bytes: [u8; 4] = chunk_header[4..8].try_into()?;
+        let size = match format {
+            ChunkFormat::Wav => u32::from_le_bytes(size_bytes),
+            ChunkFormat::Aiff => u32::from_be_bytes(size_bytes),
+        } as u64;
+        let data_start = position + 8;
+        // the data chunk of a file still being written can claim more than there is
+        let data_end = (data_start + size).min(len);
+
+        match (format, id) {
+            (ChunkFormat::Wav, b"fmt ") => {
+                let data = read_chunk(&mut reader, data_start, data_end)?;
+                byte_rate = data.get(8..12).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            },
+            (ChunkFormat::Wav, b"data") => info.audio = Some((data_start, data_end)),
+            (ChunkFormat::Wav, b"LIST") => {
+                let data = read_chunk(&mut reader, data_start, data_end)?;
+                if data.starts_with(b"INFO") {
+                    info.text.extend(parse_info_list(&data[4..]));
+                }
+            },
+            (ChunkFormat::Aiff, b"COMM") => {
+                let data = read_chunk(&mut reader, data_start, data_end)?;
+                if data.len() >= 18 {
+                    let sample_frames = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+                    frames = Some((sample_frames as u64, extended_to_f64(data[8..18].try_into()?)));
+                }
+            },
+            // sound data starts with an offset and a block size, both of 4 bytes
+            (ChunkFormat::Aiff, b"SSND") => info.audio = Some(((data_start + 8).min(data_end), data_end)),
+            (ChunkFormat::Aiff, b"NAME") | (ChunkFormat::Aiff, b"AUTH") => {
+                let data = read_chunk(&mut reader, data_start, data_end)?;
+                info.text.insert(String::from_utf8_lossy(id).to_string(), chunk_text(&data));
+            },
+            (_, b"id3 ") | (_, b"ID3 ") => {
+                let data = read_chunk(&mut reader, data_start, data_end)?;
+                info.id3 = id3::Tag::read_from(&data[..]).ok();
+            },
+            _ => (),
+        }
+
+        position = data_start + size + size % 2;
+    }
+
+    let data_len = info.audio.map_or(0, |(start, end)| end - start);
+    info.length_seconds = match (format, byte_rate, frames) {
+        (ChunkFormat::Wav, Some(rate), _) if rate > 0 => data_len / rate as u64,
+        (ChunkFormat::Aiff, _, Some((frames, rate))) if rate > 0.0 => (frames as f64 / rate) as u64,
+        _ => 0,
+    };
+
+    Ok(info)
+}
+
+fn read_chunk<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Result<Vec<u8>, BoxError> {
+    let mut data = vec![0u8; end.saturating_sub(start) as usize];
+    reader.seek(SeekFrom::Start(start))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// subchunks of a LIST INFO chunk, each an id, a little endian size then a null terminated string
+fn parse_info_list(mut data: &[u8]) -> HashMap<String, String> {
+    let mut text = HashMap::new();
+    while data.len() >= 8 {
+        let id = String::from_utf8_lossy(&data[0..4]).to_string();
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let value = match data.get(8..8 + size) {
+            Some(value) => value,
+            None => break,
+        };
+        text.insert(id, chunk_text(value));
+        data = data.get(8 + size + size % 2..).unwrap_or(&[]);
+    }
+    text
+}
+
+// text up to the first null, since writers disagree on whether to terminate it
+fn chunk_text(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+// aiff stores its sample rate as an 80 bit extended float:
+// a sign bit, a 15 bit exponent biased by 16383, then a 64 bit mantissa with an explicit integer bit
+fn extended_to_f64(bytes: [u8; 10]) -> f64 {
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7fff) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap_or_default());
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    let value = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    if bytes[0] & 0x80 != 0 { -value } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use id3::{TagLike, Version};
+    use super::*;
+
+    // 44100 as an extended float, 1.3458 * 2^15
+    const RATE_44100: [u8; 10] = [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0];
+
+    // a chunk, padded to an even size
+    fn chunk(format: ChunkFormat, id: &[u8], data: &[u8]) -> Vec<u8> {
+        let size = match format {
+            ChunkFormat::Wav => (data.len() as u32).to_le_bytes(),
+            ChunkFormat::Aiff => (data.len() as u32).to_be_bytes(),
+        };
+        let mut chunk = [id, &size, data].concat();
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn riff_file(format: ChunkFormat, chunks: &[Vec<u8>]) -> Vec<u8> {
+        let chunks = chunks.concat();
+        let size = chunks.len() as u32 + 4;
+        match format {
+            ChunkFormat::Wav => [&b"RIFF"[..], &size.to_le_bytes(), b"WAVE", &chunks].concat(),
+            ChunkFormat::Aiff => [&b"FORM"[..], &size.to_be_bytes(), b"AIFF", &chunks].concat(),
+        }
+    }
+
+    // fmt chunk of pcm audio, of which only the byte rate is read
+    fn fmt_chunk(byte_rate: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 16];
+        data[8..12].copy_from_slice(&byte_rate.to_le_bytes());
+        chunk(ChunkFormat::Wav, b"fmt ", &data)
+    }
+
+    fn comm_chunk(frames: u32, rate: [u8; 10]) -> Vec<u8> {
+        let data = [&2u16.to_be_bytes()[..], &frames.to_be_bytes(), &16u16.to_be_bytes(), &rate].concat();
+        chunk(ChunkFormat::Aiff, b"COMM", &data)
+    }
+
+    fn info_chunk(items: &[(&[u8], &str)]) -> Vec<u8> {
+        let mut data = b"INFO".to_vec();
+        for (id, value) in items {
+            data.extend(chunk(ChunkFormat::Wav, id, format!("{}\0", value).as_bytes()));
+        }
+        chunk(ChunkFormat::Wav, b"LIST", &data)
+    }
+
+    fn id3_chunk(format: ChunkFormat, title: &str) -> Vec<u8> {
+        let mut tag = id3::Tag::new();
+        tag.set_title(title);
+        let mut data = Vec::new();
+        tag.write_to(&mut data, Version::Id3v24).unwrap();
+        chunk(format, b"id3 ", &data)
+    }
+
+    fn open(dir: &Path, name: &str, data: &[u8]) -> ParsedFile {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        ParsedFile::new(File::open(&path).unwrap())
+    }
+
+    #[test]
+    fn wav_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = riff_file(ChunkFormat::Wav, &[fmt_chunk(8000), chunk(ChunkFormat::Wav, b"data", &[0; 24001])]);
+        let mut file = open(dir.path(), "a.wav", &data);
+
+        assert_eq!(RiffParser.read_duration(&mut file).unwrap(), 3);
+        // after the riff header, the fmt chunk and the data chunk's header
+        assert_eq!(RiffParser.audio_ranges(&mut file).unwrap(), vec![(44, 44 + 24001)]);
+        assert!(RiffParser.read_tags(&mut file).unwrap().is_none());
+    }
+
+    #[test]
+    fn aiff_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let sound = [&[0u8; 8][..], &[0; 100]].concat();
+        let data = riff_file(ChunkFormat::Aiff, &[comm_chunk(44100 * 7 + 100, RATE_44100), chunk(ChunkFormat::Aiff, b"SSND", &sound)]);
+        let mut file = open(dir.path(), "a.aiff", &data);
+
+        assert_eq!(RiffParser.read_duration(&mut file).unwrap(), 7);
+        // the sound data leaves out its offset and block size
+        let ssnd_start = 12 + 26 + 8;
+        assert_eq!(RiffParser.audio_ranges(&mut file).unwrap(), vec![(ssnd_start + 8, ssnd_start + 108)]);
+    }
+
+    #[test]
+    fn extended_sample_rates() {
+        assert_eq!(extended_to_f64(RATE_44100), 44100.0);
+        assert_eq!(extended_to_f64([0x40, 0x0e, 0xbb, 0x80, 0, 0, 0, 0, 0, 0]), 48000.0);
+        assert_eq!(extended_to_f64([0x40, 0x0d, 0xac, 0x44, 0, 0, 0, 0, 0, 0]), 22050.0);
+        assert_eq!(extended_to_f64([0xc0, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]), -44100.0);
+        assert_eq!(extended_to_f64([0; 10]), 0.0);
+    }
+
+    #[test]
+    fn info_chunk_fills_in_what_id3_lacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let info = info_chunk(&[(b"INAM", "Info Title"), (b"IART", "Info Artist"), (b"IPRD", "Info Album"), (b"ITRK", "3/12"), (b"IGNR", "")]);
+
+        // the info chunk alone
+        let data = riff_file(ChunkFormat::Wav, &[fmt_chunk(8000), info.clone()]);
+        let tags = RiffParser.read_tags(&mut open(dir.path(), "a.wav", &data)).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Info Title"));
+        assert_eq!(tags.artists, vec!["Info Artist"]);
+        assert_eq!(tags.album.as_deref(), Some("Info Album"));
+        assert_eq!(tags.track_number, Some(3));
+        // empty values don't count
+        assert!(tags.genres.is_empty());
+
+        // the id3 chunk comes first, field by field
+        let data = riff_file(ChunkFormat::Wav, &[fmt_chunk(8000), info, id3_chunk(ChunkFormat::Wav, "Id3 Title")]);
+        let tags = RiffParser.read_tags(&mut open(dir.path(), "a.wav", &data)).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Id3 Title"));
+        assert_eq!(tags.artists, vec!["Info Artist"]);
+
+        // aiff has name and author chunks instead
+        let data = riff_file(ChunkFormat::Aiff, &[
+            comm_chunk(44100, RATE_44100),
+            chunk(ChunkFormat::Aiff, b"NAME", b"Aiff Title"),
+            chunk(ChunkFormat::Aiff, b"AUTH", b"Aiff Artist"),
+        ]);
+        let tags = RiffParser.read_tags(&mut open(dir.path(), "a.aiff", &data)).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Aiff Title"));
+        assert_eq!(tags.artists, vec!["Aiff Artist"]);
+    }
+
+    #[test]
+    fn truncated_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        // a data chunk claiming more than the file holds, as while it's still being written
+        let mut data = riff_file(ChunkFormat::Wav, &[fmt_chunk(8000), chunk(ChunkFormat::Wav, b"data", &[0; 16000])]);
+        data.truncate(data.len() - 8000);
+        let mut file = open(dir.path(), "a.wav", &data);
+        assert_eq!(RiffParser.read_duration(&mut file).unwrap(), 1);
+        assert_eq!(RiffParser.audio_ranges(&mut file).unwrap(), vec![(44, 44 + 8000)]);
+
+        // a fmt chunk cut before its byte rate, then a chunk header cut short
+        let mut data = riff_file(ChunkFormat::Wav, &[fmt_chunk(8000)]);
+        data.truncate(12 + 8 + 6);
+        let mut file = open(dir.path(), "a.wav", &data);
+        assert_eq!(RiffParser.read_duration(&mut file).unwrap(), 0);
+        let mut data = riff_file(ChunkFormat::Wav, &[fmt_chunk(8000), chunk(ChunkFormat::Wav, b"data", &[0; 100])]);
+        data.truncate(36 + 5);
+        let mut file = open(dir.path(), "a.wav", &data);
+        assert_eq!(RiffParser.read_duration(&mut file).unwrap(), 0);
+        assert!(RiffParser.audio_ranges(&mut file).unwrap().is_empty());
+
+        // a header cut short
+        let mut file = open(dir.path(), "a.wav", b"RIFF\x04\0\0\0WA");
+        assert!(RiffParser.read_duration(&mut file).is_err());
+    }
+}
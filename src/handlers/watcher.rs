@@ -0,0 +1,722 @@
+use std::{
+    path::{Path, PathBuf},
+    collections::{HashSet, HashMap},
+    time::Duration,
+};
+use notify::{
+    Watcher, RecursiveMode, RecommendedWatcher, PollWatcher, Event, EventKind,
+    ErrorKind,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender, UnboundedReceiver};
+use tower::BoxError;
+use sqlx::{
+    postgres::PgPool,
+    types::time::PrimitiveDateTime
+};
+
+use crate::{
+    utils::{SharedState, Config, Library, ReloadPhase, ScanTrigger, ScanCounts},
+    handlers::{
+        exclude::{ExcludeFilter, IGNORE_FILE_NAME, walk_audio_files},
+        scan_history::{start_scan_run, finish_scan_run},
+        reload::{
+            MissingTracks, AddResult, add_track_from_path, update_track, delete_track, new_job_id, record_scan_error, backfill_fingerprint,
+            delete_empty_albums_and_artists, invalidate_album_cache, affected_album_ids, scope_prefix,
+        },
+    },
+};
+
+// watch every library root in the background and apply changes to the db as they come in
+pub fn spawn_watcher(pool: PgPool, config: Config, state: SharedState) -> Result<(), BoxError> {
+    // events come with canonical paths
+    let mut roots = Vec::new();
+    for library in config.libraries.iter() {
+        roots.push((Path::new(&library.path).canonicalize()?, library.clone()));
+    }
+    let (tx, rx) = unbounded_channel();
+    let (fallback_tx, mut fallback_rx) = unbounded_channel();
+    let mut watcher = start_watcher(&roots, &config, tx.clone(), fallback_tx.clone())?;
+
+    let watched_roots = roots.clone();
+    let poll_interval = Duration::from_secs(config.watcher_poll_interval_seconds);
+    tokio::spawn(async move {
+        // watcher stops watching once dropped so keep it alive for as long as the task
+        // the os can also run out of watches later on, when new directories show up, so switch to polling then
+        let _fallback_tx = fallback_tx;
+        let mut polling = false;
+        while fallback_rx.recv().await.is_some() {
+            if polling {
+                continue;
+            }
+            tracing::warn!("Ran out of file watches, polling every {} seconds instead", poll_interval.as_secs());
+            match poll_watcher(&watched_roots, poll_interval, tx.clone()) {
+                Ok(poll_watcher) => {
+                    watcher = poll_watcher;
+                    polling = true;
+                    resync_roots(&watched_roots, &tx);
+                },
+                Err(e) => tracing::warn!("Failed to start polling for changes: {}", e),
+            }
+        }
+        drop(watcher);
+    });
+    tokio::spawn(process_events(pool, config, state, roots, rx));
+
+    Ok(())
+}
+
+// use the os's file notifications if possible, falling back to polling if it runs out of watches
+// running out of watches after that is reported through fallback_tx
+fn start_watcher(
+    roots: &[(PathBuf, Library)],
+    config: &Config,
+    tx: UnboundedSender<Vec<PathBuf>>,
+    fallback_tx: UnboundedSender<()>,
+) -> Result<Box<dyn Watcher + Send>, BoxError> {
+    let poll_tx = tx.clone();
+    let mut watcher = RecommendedWatcher::new(event_handler(tx, Some(fallback_tx)), notify::Config::default())?;
+
+    let watched = roots.iter().try_for_each(|(root, _)| watcher.watch(root, RecursiveMode::Recursive));
+    match watched {
+        Ok(()) => {
+            for (root, _) in roots.iter() {
+                tracing::debug!("Watching {} for changes", root.to_string_lossy());
+            }
+            Ok(Box::new(watcher))
+        },
+        Err(e) if matches!(e.kind, ErrorKind::MaxFilesWatch) => {
+            tracing::warn!("Ran out of file watches, polling every {} seconds instead", config.watcher_poll_interval_seconds);
+            poll_watcher(roots, Duration::from_secs(config.watcher_poll_interval_seconds), poll_tx)
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+// watcher checking every root for changes every poll_interval
+fn poll_watcher(roots: &[(PathBuf, Library)], poll_interval: Duration, tx: UnboundedSender<Vec<PathBuf>>) -> Result<Box<dyn Watcher + Send>, BoxError> {
+    let poll_config = notify::Config::default().with_poll_interval(poll_interval);
+    let mut poll_watcher = PollWatcher::new(event_handler(tx, None), poll_config)?;
+    for (root, _) in roots.iter() {
+        poll_watcher.watch(root, RecursiveMode::Recursive)?;
+    }
+    Ok(Box::new(poll_watcher))
+}
+
+// go through every library again, for changes that happened where nothing was watching
+fn resync_roots(roots: &[(PathBuf, Library)], tx: &UnboundedSender<Vec<PathBuf>>) {
+    let _ = tx.send(roots.iter().map(|(root, _)| root.clone()).collect());
+}
+
+// forward the paths of relevant events to the processing task
+// running out of watches is reported to fallback_tx, if there is one
+fn event_handler(tx: UnboundedSender<Vec<PathBuf>>, fallback_tx: Option<UnboundedSender<()>>) -> impl Fn(notify::Result<Event>) {
+    move |res: notify::Result<Event>| {
+        match res {
+            Ok(event) => {
+                // reading files doesn't change anything
+                if !matches!(event.kind, EventKind::Access(_)) {
+                    // receiver only goes away when the server shuts down
+                    let _ = tx.send(event.paths);
+                }
+            },
+            Err(e) => match fallback_tx.as_ref() {
+                Some(fallback_tx) if matches!(e.kind, ErrorKind::MaxFilesWatch) => {
+                    let _ = fallback_tx.send(());
+                },
+                _ => tracing::warn!("Watcher error: {}", e),
+            },
+        }
+    }
+}
+
+// collect changed paths until no event has arrived for watcher_debounce_ms, then apply them
+async fn process_events(
+    pool: PgPool,
+    config: Config,
+    state: SharedState,
+    roots: Vec<(PathBuf, Library)>,
+    mut rx: UnboundedReceiver<Vec<PathBuf>>,
+) {
+    let debounce = Duration::from_millis(config.watcher_debounce_ms);
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+
+    loop {
+        // wait for the first event of a batch
+        match rx.recv().await {
+            Some(paths) => pending.extend(paths),
+            None => return,
+        }
+
+        loop {
+            match tokio::time::timeout(debounce, rx.recv()).await {
+                Ok(Some(paths)) => pending.extend(paths),
+                Ok(None) => return,
+                Err(_) => {
+                    // quiet for long enough. apply the changes unless a reload is already going through the library
+                    let mut state_write = state.write().await;
+                    if !state_write.reload_running {
+                        state_write.reload_running = true;
+                        break;
+                    }
+                },
+            }
+        }
+
+        let paths: Vec<PathBuf> = pending.drain().collect();
+        apply_batch(pool.clone(), config.clone(), state.clone(), roots.clone(), paths).await;
+        // cleared whatever happened to the batch, so that it can't keep reloads and later batches out
+        state.write().await.reload_running = false;
+    }
+}
+
+// apply a batch of changes in a task of its own and record it in the scan history
+// a batch that panicked is recorded as failed, like one that errored
+async fn apply_batch(
+    pool: PgPool,
+    config: Config,
+    state: SharedState,
+    roots: Vec<(PathBuf, Library)>,
+    paths: Vec<PathBuf>,
+) {
+    // errors on single files are recorded under a job id of the batch's own
+    let job_id = new_job_id(&pool).await;
+    let scan_run_id = start_scan_run(&pool, ScanTrigger::Watcher, job_id.as_ref().ok().copied(), None, None).await;
+
+    let batch_pool = pool.clone();
+    let batch = tokio::spawn(async move {
+        let mut counts = ScanCounts::default();
+        let result = match job_id {
+            Ok(job_id) => apply_changes(&batch_pool, &config, &state, &roots, &paths, job_id, &mut counts).await,
+            Err(e) => Err(e.into()),
+        };
+        (counts, result)
+    });
+    let (mut counts, result) = match batch.await {
+        Ok((counts, result)) => (counts, result),
+        Err(e) => (ScanCounts::default(), Err(Box::new(e) as BoxError)),
+    };
+
+    let status = match result {
+        Ok(()) => "finished",
+        Err(e) => {
+            tracing::warn!("Failed to apply watched changes: {}", e);
+            counts.errors += 1;
+            "failed"
+        },
+    };
+    if let Some(scan_run_id) = scan_run_id {
+        finish_scan_run(&pool, scan_run_id, status, counts).await;
+    }
+}
+
+// sync the db with the current content of every changed path
+// all removals are looked at before any addition, so that a rename can be matched to the track it moved, even across libraries
+async fn apply_changes(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    roots: &[(PathBuf, Library)],
+    paths: &[PathBuf],
+    job_id: i32,
+    counts: &mut ScanCounts,
+) -> Result<(), BoxError> {
+    // each path is relative to the root of the library it's in, none for the root itself which stands for the whole library
+    let paths: Vec<(&Library, Option<&Path>)> = paths.iter()
+        .filter_map(|path| roots.iter().find_map(|(root, library)| Some((library, path.strip_prefix(root).ok()?))))
+        // a changed ignore file may change what is excluded anywhere in its directory, which is the whole library for one at the root
+        .map(|(library, path)| match path.file_name() {
+            Some(name) if name == IGNORE_FILE_NAME => (library, path.parent().unwrap_or(path)),
+            _ => (library, path),
+        })
+        .map(|(library, path)| (library, Some(path).filter(|path| !path.as_os_str().is_empty())))
+        .collect();
+
+    // errors on a single file are recorded and the file is skipped, other errors on a path are logged and the path is skipped
+    let mut excludes = HashMap::new();
+    for (_, library) in roots.iter() {
+        excludes.insert(library.name.as_str(), ExcludeFilter::new(config, library)?);
+    }
+    let mut missing = MissingTracks::new();
+    for (library, path) in paths.iter() {
+        // every library has a filter
+        let exclude = match excludes.get_mut(library.name.as_str()) {
+            Some(exclude) => exclude,
+            None => continue,
+        };
+        let synced = sync_existing_tracks(pool, config, state, library, *path, exclude, &mut missing, job_id, counts).await;
+        if let Err(e) = synced {
+            tracing::warn!("Failed to update {}: {}", display_path(library, *path), e);
+            counts.errors += 1;
+        }
+    }
+    for (library, path) in paths.iter() {
+        if let Err(e) = add_new_tracks(pool, config, state, library, *path, &mut missing, job_id, counts).await {
+            tracing::warn!("Failed to update {}: {}", display_path(library, *path), e);
+            counts.errors += 1;
+        }
+    }
+
+    // tracks that weren't found anywhere else are really gone
+    for (track_id, _) in missing.into_values().flatten() {
+        let album_ids = sqlx::query_scalar!("SELECT album_id FROM album_track WHERE track_id = ($1)", track_id)
+            .fetch_all(pool)
+            .await?;
+        delete_track(pool, track_id).await?;
+        counts.files_deleted += 1;
+        for album_id in album_ids.iter() {
+            invalidate_album_cache(state, *album_id).await;
+        }
+    }
+
+    delete_empty_albums_and_artists(pool).await?;
+
+    Ok(())
+}
+
+// path relative to the library's root for logs, the root itself if none
+fn display_path(library: &Library, path: Option<&Path>) -> String {
+    match path {
+        Some(path) => path.to_string_lossy().to_string(),
+        None => library.path.clone(),
+    }
+}
+
+// update tracks of a library at or below path, collecting the ones whose file is gone into missing
+// path is relative to the library's root and can be either a file or a directory, the whole library if none
+// what was updated and deleted goes into counts, and files that failed to update are recorded as errors of the job
+#[allow(clippy::too_many_arguments)]
+async fn sync_existing_tracks(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    library: &Library,
+    path: Option<&Path>,
+    exclude: &mut ExcludeFilter,
+    missing: &mut MissingTracks,
+    job_id: i32,
+    counts: &mut ScanCounts,
+) -> Result<(), BoxError> {
+    // struct for interfacing with the database
+    struct DBTrack {
+        track_id: i32,
+        path: String,
+        last_modified: PrimitiveDateTime,
+        fingerprint: Option<Vec<u8>>,
+    }
+
+    let (path_str, dir_prefix) = scope_prefix(path);
+
+    // albums of tracks about to change need their cache invalidated too
+    let affected_albums = affected_album_ids(pool, &library.name, path).await?;
+
+    let tracks = sqlx::query_as!(DBTrack, "SELECT path, track_id, last_modified, fingerprint FROM track \
+        WHERE library = ($1) AND ($2::text IS NULL OR path = ($2) OR starts_with(path, $3))",
+        library.name, path_str, dir_prefix)
+        .fetch_all(pool)
+        .await?;
+    for track in tracks.into_iter() {
+        let track_path = Path::new(&track.path);
+        let track_path_full = Path::new(&library.path).join(track_path);
+
+        if exclude.is_excluded(track_path, false) {
+            delete_track(pool, track.track_id).await?;
+            counts.files_deleted += 1;
+        } else if !track_path_full.exists() {
+            match track.fingerprint {
+                Some(fingerprint) => missing.entry(fingerprint).or_default().push((track.track_id, track.path)),
+                None => {
+                    delete_track(pool, track.track_id).await?;
+                    counts.files_deleted += 1;
+                },
+            }
+        } else {
+            match update_track(pool, config, state, library, track.track_id, track.last_modified, track_path).await {
+                Ok(true) => counts.files_updated += 1,
+                // unchanged tracks stored before fingerprints get theirs now, so that they can be recognized once moved
+                Ok(false) if track.fingerprint.is_none() => {
+                    if let Err(e) = backfill_fingerprint(pool, library, track.track_id, track_path).await {
+                        record_scan_error(pool, state, job_id, Some(track_path), ReloadPhase::UpdateOldMetadata, e.as_ref()).await;
+                        counts.errors += 1;
+                    }
+                },
+                Ok(false) => (),
+                Err(e) => {
+                    record_scan_error(pool, state, job_id, Some(track_path), ReloadPhase::UpdateOldMetadata, e.as_ref()).await;
+                    counts.errors += 1;
+                },
+            }
+        }
+    }
+
+    for album_id in affected_albums.iter() {
+        invalidate_album_cache(state, *album_id).await;
+    }
+
+    Ok(())
+}
+
+// add the audio files of a library at or below path that aren't in the db yet, of the whole library if path is none
+// what was added and moved goes into counts, and files that failed to be added are recorded as errors of the job
+#[allow(clippy::too_many_arguments)]
+async fn add_new_tracks(
+    pool: &PgPool,
+    config: &Config,
+    state: &SharedState,
+    library: &Library,
+    path: Option<&Path>,
+    missing: &mut MissingTracks,
+    job_id: i32,
+    counts: &mut ScanCounts,
+) -> Result<(), BoxError> {
+    // add_track_from_path skips the ones that are already in the db
+    for file in walk_audio_files(config, library, path)? {
+        match add_track_from_path(pool, config, state, library, &file, missing).await {
+            Ok(AddResult::Added) => counts.files_added += 1,
+            Ok(AddResult::Moved) => counts.files_moved += 1,
+            Ok(AddResult::AlreadyExists) => (),
+            Err(e) => {
+                record_scan_error(pool, state, job_id, Some(&file), ReloadPhase::LoadNewMetadata, e.as_ref()).await;
+                counts.errors += 1;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use notify::event::{AccessKind, CreateKind};
+    use tempfile::TempDir;
+    use super::*;
+    use crate::utils::testing::{TestDb, test_config, test_state, write_test_mp3, retag_test_mp3};
+
+    struct TestLibrary {
+        db: TestDb,
+        // removed once dropped
+        _dir: TempDir,
+        root: PathBuf,
+        config: Config,
+        state: SharedState,
+    }
+
+    impl TestLibrary {
+        async fn create() -> Option<TestLibrary> {
+            let db = TestDb::create().await?;
+            let dir = tempfile::tempdir().unwrap();
+            let root = dir.path().canonicalize().unwrap();
+            fs::create_dir(root.join("music")).unwrap();
+            fs::create_dir(root.join("art")).unwrap();
+            let mut config = test_config(&root.join("music"), &root.join("art"));
+            config.watcher_debounce_ms = 50;
+            Some(TestLibrary { db, _dir: dir, root: root.join("music"), config, state: test_state() })
+        }
+
+        fn roots(&self) -> Vec<(PathBuf, Library)> {
+            vec![(self.root.clone(), self.config.libraries[0].clone())]
+        }
+
+        async fn apply(&self, paths: &[&str]) -> ScanCounts {
+            let paths: Vec<PathBuf> = paths.iter().map(|path| self.root.join(path)).collect();
+            let mut counts = ScanCounts::default();
+            let job_id = new_job_id(&self.db.pool).await.unwrap();
+            apply_changes(&self.db.pool, &self.config, &self.state, &self.roots(), &paths, job_id, &mut counts).await.unwrap();
+            counts
+        }
+
+        // track id, name and path of every track
+        async fn tracks(&self) -> Vec<(i32, String, String)> {
+            sqlx::query!("SELECT track_id, track_name, path FROM track ORDER BY path")
+                .fetch_all(&self.db.pool)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|track| (track.track_id, track.track_name, track.path))
+                .collect()
+        }
+
+        // wait for the tracks to satisfy f, for up to a few seconds
+        async fn wait_for<F: Fn(&[(i32, String, String)]) -> bool>(&self, f: F) -> Vec<(i32, String, String)> {
+            for _ in 0..100 {
+                let tracks = self.tracks().await;
+                if f(&tracks) {
+                    return tracks;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            panic!("changes weren't applied, tracks are {:?}", self.tracks().await);
+        }
+    }
+
+    #[tokio::test]
+    async fn apply_changes_adds_updates_and_deletes() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        let counts = library.apply(&["a.mp3"]).await;
+        assert_eq!(counts.files_added, 1);
+        let tracks = library.tracks().await;
+        assert_eq!(tracks.len(), 1);
+        let (track_id, name, path) = &tracks[0];
+        assert_eq!((name.as_str(), path.as_str()), ("A", "a.mp3"));
+
+        retag_test_mp3(&library.root.join("a.mp3"), "A2");
+        let counts = library.apply(&["a.mp3"]).await;
+        assert_eq!(counts.files_updated, 1);
+        let tracks = library.tracks().await;
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].1, "A2");
+        // updates replace the track
+        assert_ne!(tracks[0].0, *track_id);
+
+        fs::remove_file(library.root.join("a.mp3")).unwrap();
+        let counts = library.apply(&["a.mp3"]).await;
+        assert_eq!(counts.files_deleted, 1);
+        assert!(library.tracks().await.is_empty());
+        assert_eq!(library.db.count("album").await, 0);
+        assert_eq!(library.db.count("artist").await, 0);
+        library.db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn apply_changes_moves_renamed_tracks() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        write_test_mp3(&library.root.join("b.mp3"), "B", 2);
+        library.apply(&["a.mp3", "b.mp3"]).await;
+        let before = library.tracks().await;
+
+        // a rename comes as the old and the new path
+        fs::create_dir(library.root.join("dir")).unwrap();
+        fs::rename(library.root.join("a.mp3"), library.root.join("dir/c.mp3")).unwrap();
+        let counts = library.apply(&["a.mp3", "dir"]).await;
+        assert_eq!((counts.files_moved, counts.files_added, counts.files_deleted), (1, 0, 0));
+
+        let after = library.tracks().await;
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0], before[1]);
+        assert_eq!(after[1], (before[0].0, "A".to_string(), "dir/c.mp3".to_string()));
+        library.db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn apply_changes_records_bad_files_and_goes_on() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+
+        fs::create_dir(library.root.join("dir")).unwrap();
+        for (n, name) in ["a", "b", "c"].iter().enumerate() {
+            write_test_mp3(&library.root.join(format!("dir/{}.mp3", name)), name, n as u64);
+        }
+        library.db.fail_inserts_into_when("track", "NEW.path = 'dir/b.mp3'").await;
+        let counts = library.apply(&["dir"]).await;
+        assert_eq!((counts.files_added, counts.errors), (2, 1));
+        let paths: Vec<String> = library.tracks().await.into_iter().map(|(_, _, path)| path).collect();
+        assert_eq!(paths, vec!["dir/a.mp3", "dir/c.mp3"]);
+
+        // updates of the files of a whole library resync go on past a bad one too
+        retag_test_mp3(&library.root.join("dir/a.mp3"), "A2");
+        retag_test_mp3(&library.root.join("dir/c.mp3"), "C2");
+        library.db.fail_inserts_into_when("track", "NEW.path IN ('dir/a.mp3', 'dir/b.mp3')").await;
+        let counts = library.apply(&[""]).await;
+        assert_eq!((counts.files_updated, counts.errors), (1, 2));
+
+        let errors = sqlx::query!("SELECT path AS \"path!\", phase FROM scan_error ORDER BY scan_error_id")
+            .fetch_all(&library.db.pool)
+            .await
+            .unwrap();
+        let errors: Vec<(String, String)> = errors.into_iter().map(|error| (error.path, error.phase)).collect();
+        assert_eq!(errors, vec![
+            ("dir/b.mp3".to_string(), "load_new_metadata".to_string()),
+            ("dir/a.mp3".to_string(), "update_old_metadata".to_string()),
+            ("dir/b.mp3".to_string(), "load_new_metadata".to_string()),
+        ]);
+        library.db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn apply_changes_syncs_the_whole_library_for_its_root() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+
+        fs::create_dir(library.root.join("dir")).unwrap();
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        write_test_mp3(&library.root.join("dir/b.mp3"), "B", 2);
+        let counts = library.apply(&[""]).await;
+        assert_eq!(counts.files_added, 2);
+
+        fs::remove_file(library.root.join("dir/b.mp3")).unwrap();
+        let counts = library.apply(&[""]).await;
+        assert_eq!(counts.files_deleted, 1);
+        assert_eq!(library.tracks().await.len(), 1);
+        library.db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn process_events_applies_batches_of_events() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+        let (tx, rx) = unbounded_channel();
+        let task = tokio::spawn(process_events(
+            library.db.pool.clone(), library.config.clone(), library.state.clone(), library.roots(), rx));
+
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        tx.send(vec![library.root.join("a.mp3")]).unwrap();
+        let tracks = library.wait_for(|tracks| tracks.len() == 1).await;
+        let track_id = tracks[0].0;
+
+        // both sides of a rename arriving apart still make it into the same batch
+        fs::rename(library.root.join("a.mp3"), library.root.join("b.mp3")).unwrap();
+        tx.send(vec![library.root.join("a.mp3")]).unwrap();
+        tx.send(vec![library.root.join("b.mp3")]).unwrap();
+        let tracks = library.wait_for(|tracks| tracks.iter().any(|(_, _, path)| path == "b.mp3")).await;
+        assert_eq!(tracks, vec![(track_id, "A".to_string(), "b.mp3".to_string())]);
+
+        retag_test_mp3(&library.root.join("b.mp3"), "B");
+        tx.send(vec![library.root.join("b.mp3")]).unwrap();
+        library.wait_for(|tracks| tracks.len() == 1 && tracks[0].1 == "B").await;
+
+        fs::remove_file(library.root.join("b.mp3")).unwrap();
+        tx.send(vec![library.root.join("b.mp3")]).unwrap();
+        library.wait_for(|tracks| tracks.is_empty()).await;
+
+        drop(tx);
+        task.await.unwrap();
+        assert!(!library.state.read().await.reload_running);
+        let runs = sqlx::query_scalar!("SELECT COUNT(*) AS \"count!\" FROM scan_run WHERE trigger = 'watcher' AND status = 'finished'")
+            .fetch_one(&library.db.pool)
+            .await
+            .unwrap();
+        assert_eq!(runs, 4);
+        library.db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn process_events_waits_for_running_reloads() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+        let (tx, rx) = unbounded_channel();
+        let task = tokio::spawn(process_events(
+            library.db.pool.clone(), library.config.clone(), library.state.clone(), library.roots(), rx));
+
+        library.state.write().await.reload_running = true;
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        tx.send(vec![library.root.join("a.mp3")]).unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(library.tracks().await.is_empty());
+
+        library.state.write().await.reload_running = false;
+        library.wait_for(|tracks| tracks.len() == 1).await;
+
+        drop(tx);
+        task.await.unwrap();
+        library.db.drop_db().await;
+    }
+
+    #[tokio::test]
+    async fn process_events_clears_the_running_flag_after_a_failed_batch() {
+        let mut library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+        // the exclude filters are built before anything else, failing the whole batch
+        library.config.exclude_globs = vec!["[".to_string()];
+        let (tx, rx) = unbounded_channel();
+        let task = tokio::spawn(process_events(
+            library.db.pool.clone(), library.config.clone(), library.state.clone(), library.roots(), rx));
+
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        tx.send(vec![library.root.join("a.mp3")]).unwrap();
+        let mut status = None;
+        for _ in 0..100 {
+            status = sqlx::query_scalar!("SELECT status FROM scan_run WHERE trigger = 'watcher' AND finished_at IS NOT NULL")
+                .fetch_optional(&library.db.pool)
+                .await
+                .unwrap();
+            if status.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(status.as_deref(), Some("failed"));
+
+        drop(tx);
+        task.await.unwrap();
+        assert!(!library.state.read().await.reload_running);
+        assert!(library.tracks().await.is_empty());
+        library.db.drop_db().await;
+    }
+
+    #[test]
+    fn event_handler_forwards_changes_and_running_out_of_watches() {
+        let (tx, mut rx) = unbounded_channel();
+        let (fallback_tx, mut fallback_rx) = unbounded_channel();
+        let handler = event_handler(tx, Some(fallback_tx));
+
+        handler(Ok(Event::new(EventKind::Access(AccessKind::Any)).add_path(PathBuf::from("/a.mp3"))));
+        handler(Ok(Event::new(EventKind::Create(CreateKind::File)).add_path(PathBuf::from("/b.mp3"))));
+        handler(Err(notify::Error::new(ErrorKind::MaxFilesWatch)));
+        handler(Err(notify::Error::generic("other error")));
+
+        assert_eq!(rx.try_recv().unwrap(), vec![PathBuf::from("/b.mp3")]);
+        assert!(rx.try_recv().is_err());
+        assert!(fallback_rx.try_recv().is_ok());
+        assert!(fallback_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn poll_watcher_resyncs_every_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = vec![(dir.path().to_path_buf(), Library {
+            name: "library".to_string(),
+            path: dir.path().to_string_lossy().to_string(),
+        })];
+        let (tx, mut rx) = unbounded_channel();
+        let _watcher = poll_watcher(&roots, Duration::from_secs(60), tx.clone()).unwrap();
+        resync_roots(&roots, &tx);
+        assert_eq!(rx.recv().await.unwrap(), vec![dir.path().to_path_buf()]);
+    }
+
+    #[tokio::test]
+    async fn root_ignore_file_syncs_the_whole_library() {
+        let library = match TestLibrary::create().await {
+            Some(library) => library,
+            None => return,
+        };
+
+        fs::create_dir(library.root.join("dir")).unwrap();
+        write_test_mp3(&library.root.join("a.mp3"), "A", 1);
+        write_test_mp3(&library.root.join("dir/b.mp3"), "B", 2);
+        library.apply(&["a.mp3", "dir"]).await;
+        assert_eq!(library.tracks().await.len(), 2);
+
+        fs::write(library.root.join(IGNORE_FILE_NAME), "dir/\n").unwrap();
+        let counts = library.apply(&[IGNORE_FILE_NAME]).await;
+        assert_eq!(counts.files_deleted, 1);
+        let tracks = library.tracks().await;
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].2, "a.mp3");
+
+        fs::remove_file(library.root.join(IGNORE_FILE_NAME)).unwrap();
+        let counts = library.apply(&[IGNORE_FILE_NAME]).await;
+        assert_eq!(counts.files_added, 1);
+        assert_eq!(library.tracks().await.len(), 2);
+        library.db.drop_db().await;
+    }
+}
@@ -34,19 +34,29 @@ mod handlers;
 mod utils;
 
 use crate::{
-    handlers::{reload, list},
+    handlers::{reload, list, watcher, dry_run, scheduler, exclude, art_gc, check, scan_history},
     utils::{SharedState, parse_cfg, find_file},
 };
 
 #[tokio::main]
 async fn main() -> Result<(), BoxError> {
+    // `musicthing check [--repair]` checks the db against the library instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let check_repair = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => None,
+        ["check"] => Some(false),
+        ["check", "--repair"] => Some(true),
+        _ => return Err(format!("Unknown arguments {:?}, usage: musicthing [check [--repair]]", args).into()),
+    };
+
     //set up tracing
+    // logs go to stderr, so that stdout only holds the output of commands
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
             std::env::var("RUST_LOG")
                 .unwrap_or_else(|_| "musicthing=debug,tower_http=debug".into()),
         ))
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
     // load config
@@ -60,23 +70,80 @@ async fn main() -> Result<(), BoxError> {
         .await
         .expect("Can't connect to database");
 
+    if let Some(repair) = check_repair {
+        return check::run_check(pool, config, repair).await;
+    }
+
+    // shared state
+    let state = SharedState::default();
+
+    // scans that were running when the server last stopped will never finish
+    let interrupted = scan_history::mark_interrupted_scan_runs(&pool).await?;
+    if interrupted > 0 {
+        tracing::warn!("{} scans were interrupted by the last shutdown", interrupted);
+    }
+
+    // fail early on invalid exclude_globs rather than on every reload
+    for library in config.libraries.iter() {
+        exclude::ExcludeFilter::new(&config, library)?;
+    }
+
+    // keep the db in sync with the library roots if enabled
+    if config.watch_music_directory {
+        watcher::spawn_watcher(pool.clone(), config.clone(), state.clone())?;
+    }
+
+    // run the reloads scheduled in config
+    scheduler::spawn_scheduler(pool.clone(), config.clone(), state.clone()).await?;
+
     // app routing
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/api/reload", get(reload::reload_handler))
         .route("/api/hard_reload", get(reload::hard_reload_handler))
+        .route("/api/reload/status", get(reload::reload_status_handler))
+        .route("/api/reload/errors", get(reload::reload_errors_handler))
+        .route("/api/reload/dry_run", get(dry_run::dry_run_handler))
+        .route("/api/reload/schedules", get(scheduler::schedules_handler))
+        .route("/api/reload/:job_id", get(reload::reload_job_handler))
+        .route("/api/reload/:job_id/errors", get(reload::reload_job_errors_handler))
+        .route("/api/reload/:job_id/cancel", get(reload::reload_cancel_handler))
+        .route("/api/reload/:job_id/report", get(dry_run::dry_run_report_handler))
+        .route("/api/reload/:job_id/art_gc", get(art_gc::art_gc_report_handler))
+        .route("/api/admin/scans", get(scan_history::scans_handler))
         .route("/api/list/albums", get(list::list_albums_handler))
         .route("/api/list/album/:id", get(list::list_album_id_handler))
+        .route("/api/list/genres", get(list::list_genres_handler))
+        .route("/api/list/genre/:id", get(list::list_genre_id_handler))
+        .route("/api/list/artist/:id", get(list::list_artist_id_handler))
         .layer(Extension(pool))
         .layer(Extension(config.clone()))
-        .layer(Extension(SharedState::default()))
-        .nest(
+        .layer(Extension(state));
+
+    // tracks are served from /api/track/<library>/<path>
+    // or straight from /api/track/<path> when music_directory is the only library, so that older clients keep working
+    if config.is_single_root() {
+        app = app.nest(
             "/api/track",
-            get_service(ServeDir::new(config.music_directory))
+            get_service(ServeDir::new(&config.music_directory))
             .handle_error(|e: std::io::Error| async move {(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Unhandled internal error: {}", e),
             )}),
-        )
+        );
+    } else {
+        for library in config.libraries.iter() {
+            app = app.nest(
+                &format!("/api/track/{}", library.name),
+                get_service(ServeDir::new(&library.path))
+                .handle_error(|e: std::io::Error| async move {(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Unhandled internal error: {}", e),
+                )}),
+            );
+        }
+    }
+
+    let app = app
         .nest(
             "/api/art",
             get_service(ServeDir::new(config.art_directory))
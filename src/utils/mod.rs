@@ -5,6 +5,7 @@ use std::{
     fs::File,
     io::BufReader,
     collections::HashMap,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use axum::http::StatusCode;
 use tokio::sync::RwLock;
@@ -14,6 +15,9 @@ use shellexpand;
 use serde::{Serialize, Deserialize};
 use serde_json;
 
+#[cfg(test)]
+pub mod testing;
+
 // config struct
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
@@ -27,8 +31,93 @@ pub struct Config {
     pub db_connection_timeout_seconds: u64,
     pub concurrency_limit: usize,
     pub timeout_seconds: u64,
+    // single library root, used as the library named default when libraries is empty
+    #[serde(default)]
     pub music_directory: String,
+    // named library roots. always holds at least one library once config is parsed
+    #[serde(default)]
+    pub libraries: Vec<Library>,
     pub art_directory: String,
+    // incrementally update the library when files under any library root change
+    #[serde(default)]
+    pub watch_music_directory: bool,
+    #[serde(default = "default_watcher_debounce_ms")]
+    pub watcher_debounce_ms: u64,
+    // only used when the os can't watch any more files
+    #[serde(default = "default_watcher_poll_interval_seconds")]
+    pub watcher_poll_interval_seconds: u64,
+    // number of files to parse in parallel during a reload, capped by max_db_connections
+    #[serde(default = "default_scan_workers")]
+    pub scan_workers: usize,
+    // write tracks in batches when reloading into an empty db, as after a hard reload
+    #[serde(default = "default_bulk_load")]
+    pub bulk_load: bool,
+    // gitignore-style patterns, relative to each library root, of paths to leave out of the library
+    // on top of the ones in .musicthingignore files
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+    // strings a genre tag is split on when it holds several genres, as in "Rock; Pop"
+    #[serde(default = "default_genre_separators")]
+    pub genre_separators: Vec<String>,
+    // reloads the server starts on its own
+    #[serde(default)]
+    pub reload_schedules: Vec<ReloadSchedule>,
+}
+
+// when to run a scheduled reload, either a cron expression or a fixed interval
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReloadSchedule {
+    // cron expression starting with seconds, in utc. eg "0 0 4 * * *" for every day at 4am
+    pub cron: Option<String>,
+    // time between the start of two runs
+    pub interval_seconds: Option<u64>,
+    #[serde(default)]
+    pub hard: bool,
+    // only reload this library, all of them if none
+    pub library: Option<String>,
+    // only reload the tracks at or below this path, relative to the library's root
+    pub path: Option<String>,
+}
+
+// name of the library music_directory is loaded as
+pub const DEFAULT_LIBRARY: &str = "default";
+
+// a named root of the music library. track paths are stored relative to the root of their library
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Library {
+    pub name: String,
+    pub path: String,
+}
+
+impl Config {
+    pub fn library(&self, name: &str) -> Option<&Library> {
+        self.libraries.iter().find(|l| l.name == name)
+    }
+
+    // whether the only library is the one made from music_directory, as in configs from before libraries existed
+    pub fn is_single_root(&self) -> bool {
+        matches!(self.libraries.as_slice(), [library] if library.name == DEFAULT_LIBRARY && library.path == self.music_directory)
+    }
+}
+
+fn default_watcher_debounce_ms() -> u64 {
+    2000
+}
+
+fn default_watcher_poll_interval_seconds() -> u64 {
+    60
+}
+
+fn default_bulk_load() -> bool {
+    true
+}
+
+fn default_genre_separators() -> Vec<String> {
+    vec![String::from(";")]
+}
+
+fn default_scan_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
 }
 
 // parse then return config
@@ -45,6 +134,9 @@ pub fn parse_cfg() -> Result<Config, BoxError> {
             config.ssl_certificate_key = shellexpand::full(&config.ssl_certificate_key)?.to_string();
             config.music_directory = shellexpand::full(&config.music_directory)?.to_string();
             config.art_directory = shellexpand::full(&config.art_directory)?.to_string();
+            for library in config.libraries.iter_mut() {
+                library.path = shellexpand::full(&library.path)?.to_string();
+            }
         },
         None => {
             // no path found - load default config
@@ -60,10 +152,40 @@ pub fn parse_cfg() -> Result<Config, BoxError> {
                 concurrency_limit: 1024,
                 timeout_seconds: 60,
                 music_directory: "../music".to_string(),
+                libraries: Vec::new(),
                 art_directory: "./art".to_string(),
+                watch_music_directory: false,
+                watcher_debounce_ms: default_watcher_debounce_ms(),
+                watcher_poll_interval_seconds: default_watcher_poll_interval_seconds(),
+                scan_workers: default_scan_workers(),
+                bulk_load: default_bulk_load(),
+                exclude_globs: Vec::new(),
+                reload_schedules: Vec::new(),
+                genre_separators: default_genre_separators(),
             };
-            println!("No config.json found. Using default config.");
-            println!("{:#?}", config);
+            eprintln!("No config.json found. Using default config.");
+            eprintln!("{:#?}", config);
+        }
+    }
+
+    // a config from before libraries existed has a single root
+    if config.libraries.is_empty() {
+        if config.music_directory.is_empty() {
+            return Err("Config needs either music_directory or libraries".into());
+        }
+        config.libraries.push(Library {
+            name: DEFAULT_LIBRARY.to_string(),
+            path: config.music_directory.clone(),
+        });
+    }
+
+    // names end up in urls, as in /api/track/<name>/<path>
+    for (i, library) in config.libraries.iter().enumerate() {
+        if library.name.is_empty() || library.name.contains('/') {
+            return Err(format!("Invalid library name {:?}", library.name).into());
+        }
+        if config.libraries[..i].iter().any(|l| l.name == library.name) {
+            return Err(format!("Library name {} is used more than once", library.name).into());
         }
     }
 
@@ -72,47 +194,56 @@ pub fn parse_cfg() -> Result<Config, BoxError> {
 
 pub fn find_file(filename: &str) -> Result<Option<PathBuf>, BoxError> {
     // look in config
-    println!("Searching for {}", filename);
+    eprintln!("Searching for {}", filename);
     match dirs::config_dir() {
         Some(config_path) => {
             let mut path = config_path;
             path.push("musicthing");
             path.push(format!("{}", filename));
-            println!("Searching in {}...", path.to_str().ok_or("Path isn't a valid UTF-8 string")?);
+            eprintln!("Searching in {}...", path.to_str().ok_or("Path isn't a valid UTF-8 string")?);
 
             if path.exists() {
-                println!("{} found.", filename);
+                eprintln!("{} found.", filename);
                 return Ok(Some(path));
             }
         },
         None => {
-            println!("Config directory not found. Skipping...");
-            println!("Review https://docs.rs/dirs/latest/dirs/fn.config_dir.html for details.");
+            eprintln!("Config directory not found. Skipping...");
+            eprintln!("Review https://docs.rs/dirs/latest/dirs/fn.config_dir.html for details.");
         }
     }
 
     // we will reach here if .config doesn't exist
     // look in current directory
-    println!("Searching in current directory...");
+    eprintln!("Searching in current directory...");
     let mut path = env::current_dir()?;
     path.push(format!("{}", filename));
     if path.exists() {
         // this code is duped from above so i do wonder whether there's a cleaner way to write this
-        println!("{} found.", filename);
+        eprintln!("{} found.", filename);
         return Ok(Some(path));
     }
 
     // we can't find it anywhere. Return none
-    println!("Failed to find {}", filename);
+    eprintln!("Failed to find {}", filename);
     Ok(None)
 }
 
 // state struct with tokio's rwlock
 pub type SharedState = Arc<RwLock<State>>;
 
+// how many finished jobs keep their status and reports in memory. older ones are only left in the scan history
+pub const KEPT_FINISHED_JOBS: usize = 20;
+
 #[derive(Debug)]
 pub struct State {
     pub reload_running: bool,
+    pub reload_jobs: HashMap<i32, ReloadStatus>,
+    pub latest_reload_job_id: Option<i32>,
+    pub dry_run_reports: HashMap<i32, DryRunReport>,
+    pub art_gc_reports: HashMap<i32, ArtGcReport>,
+    // one per entry of config.reload_schedules, in the same order
+    pub schedules: Vec<ScheduleStatus>,
     pub album_cache: AlbumCache,
     pub album_id_cache: HashMap<String, ListAlbumID>,
 }
@@ -120,6 +251,11 @@ impl Default for State {
     fn default() -> State {
         return State {
             reload_running: false,
+            reload_jobs: HashMap::new(),
+            latest_reload_job_id: None,
+            dry_run_reports: HashMap::new(),
+            art_gc_reports: HashMap::new(),
+            schedules: Vec::new(),
             album_cache: AlbumCache {
                 list_album_cache_outdated: true,
                 list_album_cache: None,
@@ -128,6 +264,22 @@ impl Default for State {
         };
     }
 }
+impl State {
+    // forget every finished job but the latest KEPT_FINISHED_JOBS, along with their reports
+    // job ids only grow, so the oldest jobs have the lowest ids
+    pub fn prune_jobs(&mut self) {
+        let mut finished: Vec<i32> = self.reload_jobs.values()
+            .filter(|status| status.end_time.is_some())
+            .map(|status| status.job_id)
+            .collect();
+        finished.sort_unstable();
+        for job_id in finished.iter().rev().skip(KEPT_FINISHED_JOBS) {
+            self.reload_jobs.remove(job_id);
+            self.dry_run_reports.remove(job_id);
+            self.art_gc_reports.remove(job_id);
+        }
+    }
+}
 
 // could be none when there's no album
 #[derive(Debug)]
@@ -136,13 +288,365 @@ pub struct AlbumCache {
     pub list_album_cache: Option<Vec<ListAlbum>>,
 }
 
+// phases a reload job goes through
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadPhase {
+    Queued,
+    Clear,
+    UpdateOldMetadata,
+    LoadNewMetadata,
+    ArtGc,
+    Finished,
+    Failed,
+    Cancelled,
+}
+impl ReloadPhase {
+    // name of the phase as stored in the db
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReloadPhase::Queued => "queued",
+            ReloadPhase::Clear => "clear",
+            ReloadPhase::UpdateOldMetadata => "update_old_metadata",
+            ReloadPhase::LoadNewMetadata => "load_new_metadata",
+            ReloadPhase::ArtGc => "art_gc",
+            ReloadPhase::Finished => "finished",
+            ReloadPhase::Failed => "failed",
+            ReloadPhase::Cancelled => "cancelled",
+        }
+    }
+}
+
+// what started a scan, stored with its run in the scan history
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanTrigger {
+    Manual,
+    Hard,
+    Scheduled,
+    Watcher,
+}
+impl ScanTrigger {
+    // name of the trigger as stored in the db
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanTrigger::Manual => "manual",
+            ScanTrigger::Hard => "hard",
+            ScanTrigger::Scheduled => "scheduled",
+            ScanTrigger::Watcher => "watcher",
+        }
+    }
+}
+
+// progress of a single reload job, reported by the reload status endpoints
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReloadStatus {
+    pub job_id: i32,
+    pub trigger: ScanTrigger,
+    pub hard: bool,
+    pub dry_run: bool,
+    // library the reload is limited to
+    pub library: Option<String>,
+    // path the reload is limited to, relative to the library's root
+    pub scope: Option<String>,
+    pub phase: ReloadPhase,
+    pub files_seen: u64,
+    pub files_added: u64,
+    pub files_updated: u64,
+    pub files_moved: u64,
+    pub files_deleted: u64,
+    pub errors: u64,
+    // set by the cancel endpoint, the job stops at the next file it gets to
+    pub cancel_requested: bool,
+    // unix timestamps in seconds
+    pub start_time: u64,
+    pub end_time: Option<u64>,
+    pub eta_seconds: Option<u64>,
+    // progress within the current phase, used for the eta
+    pub phase_processed: u64,
+    pub phase_total: Option<u64>,
+    #[serde(skip)]
+    pub phase_started: Option<Instant>,
+}
+impl ReloadStatus {
+    pub fn new(job_id: i32, trigger: ScanTrigger, hard: bool, dry_run: bool) -> ReloadStatus {
+        ReloadStatus {
+            job_id,
+            trigger,
+            hard,
+            dry_run,
+            library: None,
+            scope: None,
+            phase: ReloadPhase::Queued,
+            files_seen: 0,
+            files_added: 0,
+            files_updated: 0,
+            files_moved: 0,
+            files_deleted: 0,
+            errors: 0,
+            cancel_requested: false,
+            start_time: unix_now(),
+            end_time: None,
+            eta_seconds: None,
+            phase_processed: 0,
+            phase_total: None,
+            phase_started: None,
+        }
+    }
+
+    // move on to the next phase, with total being the number of items to process if known
+    pub fn start_phase(&mut self, phase: ReloadPhase, total: Option<u64>) {
+        self.phase = phase;
+        self.phase_processed = 0;
+        self.phase_total = total;
+        self.phase_started = Some(Instant::now());
+        self.eta_seconds = None;
+    }
+
+    // mark one item of the current phase as processed then recalculate eta
+    pub fn tick(&mut self) {
+        self.phase_processed += 1;
+        if let (Some(total), Some(started)) = (self.phase_total, self.phase_started) {
+            let remaining = total.saturating_sub(self.phase_processed);
+            let per_item = started.elapsed().as_secs_f64() / self.phase_processed as f64;
+            self.eta_seconds = Some((per_item * remaining as f64).round() as u64);
+        }
+    }
+
+    // mark job as done, with phase being how it ended: finished, failed or cancelled
+    pub fn finish(&mut self, phase: ReloadPhase) {
+        self.phase = phase;
+        self.phase_started = None;
+        self.eta_seconds = Some(0);
+        self.end_time = Some(unix_now());
+    }
+}
+
+// counts of what a scan changed, kept in the scan history once it ends
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default)]
+pub struct ScanCounts {
+    pub files_added: u64,
+    pub files_updated: u64,
+    pub files_moved: u64,
+    pub files_deleted: u64,
+    pub errors: u64,
+}
+impl From<&ReloadStatus> for ScanCounts {
+    fn from(status: &ReloadStatus) -> ScanCounts {
+        ScanCounts {
+            files_added: status.files_added,
+            files_updated: status.files_updated,
+            files_moved: status.files_moved,
+            files_deleted: status.files_deleted,
+            errors: status.errors,
+        }
+    }
+}
+
+// a past or running scan, either a reload job or a batch of watcher changes
+// job_id is none for the watcher, and finished_at is none while running or if the server stopped during the scan
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScanRun {
+    pub id: i32,
+    pub job_id: Option<i32>,
+    pub trigger: String,
+    pub status: String,
+    pub library: Option<String>,
+    pub scope: Option<String>,
+    // unix timestamps in seconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub files_added: i32,
+    pub files_updated: i32,
+    pub files_moved: i32,
+    pub files_deleted: i32,
+    pub errors: i32,
+}
+
+// a page of the scan history, most recent first
+// next is the id to pass as ?before= to get the following page, none on the last page
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScanRunPage {
+    pub runs: Vec<ScanRun>,
+    pub next: Option<i32>,
+}
+
+// an error encountered during a reload job
+// path is none for errors that aren't tied to a single file
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScanError {
+    pub id: i32,
+    pub job_id: i32,
+    pub path: Option<String>,
+    pub phase: String,
+    pub error: String,
+    pub occurred_at: i64,
+}
+
+// a page of the errors of all reload jobs, most recent first
+// next is the id to pass as ?before= to get the following page, none on the last page
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScanErrorPage {
+    pub errors: Vec<ScanError>,
+    pub next: Option<i32>,
+}
+
+// state of a scheduled reload, reported by the schedules endpoint
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScheduleStatus {
+    pub schedule: ReloadSchedule,
+    // unix timestamp in seconds, none once a cron expression has no more times
+    pub next_run: Option<i64>,
+    pub last_run: Option<ScheduledRun>,
+}
+
+// outcome of the latest run of a schedule
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ScheduledRun {
+    // unix timestamp in seconds
+    pub time: i64,
+    // none if the run was skipped
+    pub job_id: Option<i32>,
+    // phase the job ended in
+    pub phase: Option<ReloadPhase>,
+    pub skipped_reason: Option<String>,
+}
+
+// what a reload would do, as found by a dry run
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DryRunReport {
+    pub tracks_to_add: Vec<String>,
+    pub tracks_to_update: Vec<String>,
+    pub tracks_to_move: Vec<DryRunMove>,
+    pub tracks_to_delete: Vec<String>,
+    pub albums_to_create: Vec<DryRunAlbum>,
+    pub albums_to_empty: Vec<DryRunAlbum>,
+    pub artists_to_create: Vec<String>,
+    pub artists_to_empty: Vec<String>,
+    pub errors: Vec<DryRunError>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DryRunMove {
+    pub from: String,
+    pub to: String,
+}
+
+// albums are identified by name and album artists, same as when they are loaded
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DryRunAlbum {
+    pub name: String,
+    pub album_artist_names: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DryRunError {
+    pub path: String,
+    pub error: String,
+}
+
+// what the art garbage collection after a reload did and found
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ArtGcReport {
+    // art no longer used by any track, album or artist, removed along with its file
+    pub deleted: Vec<String>,
+    // files in art_directory without an art row. only reported, since they may not have been put there by a reload
+    pub orphan_files: Vec<String>,
+    // art rows whose file is gone
+    pub missing_files: Vec<String>,
+}
+
+// inconsistencies between the db and the library roots, found by the check command
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CheckReport {
+    // tracks whose file is gone
+    pub tracks_missing: Vec<CheckTrack>,
+    // tracks whose file was modified at a different time than the one it was loaded at
+    pub tracks_modified: Vec<CheckModifiedTrack>,
+    pub tracks_without_album: Vec<CheckTrack>,
+    pub tracks_without_artist: Vec<CheckTrack>,
+    // tracks of a library that isn't in config anymore. never repaired, since the library may only be left out for now
+    pub tracks_in_unknown_library: Vec<CheckTrack>,
+    pub empty_albums: Vec<CheckItem>,
+    pub empty_artists: Vec<CheckItem>,
+    // art rows whose file is gone
+    pub art_missing_files: Vec<CheckItem>,
+    // only with --repair
+    pub repair: Option<CheckRepair>,
+}
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.tracks_missing.is_empty()
+            && self.tracks_modified.is_empty()
+            && self.tracks_without_album.is_empty()
+            && self.tracks_without_artist.is_empty()
+            && self.tracks_in_unknown_library.is_empty()
+            && self.empty_albums.is_empty()
+            && self.empty_artists.is_empty()
+            && self.art_missing_files.is_empty()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CheckTrack {
+    pub id: i32,
+    pub library: String,
+    pub path: String,
+}
+
+// modified times are unix timestamps in seconds
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CheckModifiedTrack {
+    pub id: i32,
+    pub library: String,
+    pub path: String,
+    pub db_modified: i64,
+    pub file_modified: i64,
+}
+
+// an album, artist or art row, named by its name or path
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CheckItem {
+    pub id: i32,
+    pub name: String,
+}
+
+// what --repair changed
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CheckRepair {
+    pub tracks_deleted: u64,
+    pub tracks_reparsed: u64,
+    pub albums_deleted: u64,
+    pub artists_deleted: u64,
+    pub art_deleted: u64,
+    // repairs that failed, which are left as they were
+    pub errors: Vec<CheckError>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CheckError {
+    pub path: String,
+    pub error: String,
+}
+
+// current time as seconds since unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 // list json storing struct for albums query
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ListAlbum {
     pub id: i32,
     pub name: String,
-    pub artist_name: String,
+    // in the order the tags list them
+    pub album_artists: Vec<ListArtist>,
     pub art_path: Option<String>,
+    // earliest of the album's tracks', as partial dates like 2001, 2001-05 or 2001-05-17
+    pub recording_date: Option<String>,
+    pub release_date: Option<String>,
+    // year of the release date, or of the recording date if there is none. what albums are sorted and filtered by
+    pub year: Option<i32>,
 }
 
 // list json storing struct for indiv album query
@@ -150,8 +654,11 @@ pub struct ListAlbum {
 pub struct ListAlbumID {
     pub id: i32,
     pub name: String,
-    pub album_artist_name: String,
+    pub album_artists: Vec<ListArtist>,
     pub art_path: Option<String>,
+    pub recording_date: Option<String>,
+    pub release_date: Option<String>,
+    pub year: Option<i32>,
     pub discs: Vec<ListDisc>
 }
 
@@ -165,13 +672,60 @@ pub struct ListDisc {
 pub struct ListTrack {
     pub id: i32,
     pub number: i32,
-    pub artist: String,
+    // in the order the track's tags list them
+    pub artists: Vec<ListArtist>,
     pub name: String,
+    // name of the library the track is in, path is relative to its root
+    pub library: String,
     pub path: String,
     pub art_path: Option<String>,
     pub length_seconds: i32,
 }
 
+// an artist credited on an album or track
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ListArtist {
+    pub id: i32,
+    pub name: String,
+}
+
+// list json storing struct for genres query
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ListGenre {
+    pub id: i32,
+    pub name: String,
+    pub track_count: i64,
+}
+
+// list json storing struct for indiv genre query
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ListGenreID {
+    pub id: i32,
+    pub name: String,
+    // albums with at least one track of the genre
+    pub albums: Vec<ListAlbum>,
+    pub tracks: Vec<ListTrack>,
+}
+
+// list json storing struct for indiv artist query
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ListArtistID {
+    pub id: i32,
+    pub name: String,
+    // albums the artist is an album artist of
+    pub albums: Vec<ListAlbum>,
+    pub credits: Vec<ListCredit>,
+}
+
+// the tracks an artist is credited on under one role, with the albums they are on
+// the artist role is for the artists of the track's artist tag
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ListCredit {
+    pub role: String,
+    pub albums: Vec<ListAlbum>,
+    pub tracks: Vec<ListTrack>,
+}
+
 // Utility function for mapping errors into 500 http response
 pub fn internal_error(err: BoxError) -> (StatusCode, String) {
     (StatusCode::INTERNAL_SERVER_ERROR, format!("Internal error: {:?}", err))
@@ -180,4 +734,39 @@ pub fn internal_error(err: BoxError) -> (StatusCode, String) {
 // // slightly different function rwlock poison error
 // pub fn internal_poison_error<T>(err: PoisonError<T>) -> (StatusCode, String) {
 //     (StatusCode::INTERNAL_SERVER_ERROR, format!("SharedState's lock is poisoned: {}", err.to_string()))
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_jobs_keeps_running_and_latest_finished_jobs() {
+        let mut state = State::default();
+        // job 1 is still running, the others are done
+        for job_id in 1..=(KEPT_FINISHED_JOBS as i32 + 5) {
+            let mut status = ReloadStatus::new(job_id, ScanTrigger::Manual, false, job_id % 2 == 0);
+            if job_id > 1 {
+                status.finish(ReloadPhase::Finished);
+            }
+            state.reload_jobs.insert(job_id, status);
+            state.dry_run_reports.insert(job_id, DryRunReport::default());
+            state.art_gc_reports.insert(job_id, ArtGcReport::default());
+        }
+
+        state.prune_jobs();
+
+        let mut kept: Vec<i32> = state.reload_jobs.keys().copied().collect();
+        kept.sort_unstable();
+        let mut expected = vec![1];
+        expected.extend(6..=(KEPT_FINISHED_JOBS as i32 + 5));
+        assert_eq!(kept, expected);
+        assert!(state.dry_run_reports.keys().all(|job_id| state.reload_jobs.contains_key(job_id)));
+        assert!(state.art_gc_reports.keys().all(|job_id| state.reload_jobs.contains_key(job_id)));
+        assert_eq!(state.dry_run_reports.len(), expected.len());
+
+        // nothing more to forget
+        state.prune_jobs();
+        assert_eq!(state.reload_jobs.len(), expected.len());
+    }
+}
@@ -0,0 +1,204 @@
+// helpers for tests that need a database or a library on disk
+// every test database is created from musicthing_metadb_init.sql and dropped by TestDb::drop_db
+// the server to create them on is MUSICTHING_TEST_DATABASE_URL, or the local postgres user's when it's unset
+use std::{
+    env,
+    fs::write,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, UNIX_EPOCH},
+};
+use id3::{Tag, TagLike, Version};
+use filetime::FileTime;
+use sqlx::{
+    Connection, Executor,
+    postgres::{PgConnection, PgPool, PgPoolOptions},
+    types::time::PrimitiveDateTime,
+};
+
+use crate::{
+    utils::{Config, Library, DEFAULT_LIBRARY, SharedState, State},
+    handlers::tag_parser::{TrackInfo, ParsedTrack},
+};
+
+const DEFAULT_SERVER_URL: &str = "postgres://postgres@localhost/postgres";
+const INIT_SQL: &str = include_str!("../../musicthing_metadb_init.sql");
+
+static DB_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestDb {
+    pub pool: PgPool,
+    server_url: String,
+    name: String,
+}
+
+impl TestDb {
+    // a fresh database with the current schema
+    // none if no server was configured and the local one can't be reached, in which case the test should be skipped
+    pub async fn create() -> Option<TestDb> {
+        let (server_url, configured) = match env::var("MUSICTHING_TEST_DATABASE_URL") {
+            Ok(url) => (url, true),
+            Err(_) => (DEFAULT_SERVER_URL.to_string(), false),
+        };
+        let mut server = match PgConnection::connect(&server_url).await {
+            Ok(conn) => conn,
+            Err(e) if !configured => {
+                eprintln!("Skipping test, no database server at {}: {}", server_url, e);
+                return None;
+            },
+            Err(e) => panic!("Can't connect to MUSICTHING_TEST_DATABASE_URL: {}", e),
+        };
+
+        let name = format!("musicthing_test_{}_{}", std::process::id(), DB_COUNT.fetch_add(1, Ordering::SeqCst));
+        // one statement each, as several would run in a transaction
+        server.execute(format!(r#"DROP DATABASE IF EXISTS "{}""#, name).as_str()).await
+            .expect("dropping a leftover test database");
+        server.execute(format!(r#"CREATE DATABASE "{}""#, name).as_str()).await
+            .expect("creating the test database");
+        let db_url = database_url(&server_url, &name);
+
+        // the init script empties search_path for its own connection, so it gets one of its own
+        let mut init = PgConnection::connect(&db_url).await.expect("connecting to the test database");
+        init.execute(INIT_SQL).await.expect("running musicthing_metadb_init.sql");
+        init.close().await.expect("closing the init connection");
+
+        // connections are all opened upfront and never checked nor closed, so that getting one doesn't wait on anything
+        // that way their timeouts can't fire early in tests that pause tokio's clock
+        let pool = PgPoolOptions::new()
+            .max_connections(4)
+            .min_connections(4)
+            .test_before_acquire(false)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(&db_url)
+            .await
+            .expect("connecting to the test database");
+        Some(TestDb { pool, server_url, name })
+    }
+
+    pub async fn drop_db(self) {
+        self.pool.close().await;
+        let mut server = PgConnection::connect(&self.server_url).await.expect("connecting to the database server");
+        server.execute(format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#, self.name).as_str()).await
+            .expect("dropping the test database");
+    }
+
+    // make every insert into table fail from now on, as if the db broke down partway through a transaction
+    pub async fn fail_inserts_into(&self, table: &str) {
+        self.fail_inserts_into_when(table, "true").await;
+    }
+
+    // make inserts into table of rows matching condition fail from now on, NEW being the row
+    // replaces the condition of any previous call for the same table
+    pub async fn fail_inserts_into_when(&self, table: &str, condition: &str) {
+        self.pool.execute("CREATE OR REPLACE FUNCTION injected_failure() RETURNS trigger AS $$ \
+            BEGIN RAISE EXCEPTION 'injected failure'; END $$ LANGUAGE plpgsql").await
+            .expect("creating the failing trigger function");
+        self.pool.execute(format!("DROP TRIGGER IF EXISTS injected_failure ON {}", table).as_str()).await
+            .expect("dropping the previous failing trigger");
+        self.pool.execute(format!("CREATE TRIGGER injected_failure BEFORE INSERT ON {} \
+            FOR EACH ROW WHEN ({}) EXECUTE FUNCTION injected_failure()", table, condition).as_str()).await
+            .expect("creating the failing trigger");
+    }
+
+    // number of rows of a table
+    pub async fn count(&self, table: &str) -> i64 {
+        sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&self.pool)
+            .await
+            .expect("counting rows")
+    }
+}
+
+// same server, other database
+fn database_url(server_url: &str, name: &str) -> String {
+    let (base, query) = match server_url.split_once('?') {
+        Some((base, query)) => (base, format!("?{}", query)),
+        None => (server_url, String::new()),
+    };
+    let base = match base.rfind('/') {
+        Some(slash) if slash > base.find("//").map_or(0, |s| s + 1) => &base[..slash],
+        _ => base,
+    };
+    format!("{}/{}{}", base, name, query)
+}
+
+// config with a single library at music_dir and art going in art_dir
+pub fn test_config(music_dir: &Path, art_dir: &Path) -> Config {
+    let mut config: Config = serde_json::from_value(serde_json::json!({
+        "database_connection_str": "",
+        "frontend_url": "",
+        "backend_socket_addr": "127.0.0.1:0",
+        "use_tls": false,
+        "ssl_certificate": "",
+        "ssl_certificate_key": "",
+        "max_db_connections": 4,
+        "db_connection_timeout_seconds": 5,
+        "concurrency_limit": 16,
+        "timeout_seconds": 5,
+        "music_directory": music_dir.to_string_lossy(),
+        "art_directory": art_dir.to_string_lossy(),
+        "scan_workers": 2,
+    })).expect("test config");
+    config.libraries = vec![Library {
+        name: DEFAULT_LIBRARY.to_string(),
+        path: config.music_directory.clone(),
+    }];
+    config
+}
+
+pub fn test_state() -> SharedState {
+    std::sync::Arc::new(tokio::sync::RwLock::new(State::default()))
+}
+
+// a parsed track of the default library, as if read from a file at path
+pub fn test_track(path: &str, title: &str, picture: Option<&[u8]>) -> ParsedTrack {
+    ParsedTrack {
+        info: TrackInfo {
+            track_name: title.to_string(),
+            artist_names: vec!["Test Artist".to_string(), "Featured Artist".to_string()],
+            album_name: "Test Album".to_string(),
+            album_artist_names: vec!["Test Artist".to_string()],
+            genres: vec!["Rock".to_string()],
+            recording_date: Some("2001".to_string()),
+            release_date: None,
+            credits: vec![("composer".to_string(), "Test Composer".to_string())],
+            track_number: 1,
+            disc_number: 1,
+            length_seconds: 60,
+            art_id: None,
+            fingerprint: Some(blake3::hash(path.as_bytes()).as_bytes().to_vec()),
+            library: DEFAULT_LIBRARY.to_string(),
+            path_str: path.to_string(),
+            last_modified: PrimitiveDateTime::from(UNIX_EPOCH),
+        },
+        picture: picture.map(<[u8]>::to_vec),
+    }
+}
+
+// an mp3 file of a few silent frames tagged with title, by "Test Artist" on "Test Album"
+// tracks of different seeds have different fingerprints
+pub fn write_test_mp3(path: &Path, title: &str, seed: u64) {
+    let mut audio = Vec::new();
+    for _ in 0..4 {
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        frame[4..12].copy_from_slice(&seed.to_be_bytes());
+        audio.extend(frame);
+    }
+    write(path, audio).expect("writing the test mp3");
+    retag_test_mp3(path, title);
+}
+
+// replace the tag of an mp3 written by write_test_mp3, moving its modification time forward
+pub fn retag_test_mp3(path: &Path, title: &str) {
+    let previous = path.metadata().and_then(|metadata| metadata.modified()).expect("reading the test mp3's modification time");
+    let mut tag = Tag::new();
+    tag.set_title(title);
+    tag.set_artist("Test Artist");
+    tag.set_album("Test Album");
+    tag.write_to_path(path, Version::Id3v24).expect("tagging the test mp3");
+    // modification times may be too coarse to tell writes in quick succession apart
+    filetime::set_file_mtime(path, FileTime::from_system_time(previous + Duration::from_secs(1)))
+        .expect("setting the test mp3's modification time");
+}